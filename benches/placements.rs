@@ -0,0 +1,145 @@
+#![feature(test)]
+//! `cargo bench`, the pairs of the benches compare the baseline path (`rotate` and
+//! `try_shape`, the rotation and the `Vec` per call) with the rotation tables
+//! (`try_shape_idx`) on the same enumeration. `b.bytes` is a thousand times the number
+//! of the shapes that fit or the placements per iteration, so "MB/s" reads as thousands
+//! per second, see `per_iter`.
+
+extern crate test;
+
+use test::Bencher;
+use tetris::model::{Field, GameState, Point, try_shape, try_shape_idx};
+use tetris::tetrimino::TETRIMINOES;
+use tetris::train::TetrisEnv;
+use tetris::config::Config;
+use tetris::field;
+
+fn sample_field() -> Field {
+    field!("
+        . . . . . . . . . .
+        . . . . . . . . . .
+        . . . . . . . . . .
+        . . . . . . . . . .
+        . . . . . . . . . .
+        . . . . . . . . . .
+        . . . . . . . . . .
+        . . . . . . . . . .
+        . . . . . . . . . .
+        . . . . . . . . . .
+        . . . . . . . . . .
+        . . . . . . . . . .
+        . . . . . . . . . .
+        . . . . . . . . . .
+        . . . . . . . . . .
+        . . . . . . . . . .
+        * * . . . . . . . .
+        * * * . . . . * . .
+        . * * * . * * * . *
+        * * * * . * * * * *
+        * * . * * * * * * *
+        * * * * * * * . * *
+    ")
+}
+
+/// The `b.bytes` of `count` items per iteration
+fn per_iter(count: u64) -> u64 {
+    count * 1000
+}
+
+/// Try every base and rotation of every shape, `try_fit` returns `true` when the shape fits
+fn count_fits(field: &Field, mut try_fit: impl FnMut(&Point, i8, usize) -> bool) -> u64 {
+    let mut count = 0;
+    for shape_idx in 0..TETRIMINOES.len() {
+        for rotation in 0..4 {
+            for i in 0..field.height as i32 {
+                for j in 0..field.width as i32 {
+                    if try_fit(&Point(i, j), rotation, shape_idx) {
+                        count += 1;
+                    }
+                }
+            }
+        }
+    }
+    count
+}
+
+#[bench]
+fn bench_try_shape(b: &mut Bencher) {
+    let field = sample_field();
+    let try_fit = |base: &Point, rotation, shape_idx| {
+        try_shape(&field, base, rotation, &TETRIMINOES[shape_idx]).is_some()
+    };
+    b.bytes = per_iter(count_fits(&field, try_fit));
+    b.iter(|| count_fits(&field, try_fit));
+}
+
+#[bench]
+fn bench_try_shape_idx(b: &mut Bencher) {
+    let field = sample_field();
+    let try_fit = |base: &Point, rotation, shape_idx| {
+        try_shape_idx(&field, base, rotation, shape_idx).is_some()
+    };
+    b.bytes = per_iter(count_fits(&field, try_fit));
+    b.iter(|| count_fits(&field, try_fit));
+}
+
+/// The placements of every shape: every rotation at the spawn base shifted left and right
+/// and dropped, `try_cells` returns the cells of the shape or `None` if it does not fit
+fn count_placements<C: AsRef<[Point]>>(gs: &GameState, try_cells: impl Fn(&Point, i8, usize) -> Option<C>) -> u64 {
+    let mut count = 0;
+    for shape_idx in 0..TETRIMINOES.len() {
+        let spawn = gs.spawn_base(shape_idx);
+        for rotation in 0..4 {
+            for dj in -(gs.field.width as i32)..=gs.field.width as i32 {
+                let mut base = Point(spawn.0, spawn.1 + dj);
+                let mut cells = match try_cells(&base, rotation, shape_idx) {
+                    Some(cells) => cells,
+                    None => continue,
+                };
+                while let Some(next) = try_cells(&Point(base.0 + 1, base.1), rotation, shape_idx) {
+                    cells = next;
+                    base.0 += 1;
+                }
+                count += (cells.as_ref().len() == 4) as u64;
+            }
+        }
+    }
+    count
+}
+
+fn sample_state() -> GameState {
+    let mut gs = GameState::initial(22, 10, Config::default(), Some(1));
+    gs.field = sample_field();
+    gs
+}
+
+#[bench]
+fn bench_placements_try_shape(b: &mut Bencher) {
+    let gs = sample_state();
+    let try_cells = |base: &Point, rotation, shape_idx| try_shape(&gs.field, base, rotation, &TETRIMINOES[shape_idx]);
+    b.bytes = per_iter(count_placements(&gs, try_cells));
+    b.iter(|| count_placements(&gs, try_cells));
+}
+
+#[bench]
+fn bench_placements_try_shape_idx(b: &mut Bencher) {
+    let gs = sample_state();
+    let try_cells = |base: &Point, rotation, shape_idx| try_shape_idx(&gs.field, base, rotation, shape_idx);
+    b.bytes = per_iter(count_placements(&gs, try_cells));
+    b.iter(|| count_placements(&gs, try_cells));
+}
+
+/// The move generator of `get_valid_actions` alone, the tucks and the spins included
+#[bench]
+fn bench_valid_actions(b: &mut Bencher) {
+    let envs: Vec<TetrisEnv> = (0..TETRIMINOES.len())
+        .map(|shape_idx| {
+            let mut gs = sample_state();
+            gs.spawn_shape(shape_idx);
+            TetrisEnv::from_state(gs)
+        })
+        .collect();
+    let count = || envs.iter().map(|env| env.get_valid_actions().len() as u64).sum::<u64>();
+    b.bytes = per_iter(count());
+    b.iter(count);
+}
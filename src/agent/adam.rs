@@ -0,0 +1,70 @@
+//! Adam with its state in a `VarStore`
+//!
+//! `tch` keeps the moments of its optimizers inside libtorch and doesn't expose them,
+//! so the resumed training would start with the zero moments. This one keeps the
+//! moments and the step count in `state`, which is saved and loaded as any `VarStore`.
+//! The update is the one of `nn::Adam::default()`.
+
+use tch::{nn, Tensor};
+
+/// `state` - the first and the second moments of every variable, and the step count
+pub struct Adam {
+    pub state: nn::VarStore,
+    pub lr: f64,
+    pub beta1: f64,
+    pub beta2: f64,
+    pub eps: f64,
+    // the variables with their moments
+    params: Vec<(Tensor, Tensor, Tensor)>,
+    step: Tensor,
+}
+
+impl Adam {
+    /// The optimizer of the variables of `vs`, the moments start with zeros
+    pub fn new(vs: &nn::VarStore, lr: f64) -> Adam {
+        let state = nn::VarStore::new(vs.device());
+        let root = state.root();
+        let mut variables: Vec<(String, Tensor)> = vs.variables().into_iter().collect();
+        variables.sort_by(|a, b| a.0.cmp(&b.0));
+        let params = variables.into_iter()
+            .map(|(name, var)| {
+                // the names of the variables have dots, the names in `state` can't
+                let name = name.replace('.', "_");
+                let m = (&root / "m").zeros_no_train(&name, &var.size());
+                let v = (&root / "v").zeros_no_train(&name, &var.size());
+                (var, m, v)
+            })
+            .collect();
+        let step = root.zeros_no_train("step", &[1]);
+        Adam { state, lr, beta1: 0.9, beta2: 0.999, eps: 1e-8, params, step }
+    }
+
+    /// The number of the updates done
+    pub fn steps(&self) -> u64 {
+        self.step.double_value(&[0]) as u64
+    }
+
+    /// Compute the gradients of `loss` and update the variables
+    pub fn backward_step(&mut self, loss: &Tensor) {
+        for (var, _, _) in &mut self.params {
+            var.zero_grad();
+        }
+        loss.backward();
+        let Adam { lr, beta1, beta2, eps, params, step, .. } = self;
+        tch::no_grad(|| {
+            *step += 1.0;
+            let t = step.double_value(&[0]);
+            let correction1 = 1.0 - beta1.powf(t);
+            let correction2 = 1.0 - beta2.powf(t);
+            for (var, m, v) in params.iter_mut() {
+                let grad = var.grad();
+                *m *= *beta1;
+                *m += &grad * (1.0 - *beta1);
+                *v *= *beta2;
+                *v += &grad * &grad * (1.0 - *beta2);
+                let update = (&*m / correction1) / ((&*v / correction2).sqrt() + *eps) * *lr;
+                *var -= update;
+            }
+        });
+    }
+}
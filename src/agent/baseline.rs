@@ -0,0 +1,103 @@
+//! The agents without learning, to compare the learned ones with
+//!
+//! # Example
+//!
+//! ```
+//! use tetris::agent::{Agent, GreedyAgent};
+//! use tetris::train::{TetrisEnv, evaluate};
+//! let mut agent = GreedyAgent::default();
+//! let mut env = TetrisEnv::new(Some(1));
+//! let scores = evaluate(&mut agent, &mut env, 1, 50);
+//! // every piece is worth 1 with the default scoring, the heuristic survives them all
+//! assert_eq!(scores, vec![50]);
+//! ```
+
+use rand::SeedableRng;
+use rand::seq::SliceRandom;
+use rand_xoshiro::Xoshiro512StarStar;
+use crate::agent::{Agent, DQNAction};
+use crate::features::{Board, FeatureExtractor, dellacherie};
+use crate::model::Action;
+use crate::train::TetrisEnv;
+
+/// The uniformly random placement
+pub struct RandomAgent {
+    pub rng: Xoshiro512StarStar,
+}
+
+impl RandomAgent {
+    pub fn new(seed: Option<u64>) -> RandomAgent {
+        let rng = match seed {
+            Some(seed) => Xoshiro512StarStar::seed_from_u64(seed),
+            None => Xoshiro512StarStar::from_entropy(),
+        };
+        RandomAgent { rng }
+    }
+}
+
+impl Agent for RandomAgent {
+    fn select_action(&mut self, env: &TetrisEnv) -> Option<DQNAction> {
+        env.get_valid_actions().choose(&mut self.rng).cloned()
+    }
+
+    fn rank_actions(&mut self, env: &TetrisEnv) -> Vec<DQNAction> {
+        let mut actions = env.get_valid_actions();
+        actions.shuffle(&mut self.rng);
+        actions
+    }
+
+    fn seed(&mut self, seed: u64) {
+        self.rng = Xoshiro512StarStar::seed_from_u64(seed);
+    }
+}
+
+/// The placement with the best weighted sum of the features of the field after it,
+/// the placements ending the game go last
+pub struct GreedyAgent {
+    pub features: Vec<Box<dyn FeatureExtractor>>,
+    pub weights: Vec<f32>,
+}
+
+/// Dellacherie's features with his weights
+impl Default for GreedyAgent {
+    fn default() -> Self {
+        GreedyAgent {
+            features: dellacherie(),
+            weights: vec![-1.0, 1.0, -1.0, -1.0, -4.0, -1.0],
+        }
+    }
+}
+
+impl GreedyAgent {
+    /// The value of the placement, `None` if it ends the game
+    pub fn evaluate(&self, env: &TetrisEnv, action: &DQNAction) -> Option<f32> {
+        let mut after = env.clone();
+        if after.step(*action).2 {
+            return None;
+        }
+        let mut gs = env.gs.clone();
+        if action.hold {
+            gs.step(Action::Hold);
+        }
+        gs.base = action.base;
+        gs.rotation = action.rotation;
+        let (_, cells) = gs.drop_current_shape();
+        let board = Board::after_placement(&gs.field, &cells?);
+        let value = self.features.extract(&board).iter().zip(&self.weights).map(|(f, w)| f * w).sum();
+        Some(value)
+    }
+}
+
+impl Agent for GreedyAgent {
+    fn select_action(&mut self, env: &TetrisEnv) -> Option<DQNAction> {
+        self.rank_actions(env).into_iter().next()
+    }
+
+    fn rank_actions(&mut self, env: &TetrisEnv) -> Vec<DQNAction> {
+        let mut ranked: Vec<(DQNAction, f32)> = env.get_valid_actions().into_iter()
+            .map(|action| (action, self.evaluate(env, &action).unwrap_or(f32::NEG_INFINITY)))
+            .collect();
+        ranked.sort_by(|(_, v1), (_, v2)| v2.total_cmp(v1));
+        ranked.into_iter().map(|(action, _)| action).collect()
+    }
+}
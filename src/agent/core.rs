@@ -1,3 +1,110 @@
+use std::path::Path;
+use failure::{bail, Fallible};
+use crate::agent::{DQNAction, DQNAgent, DQNTransition, GreedyAgent, MCTSAgent, MCTSConf, QNetwork, RandomAgent, RandomRollout};
+use crate::finesse::finesse_path;
+use crate::model::Action;
+use crate::train::TetrisEnv;
 
+/// The player of `TetrisEnv`, only `select_action` is required,
+/// the rest is for the agents that learn or keep a state between the moves
 pub trait Agent {
+    /// Choose the placement among `env.get_valid_actions()`, `None` if there are none
+    fn select_action(&mut self, env: &TetrisEnv) -> Option<DQNAction>;
+
+    /// The valid placements from the best to the worst, by default the only one
+    /// of `select_action`
+    fn rank_actions(&mut self, env: &TetrisEnv) -> Vec<DQNAction> {
+        self.select_action(env).into_iter().collect()
+    }
+
+    /// The inputs to make the chosen placement, see `finesse::finesse_path`
+    fn select_inputs(&mut self, env: &TetrisEnv) -> Option<Vec<Action>> {
+        let action = self.select_action(env)?;
+        finesse_path(&env.gs, &action)
+    }
+
+    /// The result of the placement chosen by the agent
+    fn observe(&mut self, _transition: DQNTransition) {}
+
+    /// Called before every game
+    fn reset(&mut self) {}
+
+    fn seed(&mut self, _seed: u64) {}
+
+    /// Save what the agent has learned to the directory
+    fn save(&self, _dir: &Path) -> Fallible<()> {
+        Ok(())
+    }
+
+    /// Load what `save` saved, the agents without anything to load fail
+    fn load(&mut self, _dir: &Path) -> Fallible<()> {
+        bail!("the agent has nothing to load")
+    }
+}
+
+impl<A: Agent + ?Sized> Agent for Box<A> {
+    fn select_action(&mut self, env: &TetrisEnv) -> Option<DQNAction> {
+        (**self).select_action(env)
+    }
+
+    fn rank_actions(&mut self, env: &TetrisEnv) -> Vec<DQNAction> {
+        (**self).rank_actions(env)
+    }
+
+    fn select_inputs(&mut self, env: &TetrisEnv) -> Option<Vec<Action>> {
+        (**self).select_inputs(env)
+    }
+
+    fn observe(&mut self, transition: DQNTransition) {
+        (**self).observe(transition)
+    }
+
+    fn reset(&mut self) {
+        (**self).reset()
+    }
+
+    fn seed(&mut self, seed: u64) {
+        (**self).seed(seed)
+    }
+
+    fn save(&self, dir: &Path) -> Fallible<()> {
+        (**self).save(dir)
+    }
+
+    fn load(&mut self, dir: &Path) -> Fallible<()> {
+        (**self).load(dir)
+    }
+}
+
+/// The value for the ordering of the placements, NaN of a broken or diverged network goes last
+pub(crate) fn or_neg_inf(value: f32) -> f32 {
+    if value.is_nan() { f32::NEG_INFINITY } else { value }
+}
+
+/// The names of the agents for `new_agent`
+pub const AGENT_NAMES: [&str; 4] = ["dqn", "random", "greedy", "mcts"];
+
+/// The agent by its name with the default settings, loaded from `load` if given.
+/// The MCTS uses the random rollouts, or the value network of the loaded DQN agent
+pub fn new_agent(name: &str, seed: Option<u64>, load: Option<&Path>) -> Fallible<Box<dyn Agent>> {
+    let mut agent: Box<dyn Agent> = match (name, load) {
+        ("dqn", _) => Box::new(DQNAgent::new(seed)),
+        ("random", _) => Box::new(RandomAgent::new(seed)),
+        ("greedy", _) => Box::new(GreedyAgent::default()),
+        ("mcts", None) => {
+            let rollout = RandomRollout { depth: 3, discount: 0.99 };
+            Box::new(MCTSAgent::new(MCTSConf::default(), rollout, seed))
+        }
+        ("mcts", Some(dir)) => {
+            let mut network = DQNAgent::new(seed);
+            network.load(dir)?;
+            let evaluator = QNetwork { agent: network, temperature: 1.0 };
+            return Ok(Box::new(MCTSAgent::new(MCTSConf::default(), evaluator, seed)));
+        }
+        _ => bail!("unknown agent {}, expected one of {:?}", name, AGENT_NAMES),
+    };
+    if let Some(dir) = load {
+        agent.load(dir)?;
+    }
+    Ok(agent)
 }
@@ -1,39 +1,97 @@
-use std::collections::{VecDeque, HashMap};
 use rand_xoshiro::Xoshiro512StarStar;
 use rand::{Rng, SeedableRng};
-use crate::model::{Point, Action};
+use serde::{Serialize, Deserialize};
+use tch::{nn, nn::Module, Device, Tensor, Reduction};
+use std::collections::VecDeque;
+use std::path::Path;
+use failure::{bail, Fallible};
+use crate::checkpoint;
+use crate::model::Point;
+use crate::reward::{Reward, Rewards};
+use crate::agent::{Adam, Agent, ReplayBuffer, ReplayMode, or_neg_inf};
+use crate::train::TetrisEnv;
 
-#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+/// The number of the values in `DQNState::features`
+pub const N_FEATURES: usize = 4;
+
+/// `next_shape_idxs` - the preview of the upcoming shapes, so planners
+/// can search over the known future pieces
+/// `combo`, `back_to_back` - the chains of the clears, see `model::ClearInfo`
+#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
 pub struct DQNState {
     pub lines_burnt: usize,
     pub sum_holes: u16,
     pub sum_bumps: u16,
     pub sum_height: u16,
     pub curr_shape_idx: usize,
+    pub next_shape_idxs: Vec<usize>,
+    pub hold_shape_idx: Option<usize>,
+    pub combo: u32,
+    pub back_to_back: bool,
+}
+
+impl DQNState {
+    /// The input of the value network
+    pub fn features(&self) -> [f32; N_FEATURES] {
+        [
+            self.lines_burnt as f32,
+            self.sum_holes as f32,
+            self.sum_bumps as f32,
+            self.sum_height as f32,
+        ]
+    }
 }
 
-#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+/// `hold` - whether `Action::Hold` should be applied before the placement,
+/// in that case `base` and `rotation` relate to the shape taken from the hold slot
+/// `last_kick` - the wall kick of the rotation the placement ends with, it makes
+/// the T-spins, see `model::detect_tspin`, `None` if the last input is not a rotation of T
+#[derive(PartialEq, Eq, Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct DQNAction {
     pub base: Point,
     pub rotation: i8,
+    pub hold: bool,
+    pub last_kick: Option<usize>,
 }
 
-#[derive(Clone, Debug)]
+/// `epsilon` decays linearly from its value to `epsilon_min` during
+/// the first `epsilon_stop_episode` episodes, see `DQNAgent::decay_epsilon`
+/// `activations` - one per layer, the last is for the output,
+/// `relu`, `tanh`, `sigmoid` or `linear`
+/// `save_every` - the checkpoint interval in episodes, see `checkpoint`
+/// `target_update` - how the target network follows the trained one
+/// `double_dqn` - the targets are evaluated by the target network at the placement
+/// chosen by the trained one, see `DQNTransition::candidates`
+/// `huber_loss` - the Huber (smooth L1) loss instead of MSE
+/// `replay` - how the batches are sampled from the memory of `mem_size` transitions
+/// `n_step` - the number of the placements in the transitions of the memory
+/// `reward` - the terms of the environment reward, see `reward::Reward`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct AgentConf {
-    n_neurons: Vec<i32>,       // [32, 32]
-    batch_size: i32,           // 512
-    activations: Vec<String>,  // ['relu', 'relu', 'linear']
-    episodes: i32,             // 2000
-    epsilon: f32,              // 1.0
-    epsilon_stop_episode: i32, // 2000
-    mem_size: i32,             // 25000
-    discount: f32,             // 0.99
-    replay_start_size: i32,    // 2000
-    epochs: i32,               // 1
-    render_every: Option<i32>, // None
-    train_every: i32,          // 1
-    log_every: i32,            // 10
-    max_step: Option<i32>,     // Some(10000)
+    pub n_neurons: Vec<i32>,       // [32, 32]
+    pub batch_size: i32,           // 512
+    pub activations: Vec<String>,  // ['relu', 'relu', 'linear']
+    pub episodes: i32,             // 2000
+    pub epsilon: f32,              // 1.0
+    pub epsilon_min: f32,          // 0.0
+    pub epsilon_stop_episode: i32, // 2000
+    pub mem_size: i32,             // 25000
+    pub discount: f32,             // 0.99
+    pub learning_rate: f64,        // 1e-3
+    pub replay_start_size: i32,    // 2000
+    pub epochs: i32,               // 1
+    pub render_every: Option<i32>, // None
+    pub train_every: i32,          // 1
+    pub log_every: i32,            // 10
+    pub save_every: Option<i32>,   // Some(100)
+    pub max_step: Option<i32>,     // Some(10000)
+    pub target_update: TargetUpdate, // Off
+    pub double_dqn: bool,          // false
+    pub huber_loss: bool,          // false
+    pub replay: ReplayMode,        // Uniform
+    pub n_step: u32,               // 1
+    pub reward: Vec<Reward>,       // [ScoreDelta]
 }
 
 impl Default for AgentConf {
@@ -44,71 +102,439 @@ impl Default for AgentConf {
             activations: vec!["relu".into(), "relu".into(), "linear".into()],
             episodes: 2000,
             epsilon: 1.0,
+            epsilon_min: 0.0,
             epsilon_stop_episode: 2000,
             mem_size: 25000,
             discount: 0.99,
+            learning_rate: 1e-3,
             replay_start_size: 2000,
             epochs: 1,
             render_every: None,
             train_every: 1,
             log_every: 10,
+            save_every: Some(100),
             max_step: Some(10000),
+            target_update: TargetUpdate::Off,
+            double_dqn: false,
+            huber_loss: false,
+            replay: ReplayMode::Uniform,
+            n_step: 1,
+            reward: vec![Reward::ScoreDelta],
         }
     }
 }
 
-#[derive(Debug, Clone)]
+impl AgentConf {
+    /// Check the activations, the intervals and the names of the features in `reward`,
+    /// so the training doesn't stop on them
+    pub fn validate(&self) -> Fallible<()> {
+        if let Some(other) = self.activations.iter().find(|a| !ACTIVATIONS.contains(&a.as_str())) {
+            bail!("unknown activation {}, expected one of {:?}", other, ACTIVATIONS);
+        }
+        let intervals = [
+            ("train_every", Some(self.train_every)),
+            ("log_every", Some(self.log_every)),
+            ("render_every", self.render_every),
+            ("save_every", self.save_every),
+        ];
+        if let Some((name, _)) = intervals.iter().find(|(_, every)| every.is_some_and(|n| n <= 0)) {
+            bail!("{} must be positive", name);
+        }
+        Rewards::new(&self.reward)?;
+        Ok(())
+    }
+}
+
+/// The names of `AgentConf::activations`
+pub const ACTIVATIONS: [&str; 4] = ["relu", "tanh", "sigmoid", "linear"];
+
+/// The target network gives the values of the next states in the targets,
+/// so the targets don't chase the network being trained
+/// - `Off`: no target network, the trained one is used
+/// - `Sync`: copy the trained network every `every` training steps
+/// - `Polyak`: `target = tau * trained + (1 - tau) * target` after every training step
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TargetUpdate {
+    Off,
+    Sync { every: u32 },
+    Polyak { tau: f64 },
+}
+
+/// The agent estimates the values of the states after the placements (the afterstates)
+/// and picks the placement with the best one
+/// `epsilon` - the current probability of the random placement
+/// `opt` - Adam of the network, its moments are saved with the checkpoint
+/// `target` - the target network, if `conf.target_update` is not `Off`
+/// `train_steps` - the number of the training steps done
+/// `n_step` - the transitions of the current episode before they go to `memory`
 pub struct DQNAgent {
     pub conf: AgentConf,
-    pub memory: VecDeque<(DQNState, DQNState, f32, bool)>,
+    pub memory: ReplayBuffer<DQNTransition>,
     pub rng: Xoshiro512StarStar,
+    pub epsilon: f32,
+    pub vs: nn::VarStore,
+    pub model: nn::Sequential,
+    pub opt: Adam,
+    pub target: Option<(nn::VarStore, nn::Sequential)>,
+    pub train_steps: u64,
+    pub n_step: NStepQueue,
 }
 
-#[derive(Debug, Clone)]
+/// `reward` - the discounted sum of the rewards of the `steps` placements from `curr_state`
+/// to `next_state`, see `NStepQueue`
+/// `candidates` - the afterstates of all the placements at the last step with their
+/// returns from `curr_state` and `done`, Double DQN takes the target from them, empty otherwise
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct DQNTransition {
-    curr_state: DQNState,
-    action: DQNAction,
-    reward: f32,
-    next_state: DQNState,
-    done: bool
+    pub curr_state: DQNState,
+    pub action: DQNAction,
+    pub reward: f32,
+    pub next_state: DQNState,
+    pub done: bool,
+    pub candidates: Vec<(DQNState, f32, bool)>,
+    pub steps: u32,
+}
+
+/// Combines the one-step transitions of an episode into the n-step ones, the transition
+/// from the state `t` goes to the state `t + n`, or to the end of the episode before it
+#[derive(Debug, Clone)]
+pub struct NStepQueue {
+    n: usize,
+    discount: f32,
+    window: VecDeque<DQNTransition>,
+}
+
+impl NStepQueue {
+    pub fn new(n: usize, discount: f32) -> NStepQueue {
+        NStepQueue { n: n.max(1), discount, window: VecDeque::new() }
+    }
+
+    /// Add the next one-step transition, return the n-step transitions completed by it
+    pub fn push(&mut self, transition: DQNTransition) -> Vec<DQNTransition> {
+        let done = transition.done;
+        self.window.push_back(transition);
+        if done {
+            self.flush()
+        } else if self.window.len() == self.n {
+            let combined = self.combine();
+            self.window.pop_front();
+            vec![combined]
+        } else {
+            vec![]
+        }
+    }
+
+    /// Complete the transitions of the episode cut off before its end (e.g. by `max_step`),
+    /// they are bootstrapped from the last state
+    pub fn flush(&mut self) -> Vec<DQNTransition> {
+        let mut transitions = vec![];
+        while !self.window.is_empty() {
+            transitions.push(self.combine());
+            self.window.pop_front();
+        }
+        transitions
+    }
+
+    fn combine(&self) -> DQNTransition {
+        let first = &self.window[0];
+        let last = &self.window[self.window.len() - 1];
+        let steps = self.window.len();
+        // the rewards before the last step
+        let mut prefix = 0.0;
+        let mut gamma = 1.0;
+        for t in self.window.iter().take(steps - 1) {
+            prefix += gamma * t.reward;
+            gamma *= self.discount;
+        }
+        DQNTransition {
+            curr_state: first.curr_state.clone(),
+            action: first.action,
+            reward: prefix + gamma * last.reward,
+            next_state: last.next_state.clone(),
+            done: last.done,
+            candidates: last.candidates.iter()
+                .map(|(state, reward, done)| (state.clone(), prefix + gamma * reward, *done))
+                .collect(),
+            steps: steps as u32,
+        }
+    }
 }
 
 impl DQNAgent {
     pub fn new(seed: Option<u64>) -> DQNAgent {
-        let conf = AgentConf::default();
+        DQNAgent::with_conf(AgentConf::default(), seed).expect("the default conf is valid")
+    }
+
+    /// The agent with the new network, fails if `conf` doesn't pass `AgentConf::validate`
+    pub fn with_conf(conf: AgentConf, seed: Option<u64>) -> Fallible<DQNAgent> {
+        conf.validate()?;
         let rng = if let Some(seed) = seed {
+            tch::manual_seed(seed as i64);
             Xoshiro512StarStar::seed_from_u64(seed)
         } else {
             Xoshiro512StarStar::from_entropy()
         };
-        DQNAgent {
+        let vs = nn::VarStore::new(Device::Cpu);
+        let model = build_model(&vs.root(), &conf)?;
+        let opt = Adam::new(&vs, conf.learning_rate);
+        let target = if conf.target_update == TargetUpdate::Off {
+            None
+        } else {
+            let mut target_vs = nn::VarStore::new(Device::Cpu);
+            let target_model = build_model(&target_vs.root(), &conf)?;
+            target_vs.copy(&vs).expect("the target network of the same layout");
+            Some((target_vs, target_model))
+        };
+        let memory = ReplayBuffer::new(conf.mem_size as usize, conf.replay);
+        let n_step = NStepQueue::new(conf.n_step as usize, conf.discount);
+        Ok(DQNAgent {
+            epsilon: conf.epsilon,
             conf,
-            memory: VecDeque::default(),
+            memory,
             rng,
+            vs,
+            model,
+            opt,
+            target,
+            train_steps: 0,
+            n_step,
+        })
+    }
+
+    /// The values of the states estimated by the network
+    pub fn predict(&self, states: &[&DQNState]) -> Vec<f32> {
+        evaluate(&self.model, states)
+    }
+
+    /// The values of the states estimated by the target network, or by the trained one without it
+    pub fn predict_target(&self, states: &[&DQNState]) -> Vec<f32> {
+        match &self.target {
+            Some((_, target_model)) => evaluate(target_model, states),
+            None => self.predict(states),
         }
     }
-    pub fn select_best_action(&mut self, actions: &[DQNAction]) -> DQNAction {
-        //def policy_fn(sess: tf.Session, observation: np.ndarray, epsilon: float) -> np.ndarray:
-        //    """
-        //    Args:
-        //        sess: Tensorflow session
-        //        observation: [84, 84, 4] grayscale image, the current and 3 previous frames
-        //        epsilon: the probability to choose action randomly instead of via neural network
-        //    Returns:
-        //        probability vector for every action
-        //    """
-        //    a = np.ones(n_a, dtype=float) * epsilon / n_a
-        //    q_values = estimator.predict(sess, np.expand_dims(observation, 0))[0]
-        //    best_action = np.argmax(q_values)
-        //    a[best_action] += (1.0 - epsilon)
-        //    return a
-        if self.rng.gen::<f32>() <= self.conf.epsilon {
-            actions[self.rng.gen_range(0, actions.len())]
+
+    /// The epsilon-greedy choice among the placements and their afterstates,
+    /// see `TetrisEnv::get_next_states`, `candidates` must not be empty
+    pub fn select_best_action(&mut self, candidates: &[(DQNAction, DQNState)]) -> DQNAction {
+        if self.rng.gen::<f32>() <= self.epsilon {
+            candidates[self.rng.gen_range(0, candidates.len())].0
         } else {
-            // ask the neural network about the best value
-            // q_values = estimator.predict(sess, np.expand_dims(observation, 0))[0]
-            // best_action = np.argmax(q_values)        }
-            unreachable!();
+            let states: Vec<&DQNState> = candidates.iter().map(|(_, s)| s).collect();
+            let values = self.predict(&states);
+            let best = (0..values.len())
+                .max_by(|&i, &j| or_neg_inf(values[i]).total_cmp(&or_neg_inf(values[j])))
+                .unwrap();
+            candidates[best].0
         }
     }
-}
\ No newline at end of file
+
+    /// Add the one-step transition, it reaches the memory as a part of the n-step ones
+    pub fn add_to_memory(&mut self, transition: DQNTransition) {
+        for transition in self.n_step.push(transition) {
+            self.memory.push(transition);
+        }
+    }
+
+    /// Called when the episode is cut off before the game over
+    pub fn finish_episode(&mut self) {
+        for transition in self.n_step.flush() {
+            self.memory.push(transition);
+        }
+    }
+
+    /// Fit the values of the sampled states to `reward + discount * value(next_state)`,
+    /// return the loss of the last epoch, `None` if the memory is not filled enough yet
+    pub fn train(&mut self) -> Option<f32> {
+        let batch_size = self.conf.batch_size as usize;
+        let start_size = self.conf.replay_start_size.max(self.conf.batch_size) as usize;
+        if self.memory.len() < start_size {
+            return None;
+        }
+        let batch = self.memory.sample(&mut self.rng, batch_size);
+        let transitions: Vec<&DQNTransition> = batch.indices.iter().map(|&k| &self.memory[k]).collect();
+        let targets = self.targets(&transitions);
+        let states: Vec<&DQNState> = transitions.iter().map(|t| &t.curr_state).collect();
+        let td_errors: Vec<f32> = targets.iter().zip(self.predict(&states))
+            .map(|(target, value)| target - value)
+            .collect();
+        let n = states.len();
+        let xs = states_to_tensor(&states);
+        let ys = Tensor::of_slice(&targets).view([n as i64, 1]);
+        // the importance-sampling weights, all ones without the priorities
+        let weights = Tensor::of_slice(&batch.weights);
+        let mut loss = 0.0;
+        for _ in 0..self.conf.epochs {
+            let values = self.model.forward(&xs);
+            let losses = if self.conf.huber_loss {
+                values.smooth_l1_loss(&ys, Reduction::None)
+            } else {
+                values.mse_loss(&ys, Reduction::None)
+            };
+            let loss_t = losses.view([-1]).dot(&weights) / n as f64;
+            self.opt.backward_step(&loss_t);
+            loss = loss_t.double_value(&[]) as f32;
+        }
+        self.memory.update_priorities(&batch.indices, &td_errors);
+        self.train_steps += 1;
+        self.update_target();
+        Some(loss)
+    }
+
+    /// With Double DQN the trained network chooses the best candidate and the target network
+    /// evaluates it: `reward_a + gamma * value_target(state_a)` for `a` maximizing
+    /// `reward_a + gamma * value(state_a)`, without it `reward + gamma * value_target(next_state)`,
+    /// where `gamma` is `discount` to the power of the transition steps
+    fn targets(&self, transitions: &[&DQNTransition]) -> Vec<f32> {
+        let discount = self.conf.discount;
+        let bootstrap = |t: &DQNTransition, reward: f32, done: bool, value: f32| {
+            if done { reward } else { reward + discount.powi(t.steps as i32) * value }
+        };
+        if !self.conf.double_dqn {
+            let next_states: Vec<&DQNState> = transitions.iter().map(|t| &t.next_state).collect();
+            let next_values = self.predict_target(&next_states);
+            return transitions.iter().zip(next_values)
+                .map(|(t, value)| bootstrap(t, t.reward, t.done, value))
+                .collect();
+        }
+        // all the candidates of the batch are evaluated at once
+        let states: Vec<&DQNState> = transitions.iter()
+            .flat_map(|t| t.candidates.iter().map(|(s, _, _)| s))
+            .collect();
+        let values = self.predict(&states);
+        let target_values = self.predict_target(&states);
+        let mut offset = 0;
+        transitions.iter()
+            .map(|t| {
+                let n = t.candidates.len();
+                let best = (0..n).max_by(|&i, &j| {
+                    let (_, r_i, d_i) = t.candidates[i];
+                    let (_, r_j, d_j) = t.candidates[j];
+                    let v_i = bootstrap(t, r_i, d_i, values[offset + i]);
+                    let v_j = bootstrap(t, r_j, d_j, values[offset + j]);
+                    or_neg_inf(v_i).total_cmp(&or_neg_inf(v_j))
+                });
+                let target = match best {
+                    Some(i) => {
+                        let (_, reward, done) = t.candidates[i];
+                        bootstrap(t, reward, done, target_values[offset + i])
+                    }
+                    None => t.reward,
+                };
+                offset += n;
+                target
+            })
+            .collect()
+    }
+
+    fn update_target(&mut self) {
+        let (target_vs, _) = match &mut self.target {
+            Some(target) => target,
+            None => return,
+        };
+        match self.conf.target_update {
+            TargetUpdate::Sync { every } => {
+                if self.train_steps.is_multiple_of(every.max(1) as u64) {
+                    target_vs.copy(&self.vs).expect("the target network of the same layout");
+                }
+            }
+            TargetUpdate::Polyak { tau } => {
+                let variables = self.vs.variables();
+                tch::no_grad(|| {
+                    for (name, mut target) in target_vs.variables() {
+                        let mixed = &variables[&name] * tau + &target * (1.0 - tau);
+                        target.copy_(&mixed);
+                    }
+                });
+            }
+            TargetUpdate::Off => {}
+        }
+    }
+
+    /// Called after every episode
+    pub fn decay_epsilon(&mut self) {
+        let step = (self.conf.epsilon - self.conf.epsilon_min) / self.conf.epsilon_stop_episode.max(1) as f32;
+        self.epsilon = (self.epsilon - step).max(self.conf.epsilon_min);
+    }
+}
+
+impl Agent for DQNAgent {
+    fn select_action(&mut self, env: &TetrisEnv) -> Option<DQNAction> {
+        let candidates = env.get_next_states();
+        if candidates.is_empty() {
+            None
+        } else {
+            Some(self.select_best_action(&candidates))
+        }
+    }
+
+    /// By the values of the afterstates, without the exploration
+    fn rank_actions(&mut self, env: &TetrisEnv) -> Vec<DQNAction> {
+        let candidates = env.get_next_states();
+        let states: Vec<&DQNState> = candidates.iter().map(|(_, s)| s).collect();
+        let mut ranked: Vec<(DQNAction, f32)> = candidates.iter().map(|(a, _)| *a).zip(self.predict(&states)).collect();
+        ranked.sort_by(|(_, v1), (_, v2)| or_neg_inf(*v2).total_cmp(&or_neg_inf(*v1)));
+        ranked.into_iter().map(|(action, _)| action).collect()
+    }
+
+    /// The transition goes to the memory, the training is up to the caller, see `train`
+    fn observe(&mut self, transition: DQNTransition) {
+        self.add_to_memory(transition);
+    }
+
+    fn reset(&mut self) {
+        self.finish_episode();
+    }
+
+    fn seed(&mut self, seed: u64) {
+        self.rng = Xoshiro512StarStar::seed_from_u64(seed);
+    }
+
+    /// See `checkpoint::save_agent`
+    fn save(&self, dir: &Path) -> Fallible<()> {
+        checkpoint::save_agent(dir, self)
+    }
+
+    /// The saved agent to play, it doesn't explore; `checkpoint::load` resumes the training
+    fn load(&mut self, dir: &Path) -> Fallible<()> {
+        let agent = checkpoint::load_agent(dir)?;
+        *self = DQNAgent { epsilon: 0.0, train_steps: self.train_steps, ..agent };
+        Ok(())
+    }
+}
+
+/// The multilayer perceptron from `N_FEATURES` inputs to the single value,
+/// the hidden layers are `conf.n_neurons`
+fn build_model(p: &nn::Path, conf: &AgentConf) -> Fallible<nn::Sequential> {
+    let mut sizes = vec![N_FEATURES as i64];
+    sizes.extend(conf.n_neurons.iter().map(|n| *n as i64));
+    sizes.push(1);
+    let mut model = nn::seq();
+    for (k, dims) in sizes.windows(2).enumerate() {
+        model = model.add(nn::linear(p / format!("layer{}", k), dims[0], dims[1], Default::default()));
+        model = match conf.activations.get(k).map(|a| a.as_str()) {
+            Some("relu") => model.add_fn(|xs| xs.relu()),
+            Some("tanh") => model.add_fn(|xs| xs.tanh()),
+            Some("sigmoid") => model.add_fn(|xs| xs.sigmoid()),
+            Some("linear") | None => model,
+            Some(other) => bail!("unknown activation {}", other),
+        };
+    }
+    Ok(model)
+}
+
+fn evaluate(model: &nn::Sequential, states: &[&DQNState]) -> Vec<f32> {
+    if states.is_empty() {
+        return vec![];
+    }
+    let xs = states_to_tensor(states);
+    let ys = tch::no_grad(|| model.forward(&xs));
+    (0..states.len())
+        .map(|i| ys.double_value(&[i as i64, 0]) as f32)
+        .collect()
+}
+
+fn states_to_tensor(states: &[&DQNState]) -> Tensor {
+    let features: Vec<f32> = states.iter().flat_map(|s| s.features().to_vec()).collect();
+    Tensor::of_slice(&features).view([states.len() as i64, N_FEATURES as i64])
+}
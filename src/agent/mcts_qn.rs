@@ -1,8 +1,430 @@
-use std::collections::VecDeque;
+//! Monte Carlo Tree Search over the placements
+//!
+//! The tree alternates the decision nodes (the piece is known, the agent chooses one of
+//! `TetrisEnv::get_valid_actions`) and the chance nodes (the placement is done, the game
+//! draws the new shape of the preview). The agent doesn't know the order of the bag, so
+//! every visit of a chance node plays the placement on the copy of the game with the
+//! shuffled bag and the fresh random generator, the outcomes are told apart by the preview.
+//! The leaves are valued by `Evaluator`: the random rollouts or the value network of
+//! `DQNAgent`. The subtree of the chosen placement and the actual new shape is kept
+//! for the next move.
+//!
+//! # Example
+//!
+//! ```
+//! use tetris::agent::{Agent, MCTSAgent, MCTSConf, RandomRollout};
+//! use tetris::train::TetrisEnv;
+//! let conf = MCTSConf { simulations: 20, ..Default::default() };
+//! let mut agent = MCTSAgent::new(conf, RandomRollout { depth: 2, discount: 0.99 }, Some(1));
+//! let mut env = TetrisEnv::new(Some(1));
+//! env.reset();
+//! let action = agent.select_action(&env).unwrap();
+//! assert!(env.get_valid_actions().contains(&action));
+//! ```
 
-struct State;
-struct Reward(f32);
+use rand::{Rng, SeedableRng};
+use rand::seq::SliceRandom;
+use rand_xoshiro::Xoshiro512StarStar;
+use serde::{Serialize, Deserialize};
+use crate::agent::{Agent, DQNAction, DQNAgent, DQNState, or_neg_inf};
+use crate::train::TetrisEnv;
 
-struct MCSTAgent {
-    memory: VecDeque<(State, State, Reward, bool)>
+/// How the placement is chosen in the decision node, `Q` are normalized to `[0, 1]`
+/// by the minimum and maximum values met in the tree
+/// - `Uct`: `Q + c * sqrt(ln N / n)`, the unvisited placements go first
+/// - `Puct`: `Q + c * P * sqrt(N) / (1 + n)` with the priors `P` of `Evaluator`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Selection {
+    Uct { c: f32 },
+    Puct { c: f32 },
+}
+
+/// `simulations` - the number of the simulations per move
+/// `discount` - the discount of the rewards in the tree
+/// `reuse_tree` - keep the subtree of the actual outcome for the next move
+/// `max_nodes` - the simulations stop when the tree has this many nodes, every decision node
+/// keeps its copy of the game; the kept subtree is dropped if it has half of them or more
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MCTSConf {
+    pub simulations: usize,
+    pub selection: Selection,
+    pub discount: f32,
+    pub reuse_tree: bool,
+    pub max_nodes: usize,
+}
+
+impl Default for MCTSConf {
+    fn default() -> Self {
+        MCTSConf {
+            simulations: 200,
+            selection: Selection::Puct { c: 1.5 },
+            discount: 0.99,
+            reuse_tree: true,
+            max_nodes: 20_000,
+        }
+    }
+}
+
+/// Values the leaves of the tree
+pub trait Evaluator {
+    /// The value of the state and the priors of `actions`, they sum to 1,
+    /// `actions` are not empty
+    fn evaluate<R: Rng>(&mut self, env: &TetrisEnv, actions: &[DQNAction], rng: &mut R) -> (f32, Vec<f32>);
+}
+
+/// The discounted return of up to `depth` random placements, the uniform priors
+pub struct RandomRollout {
+    pub depth: usize,
+    pub discount: f32,
+}
+
+impl Evaluator for RandomRollout {
+    fn evaluate<R: Rng>(&mut self, env: &TetrisEnv, actions: &[DQNAction], rng: &mut R) -> (f32, Vec<f32>) {
+        let mut env = env.clone();
+        let mut value = 0.0;
+        let mut gamma = 1.0;
+        for _ in 0..self.depth {
+            let action = match env.get_valid_actions().choose(rng) {
+                Some(action) => *action,
+                None => break,
+            };
+            let (_, reward, done) = env.step(action);
+            value += gamma * reward;
+            gamma *= self.discount;
+            if done {
+                break;
+            }
+        }
+        (value, vec![1.0 / actions.len() as f32; actions.len()])
+    }
+}
+
+/// The afterstate values of the network: the value of the state is the best
+/// `reward + discount * V(afterstate)`, the priors are the softmax of them with `temperature`
+pub struct QNetwork {
+    pub agent: DQNAgent,
+    pub temperature: f32,
+}
+
+impl Evaluator for QNetwork {
+    fn evaluate<R: Rng>(&mut self, env: &TetrisEnv, actions: &[DQNAction], _rng: &mut R) -> (f32, Vec<f32>) {
+        let transitions: Vec<(DQNState, f32, bool)> = actions.iter()
+            .map(|action| {
+                let mut env = env.clone();
+                env.step(*action)
+            })
+            .collect();
+        let states: Vec<&DQNState> = transitions.iter().map(|(s, _, _)| s).collect();
+        let values = self.agent.predict(&states);
+        let discount = self.agent.conf.discount;
+        let qs: Vec<f32> = transitions.iter().zip(values)
+            .map(|((_, reward, done), value)| if *done { *reward } else { reward + discount * value })
+            .collect();
+        let best = qs.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+        let exps: Vec<f32> = qs.iter().map(|q| ((q - best) / self.temperature.max(1e-6)).exp()).collect();
+        let sum: f32 = exps.iter().sum();
+        (best, exps.iter().map(|e| e / sum).collect())
+    }
+}
+
+/// `actions`, `priors`, `children` - empty until the node is expanded,
+/// the child is the chance node of the placement
+#[derive(Debug, Clone)]
+struct Decision {
+    env: TetrisEnv,
+    visits: u32,
+    actions: Vec<DQNAction>,
+    priors: Vec<f32>,
+    children: Vec<Option<usize>>,
+}
+
+/// `value_sum` - the sum of the returns of the placement over the visits
+/// `outcomes` - the decision nodes with the preview after the placement
+#[derive(Debug, Clone)]
+struct Chance {
+    visits: u32,
+    value_sum: f32,
+    outcomes: Vec<(Vec<usize>, usize)>,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Decision(Box<Decision>),
+    Chance(Chance),
+}
+
+/// `nodes` - the tree, the root is `nodes[0]`
+/// `last_action` - the placement chosen at the root
+pub struct MCTSAgent<E: Evaluator> {
+    pub conf: MCTSConf,
+    pub evaluator: E,
+    pub rng: Xoshiro512StarStar,
+    nodes: Vec<Node>,
+    last_action: Option<DQNAction>,
+    min_q: f32,
+    max_q: f32,
+}
+
+impl<E: Evaluator> MCTSAgent<E> {
+    pub fn new(conf: MCTSConf, evaluator: E, seed: Option<u64>) -> MCTSAgent<E> {
+        let rng = match seed {
+            Some(seed) => Xoshiro512StarStar::seed_from_u64(seed),
+            None => Xoshiro512StarStar::from_entropy(),
+        };
+        MCTSAgent {
+            conf,
+            evaluator,
+            rng,
+            nodes: vec![],
+            last_action: None,
+            min_q: f32::INFINITY,
+            max_q: f32::NEG_INFINITY,
+        }
+    }
+
+    /// Run the simulations from `env` and return the visits and the mean values
+    /// of the placements at the root, in the order of `get_valid_actions`
+    pub fn search(&mut self, env: &TetrisEnv) -> Vec<(DQNAction, u32, f32)> {
+        if !(self.conf.reuse_tree && self.reuse(env) && self.nodes.len() < self.conf.max_nodes / 2) {
+            self.nodes = vec![Node::Decision(Box::new(new_decision(env.clone())))];
+            self.min_q = f32::INFINITY;
+            self.max_q = f32::NEG_INFINITY;
+        }
+        for _ in 0..self.conf.simulations {
+            // a simulation adds the chance node and the decision node at most
+            if self.nodes.len() + 2 > self.conf.max_nodes {
+                break;
+            }
+            self.simulate(0);
+        }
+        let root = self.decision(0);
+        root.actions.iter().zip(&root.children)
+            .map(|(action, child)| match child.map(|k| self.chance(k)) {
+                Some(chance) => (*action, chance.visits, chance.value_sum / chance.visits.max(1) as f32),
+                None => (*action, 0, 0.0),
+            })
+            .collect()
+    }
+
+    /// The number of the nodes in the tree
+    pub fn tree_size(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// The value of the decision node
+    fn simulate(&mut self, node: usize) -> f32 {
+        if self.decision(node).actions.is_empty() {
+            let env = &self.decision(node).env;
+            if env.gs.game_over {
+                return 0.0;
+            }
+            let actions = env.get_valid_actions();
+            if actions.is_empty() {
+                return 0.0;
+            }
+            let env = env.clone();
+            let (value, priors) = self.evaluator.evaluate(&env, &actions, &mut self.rng);
+            let decision = self.decision_mut(node);
+            decision.children = vec![None; actions.len()];
+            decision.actions = actions;
+            decision.priors = priors;
+            decision.visits = 1;
+            return value;
+        }
+        let k = self.select(node);
+        let value = self.simulate_chance(node, k);
+        self.decision_mut(node).visits += 1;
+        value
+    }
+
+    /// The value of the placement `k` of the decision node `parent` with the sampled new shape
+    fn simulate_chance(&mut self, parent: usize, k: usize) -> f32 {
+        let mut env = self.decision(parent).env.clone();
+        let action = self.decision(parent).actions[k];
+        // the agent doesn't know the order of the bag
+        env.gs.rng_queue.shuffle(&mut self.rng);
+        env.gs.rng = Xoshiro512StarStar::seed_from_u64(self.rng.gen());
+        let (_, reward, done) = env.step(action);
+        let chance = match self.decision(parent).children[k] {
+            Some(chance) => chance,
+            None => {
+                let chance = self.push(Node::Chance(Chance { visits: 0, value_sum: 0.0, outcomes: vec![] }));
+                self.decision_mut(parent).children[k] = Some(chance);
+                chance
+            }
+        };
+        let value = if done {
+            reward
+        } else {
+            let key: Vec<usize> = env.gs.next_shape_idxs.iter().cloned().collect();
+            let existing = self.chance(chance).outcomes.iter().find(|(o, _)| *o == key).map(|(_, node)| *node);
+            let child = match existing {
+                Some(child) => child,
+                None => {
+                    let child = self.push(Node::Decision(Box::new(new_decision(env))));
+                    if let Node::Chance(c) = &mut self.nodes[chance] {
+                        c.outcomes.push((key, child));
+                    }
+                    child
+                }
+            };
+            reward + self.conf.discount * self.simulate(child)
+        };
+        if let Node::Chance(c) = &mut self.nodes[chance] {
+            c.visits += 1;
+            c.value_sum += value;
+            let q = c.value_sum / c.visits as f32;
+            self.min_q = self.min_q.min(q);
+            self.max_q = self.max_q.max(q);
+        }
+        value
+    }
+
+    /// The index of the placement by `conf.selection`
+    fn select(&self, node: usize) -> usize {
+        let decision = self.decision(node);
+        let total = decision.visits as f32;
+        let score = |k: usize| {
+            let (visits, q) = match decision.children[k].map(|c| self.chance(c)) {
+                Some(chance) if chance.visits > 0 => (chance.visits as f32, self.normalize(chance.value_sum / chance.visits as f32)),
+                _ => (0.0, 0.0),
+            };
+            match self.conf.selection {
+                Selection::Uct { c } => {
+                    if visits == 0.0 {
+                        f32::INFINITY
+                    } else {
+                        q + c * (total.ln() / visits).sqrt()
+                    }
+                }
+                Selection::Puct { c } => q + c * decision.priors[k] * total.sqrt() / (1.0 + visits),
+            }
+        };
+        (0..decision.actions.len())
+            .max_by(|&i, &j| or_neg_inf(score(i)).total_cmp(&or_neg_inf(score(j))))
+            .unwrap()
+    }
+
+    fn normalize(&self, q: f32) -> f32 {
+        if self.max_q > self.min_q {
+            (q - self.min_q) / (self.max_q - self.min_q)
+        } else {
+            0.5
+        }
+    }
+
+    /// Make the decision node of `env` after the last move the root,
+    /// `false` if the tree has no such node
+    fn reuse(&mut self, env: &TetrisEnv) -> bool {
+        let action = match (self.last_action, self.nodes.first()) {
+            (Some(action), Some(_)) => action,
+            _ => return false,
+        };
+        let root = self.decision(0);
+        let chance = match root.actions.iter().position(|a| *a == action).and_then(|k| root.children[k]) {
+            Some(chance) => chance,
+            None => return false,
+        };
+        let found = self.chance(chance).outcomes.iter()
+            .map(|(_, node)| *node)
+            .find(|node| {
+                let gs = &self.decision(*node).env.gs;
+                gs.field == env.gs.field
+                    && gs.curr_shape_idx == env.gs.curr_shape_idx
+                    && gs.next_shape_idxs == env.gs.next_shape_idxs
+                    && gs.hold_shape_idx == env.gs.hold_shape_idx
+            });
+        match found {
+            Some(node) => {
+                let mut nodes = vec![];
+                self.copy_subtree(node, &mut nodes);
+                self.nodes = nodes;
+                // the real game goes on with its own random generator
+                if let Node::Decision(d) = &mut self.nodes[0] {
+                    d.env = env.clone();
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Copy the subtree of `node` to `nodes`, return the new index of `node`
+    fn copy_subtree(&self, node: usize, nodes: &mut Vec<Node>) -> usize {
+        let index = nodes.len();
+        nodes.push(self.nodes[node].clone());
+        match &self.nodes[node] {
+            Node::Decision(d) => {
+                let children: Vec<Option<usize>> = d.children.iter()
+                    .map(|child| child.map(|c| self.copy_subtree(c, nodes)))
+                    .collect();
+                if let Node::Decision(copy) = &mut nodes[index] {
+                    copy.children = children;
+                }
+            }
+            Node::Chance(c) => {
+                let outcomes: Vec<(Vec<usize>, usize)> = c.outcomes.iter()
+                    .map(|(key, child)| (key.clone(), self.copy_subtree(*child, nodes)))
+                    .collect();
+                if let Node::Chance(copy) = &mut nodes[index] {
+                    copy.outcomes = outcomes;
+                }
+            }
+        }
+        index
+    }
+
+    fn push(&mut self, node: Node) -> usize {
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    fn decision(&self, node: usize) -> &Decision {
+        match &self.nodes[node] {
+            Node::Decision(d) => d,
+            Node::Chance(_) => unreachable!("the node {} is a chance node", node),
+        }
+    }
+
+    fn decision_mut(&mut self, node: usize) -> &mut Decision {
+        match &mut self.nodes[node] {
+            Node::Decision(d) => d,
+            Node::Chance(_) => unreachable!("the node {} is a chance node", node),
+        }
+    }
+
+    fn chance(&self, node: usize) -> &Chance {
+        match &self.nodes[node] {
+            Node::Chance(c) => c,
+            Node::Decision(_) => unreachable!("the node {} is a decision node", node),
+        }
+    }
+}
+
+fn new_decision(env: TetrisEnv) -> Decision {
+    Decision { env, visits: 0, actions: vec![], priors: vec![], children: vec![] }
+}
+
+impl<E: Evaluator> Agent for MCTSAgent<E> {
+    /// The most visited placement, the ties are broken by the value
+    fn select_action(&mut self, env: &TetrisEnv) -> Option<DQNAction> {
+        self.rank_actions(env).into_iter().next()
+    }
+
+    /// By the visits and the values of `search`
+    fn rank_actions(&mut self, env: &TetrisEnv) -> Vec<DQNAction> {
+        let mut stats = self.search(env);
+        stats.sort_by(|(_, n1, q1), (_, n2, q2)| n2.cmp(n1).then(or_neg_inf(*q2).total_cmp(&or_neg_inf(*q1))));
+        self.last_action = stats.first().map(|(action, _, _)| *action);
+        stats.into_iter().map(|(action, _, _)| action).collect()
+    }
+
+    fn reset(&mut self) {
+        self.nodes.clear();
+        self.last_action = None;
+    }
+
+    fn seed(&mut self, seed: u64) {
+        self.rng = Xoshiro512StarStar::seed_from_u64(seed);
+    }
 }
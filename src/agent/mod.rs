@@ -8,10 +8,16 @@
 //! }
 //! ```
 
+pub mod adam;
+pub mod baseline;
 pub mod core;
 pub mod dqn;
 pub mod mcts_qn;
+pub mod replay;
 
+pub use adam::*;
+pub use baseline::*;
 pub use self::core::*;
 pub use dqn::*;
 pub use mcts_qn::*;
+pub use replay::*;
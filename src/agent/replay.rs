@@ -0,0 +1,216 @@
+//! Experience replay
+//!
+//! `ReplayBuffer` keeps the last `capacity` transitions and samples the training batches,
+//! either uniformly or with the priorities (Schaul et al., "Prioritized Experience Replay"):
+//! the transition `i` is sampled with the probability `p_i^alpha / sum_k p_k^alpha`, where
+//! `p_i` is its last absolute TD error plus `eps`, and the bias is corrected by the
+//! importance-sampling weights `(N * P(i))^-beta` normalized by their maximum.
+//!
+//! # Example
+//!
+//! ```
+//! use rand::SeedableRng;
+//! use rand_xoshiro::Xoshiro512StarStar;
+//! use tetris::agent::{ReplayBuffer, ReplayMode};
+//! let mut rng = Xoshiro512StarStar::seed_from_u64(1);
+//! let mut buffer = ReplayBuffer::new(3, ReplayMode::prioritized());
+//! for item in 0..5 {
+//!     buffer.push(item);
+//! }
+//! assert_eq!(buffer.len(), 3);
+//! let batch = buffer.sample(&mut rng, 2);
+//! buffer.update_priorities(&batch.indices, &[0.5, 2.0]);
+//! assert!(batch.indices.iter().all(|&k| buffer[k] >= 2));
+//! ```
+
+use std::ops::Index;
+use rand::Rng;
+use rand::seq::index::sample;
+use serde::{Serialize, Deserialize};
+
+/// - `alpha`: how much the priorities matter, 0 is the uniform sampling
+/// - `beta`: the initial exponent of the importance-sampling weights,
+///   it grows by `beta_increment` with every sampled batch up to 1
+/// - `eps`: added to the TD errors, so every transition can be sampled
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ReplayMode {
+    Uniform,
+    Prioritized { alpha: f64, beta: f64, beta_increment: f64, eps: f64 },
+}
+
+impl ReplayMode {
+    /// The parameters from the paper
+    pub fn prioritized() -> ReplayMode {
+        ReplayMode::Prioritized { alpha: 0.6, beta: 0.4, beta_increment: 1e-3, eps: 1e-2 }
+    }
+}
+
+/// The sampled indices in the buffer and their importance-sampling weights
+#[derive(Clone, Debug, PartialEq)]
+pub struct Batch {
+    pub indices: Vec<usize>,
+    pub weights: Vec<f32>,
+}
+
+/// The binary tree, where every node is the sum of its children,
+/// the leaves are the priorities, `nodes[1]` is the root
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SumTree {
+    capacity: usize,
+    nodes: Vec<f64>,
+}
+
+impl SumTree {
+    pub fn new(capacity: usize) -> SumTree {
+        SumTree { capacity, nodes: vec![0.0; 2 * capacity] }
+    }
+
+    pub fn total(&self) -> f64 {
+        self.nodes[1]
+    }
+
+    pub fn get(&self, i: usize) -> f64 {
+        self.nodes[self.capacity + i]
+    }
+
+    pub fn update(&mut self, i: usize, priority: f64) {
+        let mut k = self.capacity + i;
+        self.nodes[k] = priority;
+        while k > 1 {
+            k /= 2;
+            self.nodes[k] = self.nodes[2 * k] + self.nodes[2 * k + 1];
+        }
+    }
+
+    /// The leaf `i`, where the prefix sum of the priorities passes `value`
+    pub fn find(&self, mut value: f64) -> usize {
+        let mut k = 1;
+        while k < self.capacity {
+            let left = self.nodes[2 * k];
+            if value < left || self.nodes[2 * k + 1] == 0.0 {
+                k *= 2;
+            } else {
+                value -= left;
+                k = 2 * k + 1;
+            }
+        }
+        k - self.capacity
+    }
+}
+
+/// The ring buffer of the transitions, the oldest ones are replaced when it is full
+/// - `max_priority`: the priority of the new transitions, so each is sampled at least once
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReplayBuffer<T> {
+    pub mode: ReplayMode,
+    capacity: usize,
+    items: Vec<T>,
+    next: usize,
+    tree: SumTree,
+    max_priority: f64,
+    beta: f64,
+}
+
+impl<T> ReplayBuffer<T> {
+    pub fn new(capacity: usize, mode: ReplayMode) -> ReplayBuffer<T> {
+        assert!(capacity > 0, "the replay buffer must have a positive capacity");
+        let (tree, beta) = match mode {
+            ReplayMode::Uniform => (SumTree::new(0), 1.0),
+            ReplayMode::Prioritized { beta, .. } => (SumTree::new(capacity), beta),
+        };
+        ReplayBuffer {
+            mode,
+            capacity,
+            items: Vec::with_capacity(capacity),
+            next: 0,
+            tree,
+            max_priority: 1.0,
+            beta,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The transitions in the storage order, not the insertion one
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.items.iter()
+    }
+
+    pub fn push(&mut self, item: T) {
+        if self.items.len() < self.capacity {
+            self.items.push(item);
+        } else {
+            self.items[self.next] = item;
+        }
+        if let ReplayMode::Prioritized { alpha, .. } = self.mode {
+            self.tree.update(self.next, self.max_priority.powf(alpha));
+        }
+        self.next = (self.next + 1) % self.capacity;
+    }
+
+    /// Sample `batch_size` transitions, without repetitions in the uniform mode,
+    /// the buffer must not be empty
+    pub fn sample<R: Rng>(&mut self, rng: &mut R, batch_size: usize) -> Batch {
+        let n = self.items.len();
+        match self.mode {
+            ReplayMode::Uniform => {
+                let indices = sample(rng, n, batch_size.min(n)).into_vec();
+                let weights = vec![1.0; indices.len()];
+                Batch { indices, weights }
+            }
+            ReplayMode::Prioritized { beta_increment, .. } => {
+                // the stratified sampling: one index from every segment of the priorities
+                let total = self.tree.total();
+                let segment = total / batch_size as f64;
+                let indices: Vec<usize> = (0..batch_size)
+                    .map(|k| {
+                        let value = segment * (k as f64 + rng.gen::<f64>());
+                        self.tree.find(value.min(total)).min(n - 1)
+                    })
+                    .collect();
+                let min_probability = (0..n)
+                    .map(|i| self.tree.get(i))
+                    .fold(f64::INFINITY, f64::min) / total;
+                let max_weight = (n as f64 * min_probability).powf(-self.beta);
+                let weights = indices.iter()
+                    .map(|&i| {
+                        let probability = self.tree.get(i) / total;
+                        ((n as f64 * probability).powf(-self.beta) / max_weight) as f32
+                    })
+                    .collect();
+                self.beta = (self.beta + beta_increment).min(1.0);
+                Batch { indices, weights }
+            }
+        }
+    }
+
+    /// Set the priorities of the sampled transitions from their TD errors,
+    /// nothing happens in the uniform mode
+    pub fn update_priorities(&mut self, indices: &[usize], td_errors: &[f32]) {
+        if let ReplayMode::Prioritized { alpha, eps, .. } = self.mode {
+            for (&i, td_error) in indices.iter().zip(td_errors) {
+                let priority = td_error.abs() as f64 + eps;
+                self.max_priority = self.max_priority.max(priority);
+                self.tree.update(i, priority.powf(alpha));
+            }
+        }
+    }
+}
+
+impl<T> Index<usize> for ReplayBuffer<T> {
+    type Output = T;
+
+    fn index(&self, i: usize) -> &T {
+        &self.items[i]
+    }
+}
@@ -0,0 +1,28 @@
+//! TBP bot on stdin/stdout, e.g. for the matches against Cold Clear
+use std::io::{stdin, stdout};
+use structopt::StructOpt;
+use std::path::PathBuf;
+use tetris::agent::new_agent;
+use tetris::tbp::{self, TbpBot};
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "tetris-tbp", about = "Tetris Bot Protocol bot")]
+struct Opt {
+    /// The seed of the agent
+    #[structopt(short = "s", long = "seed")]
+    seed: Option<u64>,
+    /// The agent: dqn, random, greedy or mcts
+    #[structopt(short = "a", long = "agent", default_value = "dqn")]
+    agent: String,
+    /// The directory with the saved agent, e.g. the training checkpoint
+    #[structopt(long = "load", parse(from_os_str))]
+    load: Option<PathBuf>,
+}
+
+fn main() -> failure::Fallible<()> {
+    let opt: Opt = Opt::from_args();
+    let agent = new_agent(&opt.agent, opt.seed, opt.load.as_deref())?;
+    let mut bot = TbpBot::new(agent);
+    let stdin = stdin();
+    tbp::run(&mut bot, stdin.lock(), stdout())
+}
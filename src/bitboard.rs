@@ -0,0 +1,228 @@
+//! Bitboard representation of the field for the fast simulation
+//!
+//! `BitField` keeps the occupancy only (no colors), one `u64` per row,
+//! the bit `j` is the column `j`, so the fields up to 64 columns wide are supported.
+//! The collision check of the shape is a few mask operations, see `ShapeMask`.
+//!
+//! # Example
+//!
+//! ```
+//! use tetris::bitboard::BitField;
+//! use tetris::model::Point;
+//! use tetris::field;
+//! let field = field!("
+//!     . . . .
+//!     I . . .
+//!     I I . I
+//! ");
+//! let bits = BitField::from(&field);
+//! assert_eq!(bits.rows, vec![0b0000, 0b0001, 0b1011]);
+//! assert!(bits.fits(1, &Point(0, 2), 0)); // O
+//! assert!(!bits.fits(1, &Point(1, 2), 0));
+//! assert_eq!(bits.column_heights(), vec![2, 1, 0, 1]);
+//! ```
+
+use crate::model::{Field, Occupancy, Point, GARBAGE, WALL_KICKS_I, WALL_KICKS_X, rotated};
+use crate::tetrimino::TETRIMINOES;
+
+/// The rotated shape as the row masks, `rows` are `(di, mask)`, where
+/// the bit 0 of `mask` is the column `base.1 + min_dj`
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct ShapeMask {
+    pub rows: Vec<(i32, u64)>,
+    pub min_dj: i32,
+    pub max_dj: i32,
+}
+
+impl ShapeMask {
+    pub fn new(shape_idx: usize, rotation: i8) -> ShapeMask {
+        let diffs = rotated(shape_idx, rotation);
+        let min_dj = diffs.iter().map(|p| p.1).min().unwrap();
+        let max_dj = diffs.iter().map(|p| p.1).max().unwrap();
+        let mut rows: Vec<(i32, u64)> = vec![];
+        for p in diffs {
+            let bit = 1u64 << (p.1 - min_dj) as u64;
+            match rows.iter_mut().find(|(di, _)| *di == p.0) {
+                Some((_, mask)) => *mask |= bit,
+                None => rows.push((p.0, bit)),
+            }
+        }
+        rows.sort();
+        ShapeMask { rows, min_dj, max_dj }
+    }
+}
+
+lazy_static! {
+    /// `SHAPE_MASKS[shape_idx][rotation]`
+    pub static ref SHAPE_MASKS: Vec<Vec<ShapeMask>> = {
+        (0..TETRIMINOES.len())
+            .map(|idx| (0..4).map(|r| ShapeMask::new(idx, r)).collect())
+            .collect()
+    };
+}
+
+#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+pub struct BitField {
+    pub rows: Vec<u64>,
+    pub height: usize,
+    pub width: usize,
+}
+
+impl BitField {
+    pub fn new(height: usize, width: usize) -> BitField {
+        assert!(width <= 64, "BitField supports the widths up to 64");
+        BitField { rows: vec![0; height], height, width }
+    }
+
+    /// The mask of the full row
+    pub fn full_row(&self) -> u64 {
+        if self.width == 64 { !0 } else { (1u64 << self.width as u64) - 1 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.iter().all(|r| *r == 0)
+    }
+
+    pub fn is_occupied(&self, i: usize, j: usize) -> bool {
+        self.rows[i] >> j as u64 & 1 == 1
+    }
+
+    /// The same as `model::try_shape(..).is_some()`, but without the allocation
+    pub fn fits(&self, shape_idx: usize, base: &Point, rotation: i8) -> bool {
+        self.fits_mask(&SHAPE_MASKS[shape_idx][rotation.rem_euclid(4) as usize], base)
+    }
+
+    /// The same as `fits` with the mask of the shape taken from `SHAPE_MASKS` by the caller
+    pub fn fits_mask(&self, mask: &ShapeMask, base: &Point) -> bool {
+        let j0 = base.1 + mask.min_dj;
+        if j0 < 0 || base.1 + mask.max_dj >= self.width as i32 {
+            return false;
+        }
+        mask.rows.iter().all(|(di, row_mask)| {
+            let i = base.0 + di;
+            0 <= i && i < self.height as i32 && self.rows[i as usize] & (row_mask << j0 as u64) == 0
+        })
+    }
+
+    /// The wall kick of the shape at `base`, the same as `model::try_wall_kick`
+    pub fn try_wall_kick(&self, shape_idx: usize, base: &Point, transition: (i8, i8)) -> Option<(Point, usize)> {
+        let test_points = if shape_idx == 0 {
+            WALL_KICKS_I.get(&transition)?
+        } else {
+            WALL_KICKS_X.get(&transition)?
+        };
+        test_points.iter().enumerate().find_map(|(k, t)| {
+            let base_new = Point(base.0 + t.0, base.1 + t.1);
+            if self.fits(shape_idx, &base_new, transition.1) {
+                Some((base_new, k))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// The first row, where the shape cannot go, when dropped from `base`
+    pub fn drop_row(&self, shape_idx: usize, base: &Point, rotation: i8) -> i32 {
+        let mut i = base.0;
+        while self.fits(shape_idx, &Point(i + 1, base.1), rotation) {
+            i += 1;
+        }
+        i + 1
+    }
+
+    pub fn fill(&mut self, cells: &[Point]) {
+        for p in cells {
+            self.rows[p.0 as usize] |= 1u64 << p.1 as u64;
+        }
+    }
+
+    /// Remove the full rows, the rows above fall down, return the number of the burnt lines
+    pub fn burn_lines(&mut self) -> usize {
+        let full = self.full_row();
+        // `it` is the row to move the next kept row to, we iterate down up
+        let mut it = self.rows.len();
+        for i in (0..self.rows.len()).rev() {
+            if self.rows[i] != full {
+                it -= 1;
+                self.rows[it] = self.rows[i];
+            }
+        }
+        self.rows[..it].iter_mut().for_each(|r| *r = 0);
+        it
+    }
+
+    /// The heights of the columns, the same as `TetrisEnv::get_block_heights`
+    pub fn column_heights(&self) -> Vec<u16> {
+        let mut heights = vec![0; self.width];
+        // the rows from the top, every column gets the height of its first block
+        let mut seen = 0u64;
+        for (i, row) in self.rows.iter().enumerate() {
+            let mut new = row & !seen;
+            while new != 0 {
+                let j = new.trailing_zeros() as usize;
+                heights[j] = (self.height - i) as u16;
+                new &= new - 1;
+            }
+            seen |= row;
+        }
+        heights
+    }
+
+    /// The empty cells below the top block of their columns,
+    /// the same as `TetrisEnv::get_sum_holes`
+    pub fn holes(&self) -> u16 {
+        let mut covered = 0u64;
+        let mut holes = 0;
+        for row in &self.rows {
+            holes += (covered & !row).count_ones() as u16;
+            covered |= row;
+        }
+        holes
+    }
+
+    /// The sum of the height differences of the neighbour columns
+    pub fn bumpiness(&self) -> u16 {
+        self.column_heights()
+            .windows(2)
+            .map(|hs| (hs[0] as i16 - hs[1] as i16).unsigned_abs())
+            .sum()
+    }
+}
+
+impl Occupancy for BitField {
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn is_occupied(&self, i: usize, j: usize) -> bool {
+        self.rows[i] >> j as u64 & 1 == 1
+    }
+}
+
+impl From<&Field> for BitField {
+    fn from(field: &Field) -> BitField {
+        let mut bits = BitField::new(field.height, field.width);
+        for (i, row) in field.cells.iter().enumerate() {
+            for (j, cell) in row.iter().enumerate() {
+                if *cell != 0 {
+                    bits.rows[i] |= 1u64 << j as u64;
+                }
+            }
+        }
+        bits
+    }
+}
+
+impl From<&BitField> for Field {
+    /// The colors are lost, the occupied cells become garbage
+    fn from(bits: &BitField) -> Field {
+        let cells = (0..bits.height)
+            .map(|i| (0..bits.width).map(|j| if bits.is_occupied(i, j) { GARBAGE } else { 0 }).collect())
+            .collect();
+        Field { cells, height: bits.height, width: bits.width }
+    }
+}
@@ -0,0 +1,139 @@
+//! Checkpoints of the DQN training
+//!
+//! A checkpoint directory holds the versions of the checkpoint in the subdirectories,
+//! and the file `current` with the name of the complete one. A version has the files:
+//! - `model.ot`: the weights of the value network, see `VarStore::save`
+//! - `target.ot`: the weights of the target network, if any
+//! - `adam.ot`: the state of the optimizer, see `agent::Adam`
+//! - `conf.json`: `AgentConf`
+//! - `progress.json`: `Progress`
+//! - `memory.bin`: the replay memory
+//! - `rng.bin`: the state of the agent's `Xoshiro512StarStar`
+//! - `env.bin`: the game, see `snapshot`
+//!
+//! A save writes the new version and then replaces `current` by renaming a temporary
+//! file, so an interrupted save leaves the previous version in use. The previous
+//! version is removed after that.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use failure::{bail, Fallible};
+use serde::{Serialize, Deserialize};
+use crate::agent::{AgentConf, DQNAgent};
+use crate::model::GameState;
+use crate::reward::Rewards;
+use crate::train::TetrisEnv;
+
+/// Increment it when the layout of the checkpoint changes
+pub const CHECKPOINT_VERSION: u32 = 5;
+
+/// - `episode`: the number of the finished episodes
+/// - `epsilon`: the current exploration rate of the agent
+/// - `scores`: the scores of the finished episodes
+/// - `train_steps`: see `DQNAgent::train_steps`
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Progress {
+    pub version: u32,
+    pub episode: i32,
+    pub epsilon: f32,
+    pub scores: Vec<u32>,
+    pub train_steps: u64,
+}
+
+impl Progress {
+    pub fn new(epsilon: f32) -> Progress {
+        Progress { version: CHECKPOINT_VERSION, episode: 0, epsilon, scores: vec![], train_steps: 0 }
+    }
+}
+
+pub fn save<P: AsRef<Path>>(dir: P, agent: &DQNAgent, env: &TetrisEnv, progress: &Progress) -> Fallible<()> {
+    write_version(dir.as_ref(), |dir| {
+        write_agent(dir, agent)?;
+        fs::write(dir.join("env.bin"), &env.gs.to_bytes()?)?;
+        fs::write(dir.join("progress.json"), serde_json::to_string_pretty(progress)?)?;
+        Ok(())
+    })
+}
+
+pub fn load<P: AsRef<Path>>(dir: P) -> Fallible<(DQNAgent, TetrisEnv, Progress)> {
+    let dir = current_version(dir.as_ref())?;
+    let progress: Progress = serde_json::from_str(&fs::read_to_string(dir.join("progress.json"))?)?;
+    if progress.version != CHECKPOINT_VERSION {
+        bail!("unsupported checkpoint version {}, expected {}", progress.version, CHECKPOINT_VERSION);
+    }
+    let mut agent = read_agent(&dir)?;
+    agent.train_steps = progress.train_steps;
+    agent.epsilon = progress.epsilon;
+    let gs = GameState::from_bytes(&fs::read(dir.join("env.bin"))?)?;
+    let mut env = TetrisEnv::from_state(gs);
+    env.reward = Arc::new(Rewards::new(&agent.conf.reward)?);
+    Ok((agent, env, progress))
+}
+
+/// The files of the agent only: the networks, the optimizer, `conf.json`, `memory.bin` and `rng.bin`
+pub fn save_agent<P: AsRef<Path>>(dir: P, agent: &DQNAgent) -> Fallible<()> {
+    write_version(dir.as_ref(), |dir| write_agent(dir, agent))
+}
+
+/// The agent saved by `save_agent` or `save`, `epsilon` and `train_steps` are the initial ones
+pub fn load_agent<P: AsRef<Path>>(dir: P) -> Fallible<DQNAgent> {
+    read_agent(&current_version(dir.as_ref())?)
+}
+
+fn write_agent(dir: &Path, agent: &DQNAgent) -> Fallible<()> {
+    agent.vs.save(dir.join("model.ot"))?;
+    if let Some((target_vs, _)) = &agent.target {
+        target_vs.save(dir.join("target.ot"))?;
+    }
+    agent.opt.state.save(dir.join("adam.ot"))?;
+    fs::write(dir.join("conf.json"), serde_json::to_string_pretty(&agent.conf)?)?;
+    fs::write(dir.join("memory.bin"), &bincode::serialize(&agent.memory)?)?;
+    fs::write(dir.join("rng.bin"), &bincode::serialize(&agent.rng)?)?;
+    Ok(())
+}
+
+fn read_agent(dir: &Path) -> Fallible<DQNAgent> {
+    let conf: AgentConf = serde_json::from_str(&fs::read_to_string(dir.join("conf.json"))?)?;
+    let mut agent = DQNAgent::with_conf(conf, None)?;
+    agent.vs.load(dir.join("model.ot"))?;
+    if let Some((target_vs, _)) = &mut agent.target {
+        target_vs.load(dir.join("target.ot"))?;
+    }
+    agent.opt.state.load(dir.join("adam.ot"))?;
+    agent.memory = bincode::deserialize(&fs::read(dir.join("memory.bin"))?)?;
+    agent.rng = bincode::deserialize(&fs::read(dir.join("rng.bin"))?)?;
+    Ok(agent)
+}
+
+/// The subdirectory of the complete version of the checkpoint in `dir`
+pub fn current_version(dir: &Path) -> Fallible<PathBuf> {
+    let name = fs::read_to_string(dir.join("current"))?;
+    Ok(dir.join(name.trim()))
+}
+
+/// Write the next version of the checkpoint with `write` and make it the current one
+fn write_version(dir: &Path, write: impl FnOnce(&Path) -> Fallible<()>) -> Fallible<()> {
+    fs::create_dir_all(dir)?;
+    let previous = current_version(dir).ok();
+    let n = previous.as_ref()
+        .and_then(|path| path.file_name()?.to_str()?.trim_start_matches('v').parse::<u64>().ok())
+        .map_or(0, |n| n + 1);
+    let name = format!("v{}", n);
+    let version_dir = dir.join(&name);
+    if version_dir.exists() {
+        // left by an interrupted save
+        fs::remove_dir_all(&version_dir)?;
+    }
+    fs::create_dir(&version_dir)?;
+    write(&version_dir)?;
+    let tmp = dir.join("current.tmp");
+    fs::write(&tmp, &name)?;
+    fs::rename(&tmp, dir.join("current"))?;
+    if let Some(previous) = previous {
+        if previous.exists() {
+            fs::remove_dir_all(previous)?;
+        }
+    }
+    Ok(())
+}
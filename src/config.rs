@@ -1,33 +1,123 @@
 use core::default::Default;
+use serde::{Serialize, Deserialize};
+use crate::model::Point;
 
 /// - `BurnOnly`: 1 for each line burnt, no matter hom much a time
 /// - `PieceAndBurn`:  1 + (lines_burnt ^ 2) * field_width
-#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+/// - `Guideline`: singles, doubles, triples, tetrises and T-spins
+///   are awarded as in the Tetris Guideline, multiplied by the level
+/// - `Versus`: the number of garbage lines sent to the opponent, including
+///   T-spins, combos, back-to-back and perfect clear bonuses
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Serialize, Deserialize)]
 pub enum Scoring {
     BurnOnly,
     PieceAndBurn,
+    Guideline,
+    Versus,
 }
 
 /// This defines how to choose new tetrimino to spawn.
 /// - `JustRandom`: next_shape_idx = rng.gen_range(0, TETRIMINOES.len());
 /// - `ShuffledQueue`: next_shape_idx = random_deque.pop_back()
-#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+///
+/// In both cases the new index goes to the end of the preview `next_shape_idxs`
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Serialize, Deserialize)]
 pub enum Randomness {
     JustRandom,
     ShuffledQueue,
 }
 
-#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+/// Real-time rules (Guideline-like), all durations are in milliseconds
+/// - `start_level`: the level at the game start, the gravity grows with the level
+/// - `lines_per_level`: how many lines should be burnt to get the next level
+/// - `lock_delay`: how long the piece can rest on the ground before it is locked
+/// - `max_lock_resets`: how many moves and rotations on the ground reset the lock delay,
+///   after that the piece is locked as soon as it touches the ground
+/// - `soft_drop_factor`: how many times the soft drop is faster than the gravity
+/// - `input_delay`: the time of every input of the placement played by `TetrisEnv::step`
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Timing {
+    pub start_level: u32,
+    pub lines_per_level: u32,
+    pub lock_delay: u32,
+    pub max_lock_resets: u32,
+    pub soft_drop_factor: u32,
+    pub input_delay: u32,
+}
+
+impl Default for Timing {
+    fn default() -> Self {
+        Timing {
+            start_level: 1,
+            lines_per_level: 10,
+            lock_delay: 500,
+            max_lock_resets: 15,
+            soft_drop_factor: 20,
+            input_delay: 50,
+        }
+    }
+}
+
+/// The base of the new piece in the rotation 0
+/// - `Default`: the row 0 for O and 1 for the others, the column `width / 2 - 1`
+/// - `Custom`: the base of every shape in the order of `TETRIMINOES`
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum Spawn {
+    Default,
+    Custom([Point; 7]),
+}
+
+/// When the game is over
+/// - `BlockOut`: the new piece overlaps the blocks at its spawn position
+/// - `LockOut`: also when the piece is locked entirely in the hidden rows,
+///   even if it burns lines
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum TopOut {
+    BlockOut,
+    LockOut,
+}
+
+/// - `height`, `width`: the visible part of the field, see `GameState::from_config`
+/// - `hidden_rows`: the buffer above the visible rows, the field has `height + hidden_rows` rows
+/// - `hold_enabled`: whether `Action::Hold` is allowed, the hold slot
+///   can be used once per drop
+/// - `preview_len`: how many upcoming shapes are known, at least 1
+/// - `timing`: the real-time rules for `GameState::advance`, `None` means
+///   the piece is moved by `Action::Tick` only and locks immediately,
+///   with the timing `TetrisEnv::step` plays the placements against the clock
+/// - `track_finesse`: count `GameState::finesse_faults` on every lock,
+///   it is the search over the placements, so it is meant for the human games
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
+    pub height: usize,
+    pub width: usize,
+    pub hidden_rows: usize,
+    pub spawn: Spawn,
+    pub top_out: TopOut,
     pub scoring: Scoring,
     pub randomness: Randomness,
+    pub hold_enabled: bool,
+    pub preview_len: usize,
+    pub timing: Option<Timing>,
+    pub track_finesse: bool,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Config {
+            height: 20,
+            width: 10,
+            hidden_rows: 2,
+            spawn: Spawn::Default,
+            top_out: TopOut::BlockOut,
             scoring: Scoring::BurnOnly,
             randomness: Randomness::JustRandom,
+            hold_enabled: false,
+            preview_len: 1,
+            timing: None,
+            track_finesse: false,
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,280 @@
+//! Gym-style environments
+//!
+//! `Environment` is the interface of the game for the agents, `TetrisEnv` implements it
+//! with `DQNState` observations and `DQNAction` placements. `VecEnv` steps several
+//! independent games in lockstep, optionally on the worker threads living as long as it,
+//! and starts a new game as soon as one ends, so every step gives a batch of transitions.
+//!
+//! # Example
+//!
+//! ```
+//! use tetris::env::{Environment, VecEnv};
+//! use tetris::train::TetrisEnv;
+//! let envs = (0..4).map(|k| TetrisEnv::new(Some(k))).collect();
+//! let mut vec_env = VecEnv::new(envs, 2);
+//! let actions = vec_env.valid_actions().iter().map(|actions| actions[0]).collect();
+//! let steps = vec_env.step(actions);
+//! assert_eq!(steps.len(), 4);
+//! assert!(steps.iter().all(|s| !s.done));
+//! ```
+
+use std::mem;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use rand::SeedableRng;
+use rand_xoshiro::Xoshiro512StarStar;
+use crate::agent::{DQNAction, DQNState};
+use crate::model::ClearInfo;
+use crate::train::TetrisEnv;
+
+/// The result of `Environment::step`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step<O, I> {
+    pub observation: O,
+    pub reward: f32,
+    pub done: bool,
+    pub info: I,
+}
+
+pub trait Environment {
+    type Observation;
+    type Action;
+    /// The diagnostics of the step, not meant for the agent
+    type Info;
+
+    /// Start the new episode
+    fn reset(&mut self) -> Self::Observation;
+
+    /// The action must be one of `valid_actions`
+    fn step(&mut self, action: Self::Action) -> Step<Self::Observation, Self::Info>;
+
+    /// No actions when the episode is over
+    fn valid_actions(&self) -> Vec<Self::Action>;
+
+    /// Reseed the random generator, the next episode after `reset` depends on the seed only
+    fn seed(&mut self, seed: u64);
+}
+
+/// - `score`, `lines`: the totals of the game so far
+/// - `clear`: the lines burnt by the step
+#[derive(Debug, Clone, PartialEq)]
+pub struct TetrisInfo {
+    pub score: u32,
+    pub lines: u32,
+    pub clear: ClearInfo,
+}
+
+impl Environment for TetrisEnv {
+    type Observation = DQNState;
+    type Action = DQNAction;
+    type Info = TetrisInfo;
+
+    fn reset(&mut self) -> DQNState {
+        TetrisEnv::reset(self)
+    }
+
+    fn step(&mut self, action: DQNAction) -> Step<DQNState, TetrisInfo> {
+        let (observation, reward, done) = TetrisEnv::step(self, action);
+        let info = TetrisInfo { score: self.gs.score, lines: self.gs.lines, clear: self.clear };
+        Step { observation, reward, done, info }
+    }
+
+    fn valid_actions(&self) -> Vec<DQNAction> {
+        if self.gs.game_over {
+            vec![]
+        } else {
+            self.get_valid_actions()
+        }
+    }
+
+    fn seed(&mut self, seed: u64) {
+        self.gs.rng = Xoshiro512StarStar::seed_from_u64(seed);
+    }
+}
+
+/// `observations` - the current observation of every game
+/// `valid_actions` - the valid actions of every game, found by the worker stepping it
+/// `workers` - the games are split between them, none steps the games in place
+pub struct VecEnv<E: Environment> {
+    pub envs: Vec<E>,
+    pub observations: Vec<E::Observation>,
+    valid_actions: Vec<Vec<E::Action>>,
+    workers: Vec<Worker<E>>,
+}
+
+impl<E> VecEnv<E>
+where
+    E: Environment + Send + 'static,
+    E::Observation: Clone + Send + 'static,
+    E::Action: Send + 'static,
+    E::Info: Send + 'static,
+{
+    /// Reset all the games and start the threads, 1 steps the games in place
+    pub fn new(mut envs: Vec<E>, threads: usize) -> VecEnv<E> {
+        let observations = envs.iter_mut().map(|env| env.reset()).collect();
+        let valid_actions = envs.iter().map(|env| env.valid_actions()).collect();
+        let workers = if threads > 1 && envs.len() > 1 {
+            (0..threads.min(envs.len())).map(|_| Worker::spawn()).collect()
+        } else {
+            vec![]
+        };
+        VecEnv { envs, observations, valid_actions, workers }
+    }
+
+    pub fn len(&self) -> usize {
+        self.envs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.envs.is_empty()
+    }
+
+    /// The number of the threads stepping the games
+    pub fn threads(&self) -> usize {
+        self.workers.len().max(1)
+    }
+
+    /// The game `k` gets the seed `seed + k`, the games are reset
+    pub fn seed(&mut self, seed: u64) -> Vec<E::Observation> {
+        for (k, env) in self.envs.iter_mut().enumerate() {
+            env.seed(seed + k as u64);
+        }
+        self.reset()
+    }
+
+    pub fn reset(&mut self) -> Vec<E::Observation> {
+        self.observations = self.envs.iter_mut().map(|env| env.reset()).collect();
+        self.valid_actions = self.envs.iter().map(|env| env.valid_actions()).collect();
+        self.observations.clone()
+    }
+
+    /// The valid actions of every game in the current state, they are found
+    /// after the step on the thread of the game, see `step`
+    pub fn valid_actions(&self) -> &[Vec<E::Action>] {
+        &self.valid_actions
+    }
+
+    /// Apply one action to every game. The finished games are reset, their steps
+    /// have the last observation of the episode with `done`, and `observations`
+    /// and `valid_actions` are the ones of the next episode.
+    ///
+    /// # Panics
+    ///
+    /// If a game panics. The games are moved to the workers for the step, so
+    /// the games of the panicked worker are lost, and `envs` is left empty.
+    pub fn step(&mut self, actions: Vec<E::Action>) -> Vec<Step<E::Observation, E::Info>> {
+        assert_eq!(actions.len(), self.envs.len(), "one action per game is expected");
+        let steps = if self.workers.is_empty() {
+            self.envs.iter_mut().zip(actions).map(|(env, action)| step_and_reset(env, action)).collect()
+        } else {
+            self.step_parallel(actions)
+        };
+        self.observations.clear();
+        self.valid_actions.clear();
+        steps.into_iter()
+            .map(|(step, next, valid_actions)| {
+                self.observations.push(next);
+                self.valid_actions.push(valid_actions);
+                step
+            })
+            .collect()
+    }
+
+    /// The games are moved to the workers and back in the same order
+    fn step_parallel(&mut self, actions: Vec<E::Action>) -> Vec<StepAndNext<E>> {
+        let chunk_size = self.envs.len().div_ceil(self.workers.len());
+        let mut envs = mem::take(&mut self.envs).into_iter();
+        let mut actions = actions.into_iter();
+        let mut busy = 0;
+        for worker in &self.workers {
+            let chunk: Chunk<E> = envs.by_ref().zip(actions.by_ref()).take(chunk_size).collect();
+            if chunk.is_empty() {
+                break;
+            }
+            worker.send(chunk);
+            busy += 1;
+        }
+        let mut steps = Vec::with_capacity(busy * chunk_size);
+        for worker in &self.workers[..busy] {
+            for (env, step) in worker.receive() {
+                self.envs.push(env);
+                steps.push(step);
+            }
+        }
+        steps
+    }
+}
+
+/// The thread stepping the chunks of the games sent to it, it ends when the worker is dropped
+struct Worker<E: Environment> {
+    chunks: Option<Sender<Chunk<E>>>,
+    results: Receiver<Vec<(E, StepAndNext<E>)>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl<E> Worker<E>
+where
+    E: Environment + Send + 'static,
+    E::Observation: Clone + Send + 'static,
+    E::Action: Send + 'static,
+    E::Info: Send + 'static,
+{
+    fn spawn() -> Worker<E> {
+        let (chunks, chunks_rx) = mpsc::channel::<Chunk<E>>();
+        let (results_tx, results) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            for chunk in chunks_rx {
+                let stepped: Vec<_> = chunk.into_iter()
+                    .map(|(mut env, action)| {
+                        let step = step_and_reset(&mut env, action);
+                        (env, step)
+                    })
+                    .collect();
+                if results_tx.send(stepped).is_err() {
+                    break;
+                }
+            }
+        });
+        Worker { chunks: Some(chunks), results, handle: Some(handle) }
+    }
+
+    fn send(&self, chunk: Chunk<E>) {
+        let chunks = self.chunks.as_ref().expect("the worker is running");
+        chunks.send(chunk).expect("the environment thread panicked");
+    }
+
+    fn receive(&self) -> Vec<(E, StepAndNext<E>)> {
+        self.results.recv().expect("the environment thread panicked")
+    }
+}
+
+impl<E: Environment> Drop for Worker<E> {
+    fn drop(&mut self) {
+        // the closed channel ends the loop of the thread
+        self.chunks = None;
+        if let Some(handle) = self.handle.take() {
+            // the panic of the thread has been reported by `step` already
+            let _ = handle.join();
+        }
+    }
+}
+
+/// The games with their actions sent to a worker
+type Chunk<E> = Vec<(E, <E as Environment>::Action)>;
+
+/// The step, the observation to continue from and the valid actions there
+type StepAndNext<E> = (
+    Step<<E as Environment>::Observation, <E as Environment>::Info>,
+    <E as Environment>::Observation,
+    Vec<<E as Environment>::Action>,
+);
+
+fn step_and_reset<E: Environment>(env: &mut E, action: E::Action) -> StepAndNext<E>
+where
+    E::Observation: Clone,
+{
+    let step = env.step(action);
+    let next = if step.done { env.reset() } else { step.observation.clone() };
+    let valid_actions = env.valid_actions();
+    (step, next, valid_actions)
+}
@@ -0,0 +1,328 @@
+//! Board features for the value functions
+//!
+//! Every feature implements `FeatureExtractor`, the features are composed into one vector
+//! with `Vec<Box<dyn FeatureExtractor>>`. The heights count from the floor, the cell in the
+//! bottom row has the height 1, the walls and the floor are occupied. `dellacherie()` is
+//! the set of Pierre Dellacherie's controller, see C. Fahey, "Tetris AI" and Thiery &
+//! Scherrer, "Building Controllers for Tetris".
+//!
+//! # Example
+//!
+//! ```
+//! use tetris::features::{Board, FeatureExtractor, dellacherie};
+//! use tetris::model::Point;
+//! use tetris::field;
+//! let cells = [Point(0, 3), Point(1, 3), Point(2, 3), Point(3, 3)];
+//! let field = field!("
+//!     . . . .
+//!     . . . .
+//!     . . . .
+//!     * * . .
+//! ");
+//! let board = Board::after_placement(&field, &cells);
+//! let features = dellacherie();
+//! assert_eq!(features.names()[0], "landing_height");
+//! assert_eq!(features.extract(&board)[0], 2.5);
+//! ```
+
+use std::borrow::Cow;
+use crate::model::{Field, Point};
+
+/// The field after the placement of the last piece and the burning of the lines
+/// - `field`: borrowed, if the board is the one of the game, see `Board::borrowed`
+/// - `landing`: the placement that produced the field, if known
+#[derive(Debug, Clone, PartialEq)]
+pub struct Board<'a> {
+    pub field: Cow<'a, Field>,
+    pub landing: Option<Landing>,
+}
+
+/// - `cells`: the cells of the piece on the field before the burning
+/// - `burnt_rows`: the rows burnt by the piece, on the field before the burning
+/// - `height`: the height of the field
+#[derive(Debug, Clone, PartialEq)]
+pub struct Landing {
+    pub cells: Vec<Point>,
+    pub burnt_rows: Vec<usize>,
+    pub height: usize,
+}
+
+impl<'a> Board<'a> {
+    /// The board without the last piece, the features of the placement are 0
+    pub fn new(field: Field) -> Board<'static> {
+        Board { field: Cow::Owned(field), landing: None }
+    }
+
+    /// The same as `new`, but without the copy of `field`
+    pub fn borrowed(field: &'a Field) -> Board<'a> {
+        Board { field: Cow::Borrowed(field), landing: None }
+    }
+
+    /// Lock `cells` into `field` as the cells of I and burn the full rows
+    pub fn after_placement(field: &Field, cells: &[Point]) -> Board<'static> {
+        let mut cells_after = field.cells.clone();
+        for p in cells {
+            cells_after[p.0 as usize][p.1 as usize] = 1;
+        }
+        let burnt_rows: Vec<usize> = (0..field.height)
+            .filter(|i| cells_after[*i].iter().all(|c| *c != 0))
+            .collect();
+        let mut kept: Vec<Vec<u8>> = cells_after.into_iter().enumerate()
+            .filter(|(i, _)| !burnt_rows.contains(i))
+            .map(|(_, row)| row)
+            .collect();
+        let mut rows = vec![vec![0; field.width]; burnt_rows.len()];
+        rows.append(&mut kept);
+        let landing = Landing { cells: cells.to_vec(), burnt_rows, height: field.height };
+        Board {
+            field: Cow::Owned(Field { cells: rows, height: field.height, width: field.width }),
+            landing: Some(landing),
+        }
+    }
+
+    pub fn is_occupied(&self, i: i32, j: i32) -> bool {
+        if i >= self.field.height as i32 || j < 0 || j >= self.field.width as i32 {
+            true
+        } else if i < 0 {
+            false
+        } else {
+            self.field.cells[i as usize][j as usize] != 0
+        }
+    }
+
+    /// The height of the highest block in every column, 0 for the empty ones
+    pub fn column_heights(&self) -> Vec<usize> {
+        let m = self.field.height;
+        (0..self.field.width)
+            .map(|j| (0..m).find(|i| self.field.cells[*i][j] != 0).map_or(0, |i| m - i))
+            .collect()
+    }
+
+    /// The empty cells below the highest block of their column as `Point(row, col)`
+    pub fn holes(&self) -> Vec<Point> {
+        let m = self.field.height;
+        let mut holes = vec![];
+        for (j, h) in self.column_heights().into_iter().enumerate() {
+            for i in m - h..m {
+                if self.field.cells[i][j] == 0 {
+                    holes.push(Point(i as i32, j as i32));
+                }
+            }
+        }
+        holes
+    }
+
+    /// The depths of the well cells of every column from the top: the well cell is empty, above
+    /// the highest block of its column and has both neighbours occupied, the depth counts
+    /// the well cells in a row including it
+    fn well_depths(&self) -> Vec<Vec<usize>> {
+        let m = self.field.height;
+        self.column_heights().into_iter().enumerate()
+            .map(|(j, h)| {
+                let mut depths = vec![];
+                let mut depth = 0;
+                for i in 0..(m - h) as i32 {
+                    if self.is_occupied(i, j as i32 - 1) && self.is_occupied(i, j as i32 + 1) {
+                        depth += 1;
+                        depths.push(depth);
+                    } else {
+                        depth = 0;
+                    }
+                }
+                depths
+            })
+            .collect()
+    }
+}
+
+/// A group of the features with the names, they go to the vector in the same order
+pub trait FeatureExtractor: Send + Sync {
+    fn names(&self) -> Vec<&'static str>;
+
+    fn extract_into(&self, board: &Board, features: &mut Vec<f32>);
+
+    fn extract(&self, board: &Board) -> Vec<f32> {
+        let mut features = vec![];
+        self.extract_into(board, &mut features);
+        features
+    }
+}
+
+impl FeatureExtractor for Vec<Box<dyn FeatureExtractor>> {
+    fn names(&self) -> Vec<&'static str> {
+        self.iter().flat_map(|f| f.names()).collect()
+    }
+
+    fn extract_into(&self, board: &Board, features: &mut Vec<f32>) {
+        for f in self {
+            f.extract_into(board, features);
+        }
+    }
+}
+
+/// Implement `FeatureExtractor` for the unit struct computing the single feature
+macro_rules! feature {
+    ($name:ident, $label:expr, |$board:ident| $body:expr) => {
+        impl FeatureExtractor for $name {
+            fn names(&self) -> Vec<&'static str> {
+                vec![$label]
+            }
+
+            fn extract_into(&self, $board: &Board, features: &mut Vec<f32>) {
+                features.push($body as f32);
+            }
+        }
+    };
+}
+
+/// The height of the middle of the last piece before the burning
+pub struct LandingHeight;
+feature!(LandingHeight, "landing_height", |board| match &board.landing {
+    Some(landing) => {
+        let lowest = landing.cells.iter().map(|p| p.0).max().unwrap_or(0);
+        let highest = landing.cells.iter().map(|p| p.0).min().unwrap_or(0);
+        (2 * landing.height as i32 - lowest - highest) as f32 / 2.0
+    }
+    None => 0.0,
+});
+
+/// The number of the burnt rows times the number of the cells of the last piece in them
+pub struct ErodedPieceCells;
+feature!(ErodedPieceCells, "eroded_piece_cells", |board| match &board.landing {
+    Some(landing) => {
+        let eroded = landing.cells.iter().filter(|p| landing.burnt_rows.contains(&(p.0 as usize))).count();
+        landing.burnt_rows.len() * eroded
+    }
+    None => 0,
+});
+
+/// The number of the lines burnt by the last piece
+pub struct RowsBurnt;
+feature!(RowsBurnt, "rows_burnt", |board| board.landing.as_ref().map_or(0, |l| l.burnt_rows.len()));
+
+/// The changes between the occupied and empty cells along the rows up to the highest block,
+/// the walls are occupied
+pub struct RowTransitions;
+feature!(RowTransitions, "row_transitions", |board| {
+    let top = board.field.height - board.column_heights().into_iter().max().unwrap_or(0);
+    let mut transitions = 0;
+    for i in top as i32..board.field.height as i32 {
+        for j in 0..=board.field.width as i32 {
+            if board.is_occupied(i, j - 1) != board.is_occupied(i, j) {
+                transitions += 1;
+            }
+        }
+    }
+    transitions
+});
+
+/// The changes between the occupied and empty cells along the columns, the floor is occupied
+pub struct ColumnTransitions;
+feature!(ColumnTransitions, "column_transitions", |board| {
+    let mut transitions = 0;
+    for j in 0..board.field.width as i32 {
+        for i in 0..board.field.height as i32 {
+            if board.is_occupied(i, j) != board.is_occupied(i + 1, j) {
+                transitions += 1;
+            }
+        }
+    }
+    transitions
+});
+
+/// The number of the empty cells below the highest block of their column
+pub struct Holes;
+feature!(Holes, "holes", |board| board.holes().len());
+
+/// The number of the occupied cells above the holes, summed over the holes
+pub struct HoleDepth;
+feature!(HoleDepth, "hole_depth", |board| {
+    board.holes().iter()
+        .map(|p| (0..p.0).filter(|i| board.is_occupied(*i, p.1)).count())
+        .sum::<usize>()
+});
+
+/// The number of the rows with at least one hole
+pub struct RowsWithHoles;
+feature!(RowsWithHoles, "rows_with_holes", |board| {
+    let mut rows: Vec<i32> = board.holes().iter().map(|p| p.0).collect();
+    rows.sort();
+    rows.dedup();
+    rows.len()
+});
+
+/// The sum of `1 + 2 + ... + depth` over the wells, i.e. the sum of the depths of the well cells
+pub struct CumulativeWells;
+feature!(CumulativeWells, "cumulative_wells", |board| {
+    board.well_depths().into_iter().flatten().sum::<usize>()
+});
+
+/// The number of the wells at least `min_depth` deep
+pub struct WellCount {
+    pub min_depth: usize,
+}
+
+impl FeatureExtractor for WellCount {
+    fn names(&self) -> Vec<&'static str> {
+        vec!["well_count"]
+    }
+
+    fn extract_into(&self, board: &Board, features: &mut Vec<f32>) {
+        let wells = board.well_depths().into_iter()
+            .flat_map(|depths| {
+                // the well ends, where its depth doesn't grow in the next cell
+                (0..depths.len())
+                    .filter(|k| depths.get(k + 1) != Some(&(depths[*k] + 1)))
+                    .map(|k| depths[k])
+                    .collect::<Vec<_>>()
+            })
+            .filter(|depth| *depth >= self.min_depth)
+            .count();
+        features.push(wells as f32);
+    }
+}
+
+/// The height of the highest column
+pub struct MaxHeight;
+feature!(MaxHeight, "max_height", |board| board.column_heights().into_iter().max().unwrap_or(0));
+
+/// The sum of the column heights
+pub struct AggregateHeight;
+feature!(AggregateHeight, "aggregate_height", |board| board.column_heights().into_iter().sum::<usize>());
+
+/// The sum of the height differences of the adjacent columns
+pub struct Bumpiness;
+feature!(Bumpiness, "bumpiness", |board| {
+    board.column_heights().windows(2).map(|hs| (hs[0] as i32 - hs[1] as i32).abs()).sum::<i32>()
+});
+
+/// The six features of Dellacherie's controller
+pub fn dellacherie() -> Vec<Box<dyn FeatureExtractor>> {
+    vec![
+        Box::new(LandingHeight),
+        Box::new(ErodedPieceCells),
+        Box::new(RowTransitions),
+        Box::new(ColumnTransitions),
+        Box::new(Holes),
+        Box::new(CumulativeWells),
+    ]
+}
+
+/// Dellacherie's features with the hole and well details of Thiery & Scherrer
+/// and the height features of `DQNState`
+pub fn all() -> Vec<Box<dyn FeatureExtractor>> {
+    let mut features = dellacherie();
+    features.push(Box::new(HoleDepth));
+    features.push(Box::new(RowsWithHoles));
+    features.push(Box::new(WellCount { min_depth: 1 }));
+    features.push(Box::new(RowsBurnt));
+    features.push(Box::new(MaxHeight));
+    features.push(Box::new(AggregateHeight));
+    features.push(Box::new(Bumpiness));
+    features
+}
+
+/// The single feature of `all()` with the name
+pub fn by_name(name: &str) -> Option<Box<dyn FeatureExtractor>> {
+    all().into_iter().find(|f| f.names() == [name])
+}
@@ -0,0 +1,70 @@
+//! Finesse: the minimal inputs to put the piece to its place
+//!
+//! The inputs are `Left`, `Right`, `DasLeft`, `DasRight`, `RotateCW` and `RotateCCW`,
+//! the soft drops are free as the gravity does the same, and every path ends with
+//! `HardDrop`. The shift to the wall is one input (DAS), whatever the distance.
+//!
+//! # Example
+//!
+//! ```
+//! use tetris::model::{GameState, Point, Action};
+//! use tetris::agent::DQNAction;
+//! use tetris::finesse::finesse_path;
+//! let mut gs = GameState::initial(22, 10, Default::default(), Some(1));
+//! gs.spawn_shape(1); // O
+//! let target = DQNAction { base: Point(20, 7), rotation: 0, hold: false, last_kick: None };
+//! let path = finesse_path(&gs, &target).unwrap();
+//! assert_eq!(path, vec![Action::DasRight, Action::Left, Action::HardDrop]);
+//! ```
+
+use crate::agent::DQNAction;
+use crate::bitboard::BitField;
+use crate::model::{GameState, Field, Point, Action, detect_tspin};
+use crate::movegen::search;
+
+/// The minimal inputs to lock the current shape of `gs` from its current position at
+/// `target` with the same T-spin, the base of the target may be above the landing
+/// position, `None` if the target is unreachable
+pub fn finesse_path(gs: &GameState, target: &DQNAction) -> Option<Vec<Action>> {
+    let mut path = vec![];
+    let mut gs = gs.clone();
+    if target.hold {
+        gs.step(Action::Hold);
+        path.push(Action::Hold);
+    }
+    if gs.game_over {
+        return None;
+    }
+    let mut i = target.base.0;
+    let mut cells = gs.try_current_shape(&target.base, target.rotation)?;
+    while let Some(next) = gs.try_current_shape(&Point(i + 1, target.base.1), target.rotation) {
+        cells = next;
+        i += 1;
+    }
+    cells.sort_by_key(|p| (p.0, p.1));
+    // the drop cancels the spin
+    let last_kick = if i == target.base.0 { target.last_kick } else { None };
+    let tspin = detect_tspin(&gs.field, gs.curr_shape_idx, &Point(i, target.base.1), target.rotation, last_kick);
+    let placement = search(&BitField::from(&gs.field), gs.curr_shape_idx, gs.base, gs.rotation, gs.last_kick, true)
+        .into_iter()
+        .find(|p| p.cells == cells && p.tspin == tspin)?;
+    path.extend(placement.path);
+    Some(path)
+}
+
+/// The number of the inputs counted by finesse, the drops and the hold are free
+pub fn count_inputs(path: &[Action]) -> u32 {
+    path.iter()
+        .filter(|a| !matches!(a, Action::Down | Action::HardDrop | Action::Tick | Action::Hold))
+        .count() as u32
+}
+
+/// The minimal number of the inputs to lock the shape spawned at `base` into `cells`
+pub fn min_inputs(field: &Field, shape_idx: usize, base: Point, cells: &[Point]) -> Option<u32> {
+    let mut cells = cells.to_vec();
+    cells.sort_by_key(|p| (p.0, p.1));
+    search(&BitField::from(field), shape_idx, base, 0, None, true)
+        .into_iter()
+        .find(|p| p.cells == cells)
+        .map(|p| count_inputs(&p.path))
+}
@@ -0,0 +1,539 @@
+//! Fumen v115 import and export
+//!
+//! Fumen is the de facto format to share the positions in the Tetris community,
+//! e.g. `v115@vhAAgH` is a single page with the empty field. The data consists
+//! of pages, each page has the field (23 rows of 10 cells and a garbage row below),
+//! an optional piece with the flags and an optional comment. The fields are stored
+//! as the difference with the field of the previous page after its piece is locked.
+//!
+//! # Example
+//!
+//! ```
+//! use tetris::fumen;
+//! let pages = fumen::decode("v115@vhAAgH", 22).unwrap();
+//! assert_eq!(pages.len(), 1);
+//! assert!(pages[0].field.is_empty());
+//! assert_eq!(fumen::encode(&pages).unwrap(), "v115@vhAAgH");
+//! ```
+
+use std::collections::VecDeque;
+use failure::{bail, format_err, Fallible};
+use crate::agent::DQNAction;
+use crate::model::{Field, GameState, Point, Action, rotated, srs_center, base_from_srs_center};
+
+const ENCODE_TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const COMMENT_TABLE: &[u8; 95] = b" !\"#$%&'()*+,-./0123456789:;<=>?@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_`abcdefghijklmnopqrstuvwxyz{|}~";
+const PREFIX: &str = "v115@";
+
+/// The playfield height, the garbage row is below it
+pub const FIELD_TOP: usize = 23;
+pub const FIELD_WIDTH: usize = 10;
+const FIELD_BLOCKS: usize = (FIELD_TOP + 1) * FIELD_WIDTH;
+
+/// Fumen piece codes by our cell values: empty, I, O, T, S, Z, J, L, garbage
+const TO_FUMEN: [u8; 9] = [0, 1, 3, 5, 7, 4, 6, 2, 8];
+/// Our cell values by fumen piece codes: empty, I, L, O, Z, T, J, S, gray
+const FROM_FUMEN: [u8; 9] = [0, 1, 7, 2, 5, 3, 6, 4, 8];
+
+/// The piece of the page in the coordinates of `Page::field`,
+/// the same as `GameState::base` and `GameState::rotation`
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct Operation {
+    pub shape_idx: usize,
+    pub base: Point,
+    pub rotation: i8,
+}
+
+/// - `field`: the field of the page, bottom aligned with the fumen playfield, so
+///   its height may differ from 23, but the width must be 10
+/// - `garbage`: the garbage row below the field
+/// - `lock`: the piece is locked and the lines are burnt for the next page
+/// - `rise`: the garbage row is pushed into the field for the next page
+/// - `mirror`: the field is mirrored horizontally for the next page
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Page {
+    pub field: Field,
+    pub garbage: Vec<u8>,
+    pub operation: Option<Operation>,
+    pub comment: String,
+    pub lock: bool,
+    pub rise: bool,
+    pub mirror: bool,
+}
+
+impl Page {
+    /// The page with the empty `garbage`, the `lock` flag and no comment
+    pub fn new(field: Field, operation: Option<Operation>) -> Page {
+        Page {
+            field,
+            garbage: vec![0; FIELD_WIDTH],
+            operation,
+            comment: String::new(),
+            lock: true,
+            rise: false,
+            mirror: false,
+        }
+    }
+
+    /// The page that follows this one, without the piece: the piece is locked
+    /// and the lines are burnt, then the garbage rises and the field is mirrored
+    /// according to the flags
+    pub fn next(&self) -> Fallible<Page> {
+        let field = next_fumen_field(&to_fumen_field(self)?, self);
+        let mut page = Page::new(from_fumen_field(&field, self.field.height)?, None);
+        page.garbage = decode_garbage(&field);
+        Ok(page)
+    }
+}
+
+pub fn encode(pages: &[Page]) -> Fallible<String> {
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut prev_field = [0u8; FIELD_BLOCKS];
+    let mut prev_comment = String::new();
+    let mut last_repeat_idx: Option<usize> = None;
+    for (page_idx, page) in pages.iter().enumerate() {
+        let field = to_fumen_field(page)?;
+        // the field, as the runs of the same differences with the previous one
+        let diffs: Vec<usize> = (0..FIELD_BLOCKS)
+            .map(|k| (field[k] as usize + 8) - prev_field[k] as usize)
+            .collect();
+        if diffs.iter().all(|d| *d == 8) {
+            // the unchanged fields are counted with a single symbol
+            match last_repeat_idx {
+                Some(idx) if (buffer[idx] as usize) < ENCODE_TABLE.len() - 1 => {
+                    buffer[idx] += 1;
+                }
+                _ => {
+                    push(&mut buffer, 8 * FIELD_BLOCKS + FIELD_BLOCKS - 1, 2);
+                    push(&mut buffer, 0, 1);
+                    last_repeat_idx = Some(buffer.len() - 1);
+                }
+            }
+        } else {
+            let mut k = 0;
+            while k < FIELD_BLOCKS {
+                let mut count = 1;
+                while k + count < FIELD_BLOCKS && diffs[k + count] == diffs[k] {
+                    count += 1;
+                }
+                push(&mut buffer, diffs[k] * FIELD_BLOCKS + count - 1, 2);
+                k += count;
+            }
+            last_repeat_idx = None;
+        }
+        // the piece and the flags
+        let has_comment = page.comment != prev_comment;
+        let (piece, rotation, coordinate) = match &page.operation {
+            Some(op) => encode_operation(op, page.field.height)?,
+            None => (0, 0, 0),
+        };
+        let mut value = if page.lock { 0 } else { 1 };
+        value = value * 2 + has_comment as usize;
+        value = value * 2 + (page_idx == 0) as usize; // guideline colors
+        value = value * 2 + page.mirror as usize;
+        value = value * 2 + page.rise as usize;
+        value = value * FIELD_BLOCKS + coordinate;
+        value = value * 4 + rotation;
+        value = value * 8 + piece;
+        push(&mut buffer, value, 3);
+        if has_comment {
+            let escaped = escape(&page.comment);
+            let chars = escaped.as_bytes();
+            let length = chars.len().min(4095);
+            push(&mut buffer, length, 2);
+            for chunk in chars[..length].chunks(4) {
+                let mut value = 0;
+                for (k, c) in chunk.iter().enumerate() {
+                    let idx = COMMENT_TABLE.iter().position(|t| t == c).unwrap_or(0);
+                    value += idx * 96usize.pow(k as u32);
+                }
+                push(&mut buffer, value, 5);
+            }
+            prev_comment = page.comment.clone();
+        }
+        prev_field = next_fumen_field(&field, page);
+    }
+    let data: String = buffer.iter().map(|v| ENCODE_TABLE[*v as usize] as char).collect();
+    // the viewers insert `?` to make the long data breakable
+    let mut result = String::from(PREFIX);
+    if data.len() <= 42 {
+        result.push_str(&data);
+    } else {
+        result.push_str(&data[..42]);
+        for chunk in data.as_bytes()[42..].chunks(47) {
+            result.push('?');
+            result.push_str(std::str::from_utf8(chunk)?);
+        }
+    }
+    Ok(result)
+}
+
+/// Decode the pages, the fields are of the given `height` (bottom aligned),
+/// and it is an error if there are blocks above
+pub fn decode(data: &str, height: usize) -> Fallible<Vec<Page>> {
+    let data = data.trim();
+    if !data.starts_with(PREFIX) {
+        bail!("Only {} fumen data are supported", PREFIX);
+    }
+    let mut values = VecDeque::with_capacity(data.len());
+    for c in data[PREFIX.len()..].bytes().filter(|c| *c != b'?') {
+        match ENCODE_TABLE.iter().position(|t| *t == c) {
+            Some(v) => values.push_back(v),
+            None => bail!("Unexpected symbol '{}' in the fumen data", c as char),
+        }
+    }
+    let mut pages = vec![];
+    let mut prev_field = [0u8; FIELD_BLOCKS];
+    let mut prev_comment = String::new();
+    let mut repeat_count: usize = 0;
+    while !values.is_empty() {
+        let mut field = prev_field;
+        if repeat_count == 0 {
+            let mut k = 0;
+            while k < FIELD_BLOCKS {
+                let block = poll(&mut values, 2)?;
+                let diff = block / FIELD_BLOCKS;
+                let count = block % FIELD_BLOCKS + 1;
+                if diff == 8 && count == FIELD_BLOCKS {
+                    repeat_count = poll(&mut values, 1)? + 1;
+                }
+                if k + count > FIELD_BLOCKS || diff > 16 {
+                    bail!("Corrupted fumen field");
+                }
+                for cell in &mut field[k..k + count] {
+                    let value = *cell as usize + diff;
+                    if !(8..=16).contains(&value) {
+                        bail!("Corrupted fumen field");
+                    }
+                    *cell = (value - 8) as u8;
+                }
+                k += count;
+            }
+        }
+        repeat_count = repeat_count.saturating_sub(1);
+
+        let mut value = poll(&mut values, 3)?;
+        let piece = value % 8;
+        value /= 8;
+        let rotation = value % 4;
+        value /= 4;
+        let coordinate = value % FIELD_BLOCKS;
+        value /= FIELD_BLOCKS;
+        let rise = value % 2 == 1;
+        value /= 2;
+        let mirror = value % 2 == 1;
+        value /= 2;
+        value /= 2; // guideline colors are always on
+        let has_comment = value % 2 == 1;
+        value /= 2;
+        let lock = value % 2 == 0;
+
+        let comment = if has_comment {
+            let length = poll(&mut values, 2)?;
+            let mut escaped = String::with_capacity(length + 3);
+            for _ in 0..length.div_ceil(4) {
+                let mut value = poll(&mut values, 5)?;
+                for _ in 0..4 {
+                    let c = COMMENT_TABLE.get(value % 96)
+                        .ok_or_else(|| format_err!("Corrupted fumen comment"))?;
+                    escaped.push(*c as char);
+                    value /= 96;
+                }
+            }
+            escaped.truncate(length);
+            unescape(&escaped)
+        } else {
+            prev_comment.clone()
+        };
+        prev_comment = comment.clone();
+
+        let operation = if (1..=7).contains(&piece) {
+            Some(decode_operation(piece, rotation, coordinate, height)?)
+        } else {
+            None
+        };
+        let page = Page {
+            field: from_fumen_field(&field, height)?,
+            garbage: decode_garbage(&field),
+            operation,
+            comment,
+            lock,
+            rise,
+            mirror,
+        };
+        prev_field = next_fumen_field(&field, &page);
+        pages.push(page);
+    }
+    Ok(pages)
+}
+
+/// The single page with the field and the current piece of `gs`
+pub fn encode_game_state(gs: &GameState) -> Fallible<String> {
+    let operation = Operation {
+        shape_idx: gs.curr_shape_idx,
+        base: gs.base,
+        rotation: gs.rotation,
+    };
+    encode(&[Page::new(gs.field.clone(), Some(operation))])
+}
+
+/// Create the game with the field of the first page, the piece of the page
+/// (if any) becomes the current one
+pub fn decode_game_state(data: &str, gs: &GameState) -> Fallible<GameState> {
+    let pages = decode(data, gs.field.height)?;
+    let page = pages.first().ok_or_else(|| format_err!("No pages in the fumen data"))?;
+    let mut gs = gs.clone();
+    gs.field = page.field.clone();
+    gs.game_over = false;
+    if let Some(op) = &page.operation {
+        gs.curr_shape_idx = op.shape_idx;
+        gs.base = op.base;
+        gs.rotation = op.rotation;
+        let cells = gs.try_current_shape(&op.base, op.rotation)
+            .ok_or_else(|| format_err!("The piece of the page overlaps the field"))?;
+        gs.set_curr_cells(&cells);
+    } else if let Some(cells) = gs.try_current_shape(&gs.base, gs.rotation) {
+        gs.set_curr_cells(&cells);
+    } else {
+        gs.game_over = true;
+    }
+    Ok(gs)
+}
+
+/// Replay the placements (as `TetrisEnv::step` does) from `gs`, every page
+/// shows the field before the placement and the locked piece
+pub fn replay_pages(gs: &GameState, actions: &[DQNAction]) -> Vec<Page> {
+    let mut gs = gs.clone();
+    let mut pages = Vec::with_capacity(actions.len());
+    for action in actions {
+        if gs.game_over {
+            break;
+        }
+        if action.hold {
+            gs.step(Action::Hold);
+        }
+        if let Some(cells) = gs.try_current_shape(&action.base, action.rotation) {
+            gs.base = action.base;
+            gs.rotation = action.rotation;
+            gs.set_curr_cells(&cells);
+            gs.last_kick = action.last_kick;
+        }
+        let (i_new, _) = gs.drop_current_shape();
+        let operation = Operation {
+            shape_idx: gs.curr_shape_idx,
+            base: Point(i_new - 1, gs.base.1),
+            rotation: gs.rotation,
+        };
+        pages.push(Page::new(gs.field.clone(), Some(operation)));
+        gs.step(Action::HardDrop);
+    }
+    pages
+}
+
+fn push(buffer: &mut Vec<u8>, mut value: usize, digits: usize) {
+    for _ in 0..digits {
+        buffer.push((value % 64) as u8);
+        value /= 64;
+    }
+}
+
+fn poll(values: &mut VecDeque<usize>, digits: usize) -> Fallible<usize> {
+    let mut value = 0;
+    for k in 0..digits {
+        let v = values.pop_front().ok_or_else(|| format_err!("Unexpected end of the fumen data"))?;
+        value += v * 64usize.pow(k as u32);
+    }
+    Ok(value)
+}
+
+/// The fumen field is 24 rows of 10 cells from the top, the last row is garbage
+fn to_fumen_field(page: &Page) -> Fallible<[u8; FIELD_BLOCKS]> {
+    let field = &page.field;
+    if field.width != FIELD_WIDTH || page.garbage.len() != FIELD_WIDTH {
+        bail!("Fumen supports the fields of width {} only", FIELD_WIDTH);
+    }
+    let mut result = [0u8; FIELD_BLOCKS];
+    for i in 0..field.height {
+        let r = FIELD_TOP as i32 - field.height as i32 + i as i32;
+        for j in 0..field.width {
+            let cell = field.cells[i][j];
+            if cell == 0 {
+                continue;
+            }
+            if r < 0 {
+                bail!("Fumen supports the fields of height {} only", FIELD_TOP);
+            }
+            result[r as usize * FIELD_WIDTH + j] = TO_FUMEN[cell as usize];
+        }
+    }
+    for j in 0..FIELD_WIDTH {
+        result[FIELD_TOP * FIELD_WIDTH + j] = TO_FUMEN[page.garbage[j] as usize];
+    }
+    Ok(result)
+}
+
+fn from_fumen_field(src: &[u8; FIELD_BLOCKS], height: usize) -> Fallible<Field> {
+    let mut cells = vec![vec![0; FIELD_WIDTH]; height];
+    for r in 0..FIELD_TOP {
+        let i = r as i32 + height as i32 - FIELD_TOP as i32;
+        for j in 0..FIELD_WIDTH {
+            let cell = src[r * FIELD_WIDTH + j];
+            if cell == 0 {
+                continue;
+            }
+            if i < 0 {
+                bail!("The fumen field doesn't fit the height {}", height);
+            }
+            cells[i as usize][j] = FROM_FUMEN[cell as usize];
+        }
+    }
+    Ok(Field { cells, height, width: FIELD_WIDTH })
+}
+
+fn decode_garbage(src: &[u8; FIELD_BLOCKS]) -> Vec<u8> {
+    src[FIELD_TOP * FIELD_WIDTH..].iter().map(|c| FROM_FUMEN[*c as usize]).collect()
+}
+
+/// The field of the next page: lock the piece, burn the lines, rise and mirror
+fn next_fumen_field(src: &[u8; FIELD_BLOCKS], page: &Page) -> [u8; FIELD_BLOCKS] {
+    let mut field = *src;
+    if !page.lock {
+        return field;
+    }
+    if let Some(op) = &page.operation {
+        let piece = TO_FUMEN[op.shape_idx + 1];
+        for p in rotated(op.shape_idx, op.rotation) {
+            let r = FIELD_TOP as i32 - page.field.height as i32 + op.base.0 + p.0;
+            let j = op.base.1 + p.1;
+            if 0 <= r && r < FIELD_TOP as i32 && 0 <= j && j < FIELD_WIDTH as i32 {
+                field[r as usize * FIELD_WIDTH + j as usize] = piece;
+            }
+        }
+    }
+    let mut rows: Vec<Vec<u8>> = field[..FIELD_TOP * FIELD_WIDTH]
+        .chunks(FIELD_WIDTH)
+        .filter(|row| row.contains(&0))
+        .map(|row| row.to_vec())
+        .collect();
+    while rows.len() < FIELD_TOP {
+        rows.insert(0, vec![0; FIELD_WIDTH]);
+    }
+    let mut garbage = field[FIELD_TOP * FIELD_WIDTH..].to_vec();
+    if page.rise {
+        rows.remove(0);
+        rows.push(garbage);
+        garbage = vec![0; FIELD_WIDTH];
+    }
+    if page.mirror {
+        for row in &mut rows {
+            row.reverse();
+        }
+    }
+    rows.push(garbage);
+    let mut result = [0u8; FIELD_BLOCKS];
+    for (k, c) in rows.iter().flatten().enumerate() {
+        result[k] = *c;
+    }
+    result
+}
+
+/// Fumen rotation codes: 0 - reverse, 1 - right, 2 - spawn, 3 - left
+fn fumen_rotation(rotation: usize) -> usize {
+    match rotation {
+        0 => 2,
+        2 => 0,
+        r => r,
+    }
+}
+
+/// The legacy fumen centers of I, O, S, Z differ from the SRS ones,
+/// the returned shift moves the legacy center to the SRS one
+fn legacy_shift(piece: usize, rotation: usize) -> (i32, i32) {
+    match (piece, rotation) {
+        (3, 3) => (1, -1), // O left
+        (3, 2) => (1, 0),  // O reverse
+        (3, 0) => (0, -1), // O spawn
+        (1, 2) => (1, 0),  // I reverse
+        (1, 3) => (0, -1), // I left
+        (7, 0) => (0, -1), // S spawn
+        (7, 1) => (-1, 0), // S right
+        (4, 0) => (0, -1), // Z spawn
+        (4, 3) => (1, 0),  // Z left
+        _ => (0, 0),
+    }
+}
+
+fn encode_operation(op: &Operation, height: usize) -> Fallible<(usize, usize, usize)> {
+    let piece = TO_FUMEN[op.shape_idx + 1] as usize;
+    let rotation = ((op.rotation % 4 + 4) % 4) as usize;
+    let (x, y) = srs_center(op.shape_idx, &op.base, op.rotation, height);
+    let (dx, dy) = legacy_shift(piece, rotation);
+    let (x, y) = (x - dx, y - dy);
+    if x < 0 || x >= FIELD_WIDTH as i32 || y < 0 || y >= FIELD_TOP as i32 {
+        bail!("The piece is out of the fumen field");
+    }
+    let coordinate = (FIELD_TOP as i32 - y - 1) as usize * FIELD_WIDTH + x as usize;
+    Ok((piece, fumen_rotation(rotation), coordinate))
+}
+
+fn decode_operation(piece: usize, rotation: usize, coordinate: usize, height: usize) -> Fallible<Operation> {
+    // fumen_rotation is the involution
+    let rotation = fumen_rotation(rotation);
+    let (dx, dy) = legacy_shift(piece, rotation);
+    let x = (coordinate % FIELD_WIDTH) as i32 + dx;
+    let y = FIELD_TOP as i32 - (coordinate / FIELD_WIDTH) as i32 - 1 + dy;
+    let shape_idx = FROM_FUMEN[piece] as usize - 1;
+    let base = base_from_srs_center(shape_idx, (x, y), rotation as i8, height);
+    Ok(Operation { shape_idx, base, rotation: rotation as i8 })
+}
+
+/// JavaScript `escape`, the fumen comments are escaped before encoding
+fn escape(src: &str) -> String {
+    let mut result = String::with_capacity(src.len());
+    for c in src.chars() {
+        if c.is_ascii_alphanumeric() || "@*_+-./".contains(c) {
+            result.push(c);
+        } else {
+            let mut buf = [0u16; 2];
+            for u in c.encode_utf16(&mut buf) {
+                if *u < 256 {
+                    result.push_str(&format!("%{:02X}", u));
+                } else {
+                    result.push_str(&format!("%u{:04X}", u));
+                }
+            }
+        }
+    }
+    result
+}
+
+/// JavaScript `unescape`, the malformed sequences are left as is
+fn unescape(src: &str) -> String {
+    let chars: Vec<char> = src.chars().collect();
+    let mut units: Vec<u16> = Vec::with_capacity(chars.len());
+    let hex = |s: &[char]| -> Option<u16> {
+        u16::from_str_radix(&s.iter().collect::<String>(), 16).ok()
+    };
+    let mut k = 0;
+    while k < chars.len() {
+        if chars[k] == '%' {
+            if k + 6 <= chars.len() && chars[k + 1] == 'u' {
+                if let Some(u) = hex(&chars[k + 2..k + 6]) {
+                    units.push(u);
+                    k += 6;
+                    continue;
+                }
+            }
+            if k + 3 <= chars.len() {
+                if let Some(u) = hex(&chars[k + 1..k + 3]) {
+                    units.push(u);
+                    k += 3;
+                    continue;
+                }
+            }
+        }
+        units.push(chars[k] as u16);
+        k += 1;
+    }
+    String::from_utf16_lossy(&units)
+}
@@ -1,8 +1,18 @@
 #![crate_type = "lib"]
 
 pub mod agent;
+pub mod bitboard;
+pub mod checkpoint;
 pub mod config;
+pub mod env;
+pub mod features;
+pub mod finesse;
+pub mod fumen;
 pub mod model;
+pub mod movegen;
+pub mod reward;
+pub mod snapshot;
+pub mod tbp;
 pub mod tetrimino;
 pub mod train;
 pub mod utils;
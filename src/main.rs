@@ -8,14 +8,15 @@ use termion::raw::IntoRawMode;
 use termion::async_stdin;
 use std::io::{Write, stdout};
 use std::{thread, io};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use core::default::Default;
 use tch::{nn, nn::ModuleT, nn::OptimizerConfig, Device, Tensor, Cuda};
 use tetris::model::{GameState, Action};
-use tetris::agent::{DQNAgent, DQNState};
-use tetris::train::run_training;
-use tetris::config::{Config, Scoring, Randomness};
+use tetris::agent::{DQNAgent, DQNState, new_agent};
+use tetris::train::{TetrisEnv, evaluate, run_training};
+use tetris::config::{Config, Scoring, Randomness, Timing};
 use std::str::FromStr;
+use std::path::PathBuf;
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
@@ -24,12 +25,30 @@ struct Opt {
     /// The mode how to run the program
     // short and long flags (-d, --debug) will be deduced from the field's name
     #[structopt(short = "m", long = "mode")]
-    mode: Mode
+    mode: Mode,
+    /// The directory to save the training checkpoints to
+    #[structopt(long = "checkpoint-dir", parse(from_os_str))]
+    checkpoint_dir: Option<PathBuf>,
+    /// Resume the training from the checkpoint in the directory, the new checkpoints go there too
+    #[structopt(long = "resume", parse(from_os_str))]
+    resume: Option<PathBuf>,
+    /// The seed of the agent and the environment
+    #[structopt(long = "seed")]
+    seed: Option<u64>,
+    /// The agent to evaluate: dqn, random, greedy or mcts
+    #[structopt(short = "a", long = "agent", default_value = "greedy")]
+    agent: String,
+    /// The directory with the saved agent to evaluate, e.g. the training checkpoint
+    #[structopt(long = "load", parse(from_os_str))]
+    load: Option<PathBuf>,
+    /// The number of the games to evaluate the agent on
+    #[structopt(long = "episodes", default_value = "10")]
+    episodes: usize,
 }
 
 #[derive(Debug)]
 enum Mode {
-    Run, Train, Mnist
+    Run, Train, Eval, Mnist
 }
 
 impl FromStr for Mode {
@@ -38,6 +57,7 @@ impl FromStr for Mode {
         match day {
             "run" => Ok(Mode::Run),
             "train" => Ok(Mode::Train),
+            "eval" => Ok(Mode::Eval),
             "mnist" => Ok(Mode::Mnist),
             _ => Err("Could not parse a day".into()),
         }
@@ -49,11 +69,28 @@ fn main() -> failure::Fallible<()> {
     let opt: Opt = Opt::from_args();
     match opt.mode {
         Mode::Run => run_interactive_game(),
-        Mode::Train => run_training(None),
+        Mode::Train => match &opt.resume {
+            Some(dir) => run_training(opt.seed, Some(dir), true),
+            None => run_training(opt.seed, opt.checkpoint_dir.as_ref().map(|dir| dir.as_path()), false),
+        },
+        Mode::Eval => run_evaluation(&opt),
         Mode::Mnist => run_training_mnist(),
     }
 }
 
+fn run_evaluation(opt: &Opt) -> failure::Fallible<()> {
+    let mut agent = new_agent(&opt.agent, opt.seed, opt.load.as_deref())?;
+    let mut env = TetrisEnv::new(opt.seed);
+    let scores = evaluate(&mut agent, &mut env, opt.episodes, 10000);
+    let avg = scores.iter().sum::<u32>() as f32 / scores.len().max(1) as f32;
+    println!(
+        "agent: {}, episodes: {}, score avg: {:.1}, min: {}, max: {}",
+        opt.agent, scores.len(), avg,
+        scores.iter().min().unwrap_or(&0), scores.iter().max().unwrap_or(&0),
+    );
+    Ok(())
+}
+
 fn run_interactive_game() -> failure::Fallible<()> {
     let mut stdout = stdout().into_raw_mode().unwrap();
     let mut stdin = async_stdin().keys();
@@ -64,10 +101,18 @@ fn run_interactive_game() -> failure::Fallible<()> {
     let config = Config {
         scoring: Scoring::BurnOnly,
         randomness: Randomness::ShuffledQueue,
+        hold_enabled: true,
+        preview_len: 3,
+        timing: Some(Timing::default()),
+        track_finesse: true,
+        ..Config::default()
     };
     let mut gs = GameState::initial(22, 10, config, Some(22));
-    let mut k = 0;
-    let k_delay = 80;
+    // the terminal doesn't report key releases, so the soft drop lasts
+    // for a while after the last Down key press (the key repeat keeps it on)
+    let soft_drop_duration = Duration::from_millis(100);
+    let mut soft_drop_until = Instant::now();
+    let mut last_advance = Instant::now();
     {
         println!("{}", gs.prettify_game_state(true, true, true));
         stdout.flush().unwrap();
@@ -77,12 +122,15 @@ fn run_interactive_game() -> failure::Fallible<()> {
             let key = c.unwrap().clone();
             let x = match &key {
                 Key::Ctrl('c') => { break; },
-                Key::Char(' ') => { gs.step(Action::HardDrop); k = 0; true },
+                Key::Char(' ') => { gs.step(Action::HardDrop); true },
                 Key::Left      => { gs.step(Action::Left); true }
                 Key::Right     => { gs.step(Action::Right); true }
-                Key::Down      => { gs.step(Action::Down); true }
+                Key::Char('a') => { gs.step(Action::DasLeft); true }
+                Key::Char('d') => { gs.step(Action::DasRight); true }
+                Key::Down      => { soft_drop_until = Instant::now() + soft_drop_duration; false }
                 Key::Up        => { gs.step(Action::RotateCW); true }
                 Key::End       => { gs.step(Action::RotateCCW); true }
+                Key::Char('c') => { gs.step(Action::Hold); true }
                 _              => false,
             };
             if x {
@@ -90,13 +138,14 @@ fn run_interactive_game() -> failure::Fallible<()> {
                 stdout.flush().unwrap();
             }
         }
-        if k >= k_delay {
-            let _ = gs.step(Action::Tick);
+        let now = Instant::now();
+        let dt = now.duration_since(last_advance).as_millis() as u32;
+        last_advance = now;
+        let before = (gs.base, gs.rotation, gs.curr_shape_idx);
+        let _ = gs.advance(dt, now < soft_drop_until);
+        if before != (gs.base, gs.rotation, gs.curr_shape_idx) {
             println!("{}", gs.prettify_game_state(true, true, true));
             stdout.flush().unwrap();
-            k = 0;
-        } else {
-            k += 1;
         }
         if gs.game_over { break; }
         thread::sleep(Duration::from_millis(10));
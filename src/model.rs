@@ -1,13 +1,20 @@
 use lazy_static;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::fmt;
+use std::mem;
+use std::str::FromStr;
 use rand_xoshiro::Xoshiro512StarStar;
 use rand::{SeedableRng, Rng};
 use rand::prelude::SliceRandom;
+use itertools::Itertools;
+use core::cmp;
 use crate::tetrimino::{TETRIMINOES, Style, Tetrimino};
-use crate::config::{Config, Scoring, Randomness};
+use crate::config::{Config, Scoring, Randomness, Spawn, TopOut};
+use crate::utils::Trim;
+use crate::finesse::min_inputs;
+use serde::{Serialize, Deserialize};
 
-#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy)]
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy, Serialize, Deserialize)]
 pub struct Point(pub i32, pub i32);
 
 /// In the loop `i` runs from `0` to `height-1`; `j` runs from `0` to `width-1`
@@ -29,19 +36,131 @@ pub struct Point(pub i32, pub i32);
 ///     for j in 0..field.width {
 ///         // ok to access field[i][j]
 ///         // field[i][j] == 0 corresponds to empty
-///         // field[i][j] == 1..7 corresponds to [I, O, L, J, T, S, Z], see TETRIMINOES
+///         // field[i][j] == 1..7 corresponds to [I, O, T, S, Z, J, L], see TETRIMINOES
+///         // field[i][j] == 8 corresponds to garbage
 ///         println!("{}", field.cells[i][j]);
 ///     }
 /// }
 /// ```
-#[derive(Debug, Eq, PartialEq, Clone)]
+/// The same field can be parsed from the text, see `Field::from_str`:
+/// ```rust
+/// use tetris::field;
+/// let field = field!(r#"
+///     . . . .
+///     . . . .
+///     . . . .
+///     . . . .
+///     . I I I
+/// "#);
+/// assert_eq!(field.cells[4], vec![0, 1, 1, 1]);
+/// ```
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
 pub struct Field {
     pub cells: Vec<Vec<u8>>,
     pub height: usize,
     pub width: usize,
 }
 
-#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+impl Field {
+    pub fn is_empty(&self) -> bool {
+        self.cells.iter().all(|row| row.iter().all(|c| *c == 0))
+    }
+}
+
+/// The occupancy of the cells, the shape checks (`is_valid`, `try_shape_idx`, `try_wall_kick`,
+/// `detect_tspin`) work on `Field` and on `bitboard::BitField` through it
+pub trait Occupancy {
+    fn height(&self) -> usize;
+
+    fn width(&self) -> usize;
+
+    /// `i` and `j` are inside the field
+    fn is_occupied(&self, i: usize, j: usize) -> bool;
+}
+
+impl Occupancy for Field {
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn is_occupied(&self, i: usize, j: usize) -> bool {
+        self.cells[i][j] != 0
+    }
+}
+
+/// The cell value of garbage, i.e. the blocks not belonging to any tetrimino
+pub const GARBAGE: u8 = 8;
+
+/// The symbols of the cells in the text representation of `Field`,
+/// the index is the cell value
+pub const CELL_SYMBOLS: [char; 9] = ['.', 'I', 'O', 'T', 'S', 'Z', 'J', 'L', '*'];
+
+pub fn cell_style(cell: u8) -> Style {
+    if cell == GARBAGE {
+        Style::Black
+    } else {
+        TETRIMINOES[cell as usize - 1].style
+    }
+}
+
+/// Build `Field` from the text, see `Field::from_str`
+/// ```rust
+/// use tetris::field;
+/// let field = field!("* . I");
+/// assert_eq!(field.cells, vec![vec![8, 0, 1]]);
+/// ```
+#[macro_export]
+macro_rules! field {
+    ($src:expr) => {
+        $src.parse::<$crate::model::Field>().unwrap()
+    };
+}
+
+impl FromStr for Field {
+    type Err = String;
+
+    /// The grammar is similar to `build_tetrimino`: the common indent and the
+    /// blank first and last lines are trimmed, whitespaces between cells are
+    /// ignored, `.` is an empty cell, `I`, `O`, `T`, `S`, `Z`, `J`, `L` are
+    /// the cells of the corresponding tetriminoes and `*` is garbage.
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        let mut cells: Vec<Vec<u8>> = Vec::new();
+        for (i, line) in src.trim_indent().split('\n').enumerate() {
+            let mut row = Vec::with_capacity(line.len());
+            for c in line.chars().filter(|c| !c.is_ascii_whitespace()) {
+                match CELL_SYMBOLS.iter().position(|s| *s == c) {
+                    Some(cell) => row.push(cell as u8),
+                    None => return Err(format!("Unexpected symbol '{}' in the row {}", c, i)),
+                }
+            }
+            if let Some(first) = cells.first() {
+                if first.len() != row.len() {
+                    return Err(format!("The row {} has {} cells, expected {}", i, row.len(), first.len()));
+                }
+            }
+            cells.push(row);
+        }
+        let height = cells.len();
+        let width = cells.first().map_or(0, |row| row.len());
+        Ok(Field { cells, height, width })
+    }
+}
+
+/// The output is parsed back by `Field::from_str`
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let text = self.cells.iter()
+            .map(|row| row.iter().map(|c| CELL_SYMBOLS[*c as usize]).join(" "))
+            .join("\n");
+        f.write_str(&text)
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Serialize, Deserialize)]
 pub enum Action {
     Left,
     Right,
@@ -50,11 +169,69 @@ pub enum Action {
     RotateCCW, // counterclockwise
     HardDrop,
     Tick,
+    Hold,
+    DasLeft,  // shift to the wall, as the auto-repeat does
+    DasRight,
+}
+
+/// The kind of the T-spin detected by the 3-corner rule, see `detect_tspin`
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy, Serialize, Deserialize)]
+pub enum TSpin {
+    None,
+    Mini,
+    Full,
+}
+
+/// The outcome of the last locked piece
+/// - `combo`: how many pieces in a row have burnt lines, including the last one
+/// - `back_to_back`: the difficult clear (tetris or T-spin with lines)
+///   follows the previous difficult clear
+/// - `perfect_clear`: the field is empty after the burning
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub struct ClearInfo {
+    pub lines_burnt: usize,
+    pub tspin: TSpin,
+    pub combo: u32,
+    pub back_to_back: bool,
+    pub perfect_clear: bool,
+}
+
+/// The result of `GameState::step` and `GameState::advance`
+/// - `clear`: the outcome of the piece locked by the step, `None` if nothing is locked
+/// - `game_over`: the game is over after the step
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct StepResult {
+    pub clear: Option<ClearInfo>,
+    pub game_over: bool,
+}
+
+impl StepResult {
+    pub fn lines_burnt(&self) -> usize {
+        self.clear.map_or(0, |clear| clear.lines_burnt)
+    }
+}
+
+impl Default for ClearInfo {
+    fn default() -> Self {
+        ClearInfo {
+            lines_burnt: 0,
+            tspin: TSpin::None,
+            combo: 0,
+            back_to_back: false,
+            perfect_clear: false,
+        }
+    }
 }
 
-/// TODO How do I make `rng` serializable?
-/// Essentially it is two u64 numbers, but they are private...
-#[derive(Debug, Clone)]
+/// The whole game in progress including the exact state of `rng`,
+/// see `snapshot` module to save and restore it
+/// - `last_clear`: the outcome of the last locked piece, it is kept until the next lock,
+///   the outcome of the particular step is `StepResult::clear`
+/// - `piece_inputs`: the shifts and rotations of the current piece that succeeded,
+///   the shift to the wall is one input
+/// - `finesse_faults`: the extra inputs of the locked pieces compared to the
+///   minimal ones, counted if `Config::track_finesse` is set, see `finesse`
+#[derive(Debug, Serialize, Deserialize)]
 pub struct GameState {
     pub config: Config,
     pub field: Field,
@@ -63,12 +240,104 @@ pub struct GameState {
     pub rotation: i8,
     pub curr_cells: Vec<Point>,
     pub curr_shape_idx: usize,
-    pub next_shape_idx: usize,
+    pub next_shape_idxs: VecDeque<usize>,
+    pub hold_shape_idx: Option<usize>,
+    pub hold_used: bool,
+    pub last_kick: Option<usize>,
+    pub last_clear: ClearInfo,
+    pub combo: u32,
+    pub back_to_back: bool,
     pub score: u32,
+    pub lines: u32,
+    pub level: u32,
+    pub piece_inputs: u32,
+    pub finesse_faults: u32,
     pub rng: Xoshiro512StarStar,
     pub rng_queue: Vec<usize>,
+    pub clock: Clock,
 }
 
+impl Clone for GameState {
+    fn clone(&self) -> GameState {
+        GameState {
+            config: self.config,
+            field: self.field.clone(),
+            game_over: self.game_over,
+            base: self.base,
+            rotation: self.rotation,
+            curr_cells: self.curr_cells.clone(),
+            curr_shape_idx: self.curr_shape_idx,
+            next_shape_idxs: self.next_shape_idxs.clone(),
+            hold_shape_idx: self.hold_shape_idx,
+            hold_used: self.hold_used,
+            last_kick: self.last_kick,
+            last_clear: self.last_clear,
+            combo: self.combo,
+            back_to_back: self.back_to_back,
+            score: self.score,
+            lines: self.lines,
+            level: self.level,
+            piece_inputs: self.piece_inputs,
+            finesse_faults: self.finesse_faults,
+            rng: self.rng.clone(),
+            rng_queue: self.rng_queue.clone(),
+            clock: self.clock,
+        }
+    }
+
+    /// The buffers of the field and the queues are reused, so the simulations
+    /// restore the state between the candidates without the allocations
+    fn clone_from(&mut self, source: &GameState) {
+        let GameState {
+            config, field, game_over, base, rotation, curr_cells, curr_shape_idx,
+            next_shape_idxs, hold_shape_idx, hold_used, last_kick, last_clear, combo,
+            back_to_back, score, lines, level, piece_inputs, finesse_faults, rng, rng_queue, clock,
+        } = source;
+        self.config = *config;
+        self.field.cells.clone_from(&field.cells);
+        self.field.height = field.height;
+        self.field.width = field.width;
+        self.game_over = *game_over;
+        self.base = *base;
+        self.rotation = *rotation;
+        self.curr_cells.clone_from(curr_cells);
+        self.curr_shape_idx = *curr_shape_idx;
+        self.next_shape_idxs.clone_from(next_shape_idxs);
+        self.hold_shape_idx = *hold_shape_idx;
+        self.hold_used = *hold_used;
+        self.last_kick = *last_kick;
+        self.last_clear = *last_clear;
+        self.combo = *combo;
+        self.back_to_back = *back_to_back;
+        self.score = *score;
+        self.lines = *lines;
+        self.level = *level;
+        self.piece_inputs = *piece_inputs;
+        self.finesse_faults = *finesse_faults;
+        self.rng.clone_from(rng);
+        self.rng_queue.clone_from(rng_queue);
+        self.clock = *clock;
+    }
+}
+
+/// The real-time state of the current piece, see `GameState::advance`,
+/// all durations are in milliseconds
+/// - `gravity_time`: time passed since the last gravity step
+/// - `lock_time`: time the piece rests on the ground
+/// - `lock_resets`: how many times the lock delay has been reset
+/// - `lowest_row`: the lowest row reached by the piece, when it goes lower
+///   the lock resets are counted from zero again
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Clock {
+    pub gravity_time: u32,
+    pub lock_time: u32,
+    pub lock_resets: u32,
+    pub lowest_row: i32,
+}
+
+
+/// Garbage lines sent for the combo, indexed by `combo - 1`
+pub const COMBO_LINES: [u32; 12] = [0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5];
 
 lazy_static!{
     // In each 0th element in each row is the index `(rotation_from, rotation_to)`
@@ -81,6 +350,18 @@ lazy_static!{
     //      2 - pi clockwise,
     //      3 - 3pi/2 clockwise = pi/2 counterclockwise
 
+    /// `ROTATIONS[shape_idx][r]` are the cells of `TETRIMINOES[shape_idx]` in the rotation `r`
+    /// relative to the base, computed once by `rotate`, see `rotated`
+    pub static ref ROTATIONS: [[[Point; 4]; 4]; 7] = {
+        let mut rotations = [[[Point(0, 0); 4]; 4]; 7];
+        for (shape, shape_rotations) in TETRIMINOES.iter().zip(rotations.iter_mut()) {
+            for (r, cells) in shape_rotations.iter_mut().enumerate() {
+                cells.copy_from_slice(&rotate(shape, r as i8));
+            }
+        }
+        rotations
+    };
+
     /// shifts to test for I
     pub static ref WALL_KICKS_I: HashMap<(i8, i8), Vec<Point>> = {
         let table = [
@@ -96,7 +377,7 @@ lazy_static!{
         let mut hm: HashMap<(i8, i8), Vec<_>> = HashMap::new();
         for row in table.iter() {
             hm.insert(row[0],
-                row[1..5].iter().map(|xy| Point(-xy.1 as i32, xy.0 as i32)).collect()
+                row[1..].iter().map(|xy| Point(-xy.1 as i32, xy.0 as i32)).collect()
             );
         }
         hm
@@ -116,7 +397,7 @@ lazy_static!{
         let mut hm: HashMap<(i8, i8), Vec<_>> = HashMap::new();
         for row in table.iter() {
             hm.insert(row[0],
-                row[1..5].iter().map(|xy| Point(-xy.1 as i32, xy.0 as i32)).collect()
+                row[1..].iter().map(|xy| Point(-xy.1 as i32, xy.0 as i32)).collect()
             );
         }
         hm
@@ -124,7 +405,19 @@ lazy_static!{
 }
 
 impl GameState {
-    pub fn initial(height: usize, width: usize, config: Config, seed: Option<u64>) -> GameState {
+    /// The field of `height` rows including `config.hidden_rows` and `width` columns,
+    /// the size in `config` is replaced, see `from_config`
+    pub fn initial(height: usize, width: usize, mut config: Config, seed: Option<u64>) -> GameState {
+        config.hidden_rows = config.hidden_rows.min(height);
+        config.height = height - config.hidden_rows;
+        config.width = width;
+        GameState::from_config(config, seed)
+    }
+
+    /// The field is `config.height + config.hidden_rows` by `config.width`
+    pub fn from_config(config: Config, seed: Option<u64>) -> GameState {
+        let height = config.height + config.hidden_rows;
+        let width = config.width;
         let rng = if let Some(seed) = seed {
             Xoshiro512StarStar::seed_from_u64(seed)
         } else {
@@ -143,10 +436,21 @@ impl GameState {
             rotation: 0,
             curr_cells: Vec::new(),
             curr_shape_idx: 0,
-            next_shape_idx: 0,
+            next_shape_idxs: VecDeque::new(),
+            hold_shape_idx: None,
+            hold_used: false,
+            last_kick: None,
+            last_clear: ClearInfo::default(),
+            combo: 0,
+            back_to_back: false,
             score: 0,
+            lines: 0,
+            level: 0,
+            piece_inputs: 0,
+            finesse_faults: 0,
             rng,
             rng_queue: Vec::new(),
+            clock: Clock::default(),
         };
         gs.reset();
         gs
@@ -160,44 +464,40 @@ impl GameState {
                 self.field.cells[i][j] = 0;
             }
         }
-        let next_shape_idx;
-        let mut rng_queue;
-        match self.config.randomness {
-            Randomness::JustRandom => {
-                next_shape_idx = self.rng.gen_range(0, TETRIMINOES.len());
-                rng_queue = Vec::new();
-            },
-            Randomness::ShuffledQueue => {
-                rng_queue = (0..TETRIMINOES.len()).collect::<Vec<_>>();
-                rng_queue.shuffle(&mut self.rng);
-                next_shape_idx = rng_queue.pop().unwrap(); // guaranteed to exist
-            },
+        // the preview is filled from the fresh random queue
+        self.rng_queue.clear();
+        self.next_shape_idxs.clear();
+        for _ in 0..cmp::max(1, self.config.preview_len) {
+            let next_shape_idx = self.gen_next_shape_idx();
+            self.next_shape_idxs.push_back(next_shape_idx);
         }
         self.curr_shape_idx = 0;
-        self.next_shape_idx = next_shape_idx;
-        self.rng_queue = rng_queue;
+        self.hold_shape_idx = None;
+        self.hold_used = false;
+        self.last_clear = ClearInfo::default();
+        self.combo = 0;
+        self.back_to_back = false;
         self.score = 0;
+        self.lines = 0;
+        self.level = self.level_for_lines(0);
+        self.finesse_faults = 0;
         self.spawn_next_shape();
     }
 
-    /// transition should be the pair of `(p, q)`, where `p, q \in {0, 1, 2, 3}`
-    pub fn try_wall_kick_current_shape(&self, transition: (i8, i8)) -> Option<(Point, Vec<Point>)> {
-        let test_points: &[Point] = if self.curr_shape_idx == 0 {
-            &WALL_KICKS_I.get(&transition)?
-        } else {
-            &WALL_KICKS_X.get(&transition)?
-        };
-        // return the first test point, that enables the rotation around
-        test_points.into_iter().find_map(|t| {
-            let base_new = Point(self.base.0 + t.0, self.base.1 + t.1);
-            self.try_current_shape(&base_new, transition.1)
-                .map(|v| (base_new, v))
-        })
+    /// transition should be the pair of `(p, q)`, where `p, q \in {0, 1, 2, 3}`,
+    /// the result contains the index of the successful test point (the wall kick index)
+    pub fn try_wall_kick_current_shape(&self, transition: (i8, i8)) -> Option<(Point, [Point; 4], usize)> {
+        try_wall_kick(&self.field, self.curr_shape_idx, &self.base, transition)
+    }
+
+    pub fn try_current_shape(&self, base: &Point, rotation: i8) -> Option<[Point; 4]> {
+        try_shape_idx(&self.field, base, rotation, self.curr_shape_idx)
     }
 
-    pub fn try_current_shape(&self, base: &Point, rotation: i8) -> Option<Vec<Point>> {
-        let shape = &TETRIMINOES[self.curr_shape_idx];
-        try_shape(&self.field, &base, rotation, &shape)
+    /// Replace `curr_cells` keeping its buffer
+    pub fn set_curr_cells(&mut self, cells: &[Point]) {
+        self.curr_cells.clear();
+        self.curr_cells.extend_from_slice(cells);
     }
 
     pub fn draw_current_shape(&mut self) {
@@ -206,46 +506,309 @@ impl GameState {
         }
     }
 
+    /// Remove the full rows, the rows above fall down, return the number of the burnt lines.
+    /// The rows are moved as a whole, the burnt ones are emptied and reused at the top
     pub fn burn_lines(&mut self) -> usize {
-        let mut burn_is: Vec<usize> = vec![];
-        for i in 0..self.field.height {
-            if self.field.cells[i].iter().all(|c| *c != 0) {
-                burn_is.push(i);
-            }
-        }
-        // burning makes the cells fall down, therefore we iterate down up
-        // `it` skips the rows without burning
-        let mut it = self.field.height - 1;
-        for i in (0..self.field.height).rev() {
-            if it != i {
-                for j in 0..self.field.width {
-                    self.field.cells[it][j] = self.field.cells[i][j];
-                }
-            }
-            if !burn_is.contains(&i) && it > 0 {
+        let cells = &mut self.field.cells;
+        // `it` is the row to move the next kept row to, the rows between
+        // the current one and `it` are the burnt ones, we iterate down up
+        let mut it = cells.len();
+        for i in (0..cells.len()).rev() {
+            if cells[i].contains(&0) {
                 it -= 1;
+                cells.swap(i, it);
             }
         }
-        // the rest of lines are populated by zeros
-        for i in (0..it + 1).rev() {
-            for j in 0..self.field.width {
-                self.field.cells[i][j] = 0;
-            }
+        for row in &mut cells[..it] {
+            row.iter_mut().for_each(|c| *c = 0);
         }
-        self.score += match &self.config.scoring {
+        it
+    }
+
+    /// The score for the locked piece, the level before the lines are burnt is used
+    pub fn lock_score(&self, clear: &ClearInfo) -> u32 {
+        let lines_burnt = clear.lines_burnt;
+        let tspin = clear.tspin;
+        match &self.config.scoring {
             Scoring::BurnOnly => 1,
             Scoring::PieceAndBurn => {
-                let lines_cleared = burn_is.len() as u32;
+                let lines_cleared = lines_burnt as u32;
                 1 + lines_cleared * lines_cleared * (self.field.width as u32)
             },
+            Scoring::Guideline => {
+                let points = match (tspin, lines_burnt) {
+                    (TSpin::None, 0) => 0,
+                    (TSpin::None, 1) => 100,
+                    (TSpin::None, 2) => 300,
+                    (TSpin::None, 3) => 500,
+                    (TSpin::None, _) => 800,
+                    (TSpin::Mini, 0) => 100,
+                    (TSpin::Mini, 1) => 200,
+                    (TSpin::Mini, _) => 400,
+                    (TSpin::Full, 0) => 400,
+                    (TSpin::Full, 1) => 800,
+                    (TSpin::Full, 2) => 1200,
+                    (TSpin::Full, _) => 1600,
+                };
+                points * cmp::max(1, self.level)
+            },
+            Scoring::Versus => {
+                let lines_sent = match (tspin, lines_burnt) {
+                    (TSpin::None, 0) | (TSpin::None, 1) => 0,
+                    (TSpin::None, 2) => 1,
+                    (TSpin::None, 3) => 2,
+                    (TSpin::None, _) => 4,
+                    (TSpin::Mini, 0) | (TSpin::Mini, 1) => 0,
+                    (TSpin::Mini, _) => 1,
+                    (TSpin::Full, n) => 2 * n as u32,
+                };
+                let combo_idx = cmp::min(clear.combo.saturating_sub(1) as usize, COMBO_LINES.len() - 1);
+                let mut lines_sent = lines_sent + COMBO_LINES[combo_idx];
+                if clear.back_to_back {
+                    lines_sent += 1;
+                }
+                if clear.perfect_clear {
+                    lines_sent += 10;
+                }
+                lines_sent
+            },
+        }
+    }
+
+    /// Draw the current shape into the field, burn the lines and spawn the next shape
+    pub fn lock_current_shape(&mut self) -> ClearInfo {
+        let tspin = detect_tspin(&self.field, self.curr_shape_idx, &self.base, self.rotation, self.last_kick);
+        if self.config.track_finesse {
+            let base = self.spawn_base(self.curr_shape_idx);
+            if let Some(min) = min_inputs(&self.field, self.curr_shape_idx, base, &self.curr_cells) {
+                self.finesse_faults += self.piece_inputs.saturating_sub(min);
+            }
+        }
+        let locked_out = self.config.top_out == TopOut::LockOut
+            && self.curr_cells.iter().all(|p| (p.0 as usize) < self.config.hidden_rows);
+        self.draw_current_shape();
+        let lines_burnt = self.burn_lines();
+        let clear = self.update_clear_info(lines_burnt, tspin);
+        self.score += self.lock_score(&clear);
+        self.last_clear = clear;
+        self.lines += lines_burnt as u32;
+        self.level = self.level_for_lines(self.lines);
+        if locked_out {
+            self.game_over = true;
+        } else {
+            self.spawn_next_shape();
+        }
+        clear
+    }
+
+    /// Update the combo and back-to-back chains with the lines just burnt
+    fn update_clear_info(&mut self, lines_burnt: usize, tspin: TSpin) -> ClearInfo {
+        let difficult = lines_burnt == 4 || lines_burnt > 0 && tspin != TSpin::None;
+        let back_to_back = difficult && self.back_to_back;
+        if lines_burnt > 0 {
+            self.combo += 1;
+            self.back_to_back = difficult;
+        } else {
+            // locks without lines break the combo, but not back-to-back
+            self.combo = 0;
+        }
+        ClearInfo {
+            lines_burnt,
+            tspin,
+            combo: self.combo,
+            back_to_back,
+            perfect_clear: lines_burnt > 0 && self.field.is_empty(),
+        }
+    }
+
+    pub fn level_for_lines(&self, lines: u32) -> u32 {
+        let timing = self.config.timing.unwrap_or_default();
+        timing.start_level + lines / cmp::max(1, timing.lines_per_level)
+    }
+
+    /// Milliseconds per row at the current level, the Guideline formula is
+    /// `(0.8 - (level - 1) * 0.007) ^ (level - 1)` seconds
+    pub fn gravity_interval(&self) -> u32 {
+        let level = self.level.clamp(1, 20) as f64;
+        let seconds = (0.8 - (level - 1.0) * 0.007).powf(level - 1.0);
+        cmp::max(1, (seconds * 1000.0).round() as u32)
+    }
+
+    /// Advance the real-time clock by `dt` milliseconds: apply the gravity
+    /// (accelerated if `soft_drop` is on) and lock the piece when it has rested
+    /// on the ground for `lock_delay`. Does nothing if `config.timing` is `None`.
+    pub fn advance(&mut self, dt: u32, soft_drop: bool) -> StepResult {
+        let timing = match self.config.timing {
+            Some(timing) if !self.game_over => timing,
+            _ => return StepResult { clear: None, game_over: self.game_over },
         };
-        burn_is.len()
+        let mut interval = self.gravity_interval();
+        if soft_drop {
+            interval = cmp::max(1, interval / cmp::max(1, timing.soft_drop_factor));
+        }
+        self.clock.gravity_time += dt;
+        while self.clock.gravity_time >= interval {
+            self.clock.gravity_time -= interval;
+            if !self.move_current_shape(1, 0) {
+                self.clock.gravity_time = 0;
+                break;
+            }
+        }
+        if self.is_on_ground() {
+            self.clock.lock_time += dt;
+            // "infinity" is limited: when the resets are over,
+            // the piece locks as soon as it touches the ground
+            if self.clock.lock_time >= timing.lock_delay || self.clock.lock_resets >= timing.max_lock_resets {
+                let clear = self.lock_current_shape();
+                return StepResult { clear: Some(clear), game_over: self.game_over };
+            }
+        } else {
+            self.clock.lock_time = 0;
+        }
+        StepResult { clear: None, game_over: self.game_over }
+    }
+
+    pub fn is_on_ground(&self) -> bool {
+        let base_new = Point(self.base.0 + 1, self.base.1);
+        try_shape_idx(&self.field, &base_new, self.rotation, self.curr_shape_idx).is_none()
+    }
+
+    /// Shift the current shape if possible, return `true` on success
+    pub fn move_current_shape(&mut self, di: i32, dj: i32) -> bool {
+        let base_new = Point(self.base.0 + di, self.base.1 + dj);
+        if let Some(cells) = self.try_current_shape(&base_new, self.rotation) {
+            self.base = base_new;
+            self.set_curr_cells(&cells);
+            self.on_current_shape_moved(di == 0);
+            self.last_kick = None;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Rotate the current shape using wall kicks, return `true` on success
+    pub fn rotate_current_shape(&mut self, rotation_new: i8) -> bool {
+        let transition = (self.rotation, rotation_new);
+        if let Some((base, cells, kick)) = self.try_wall_kick_current_shape(transition) {
+            self.base = base;
+            self.rotation = rotation_new;
+            self.set_curr_cells(&cells);
+            self.on_current_shape_moved(true);
+            self.last_kick = Some(kick);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Update the lock delay after the successful move or rotation
+    /// of the current shape, `reset` is `false` for moves down
+    fn on_current_shape_moved(&mut self, reset: bool) {
+        let bottom_row = self.bottom_row();
+        if bottom_row > self.clock.lowest_row {
+            self.clock.lowest_row = bottom_row;
+            self.clock.lock_resets = 0;
+        }
+        if let Some(timing) = self.config.timing {
+            if reset && self.clock.lock_time > 0 && self.clock.lock_resets < timing.max_lock_resets {
+                self.clock.lock_time = 0;
+                self.clock.lock_resets += 1;
+            }
+        }
+    }
+
+    fn bottom_row(&self) -> i32 {
+        self.curr_cells.iter().map(|p| p.0).max().unwrap_or(0)
     }
 
     pub fn spawn_next_shape(&mut self) {
-        let prev_shape_idx = self.curr_shape_idx;
-        self.curr_shape_idx = self.next_shape_idx;
-        self.next_shape_idx = match &self.config.randomness {
+        if self.next_shape_idxs.is_empty() {
+            // the preview is empty when the pieces come from outside, see `tbp`
+            let next_shape_idx = self.gen_next_shape_idx();
+            self.next_shape_idxs.push_back(next_shape_idx);
+        }
+        if self.spawn_shape(self.next_shape_idxs[0]) {
+            self.next_shape_idxs.pop_front();
+            let next_shape_idx = self.gen_next_shape_idx();
+            self.next_shape_idxs.push_back(next_shape_idx);
+        }
+        // the new piece is allowed to be held again
+        self.hold_used = false;
+    }
+
+    /// The previews after `spawn_next_shape`, when the spawn fails and when it succeeds,
+    /// the state is not changed, the shape to spawn is the first one of the former
+    pub fn peek_next_shapes(&self) -> (VecDeque<usize>, VecDeque<usize>) {
+        let mut gs = self.clone();
+        if gs.next_shape_idxs.is_empty() {
+            let next_shape_idx = gs.gen_next_shape_idx();
+            gs.next_shape_idxs.push_back(next_shape_idx);
+        }
+        let failed = gs.next_shape_idxs.clone();
+        gs.next_shape_idxs.pop_front();
+        let next_shape_idx = gs.gen_next_shape_idx();
+        gs.next_shape_idxs.push_back(next_shape_idx);
+        (failed, gs.next_shape_idxs)
+    }
+
+    /// Put the shape `shape_idx` to the spawn position.
+    /// Return `false` and set `game_over` if the spawn position is occupied,
+    /// the indices are left untouched to avoid incorrect color change
+    /// of the last tetrimino, that caused the game over.
+    pub fn spawn_shape(&mut self, shape_idx: usize) -> bool {
+        let base = self.spawn_base(shape_idx);
+        if let Some(cells) = try_shape_idx(&self.field, &base, 0, shape_idx) {
+            self.curr_shape_idx = shape_idx;
+            self.rotation = 0;
+            self.base = base;
+            self.set_curr_cells(&cells);
+            self.game_over = false;
+            self.last_kick = None;
+            self.piece_inputs = 0;
+            self.clock = Clock { lowest_row: self.bottom_row(), ..Clock::default() };
+            true
+        } else {
+            self.game_over = true;
+            false
+        }
+    }
+
+    pub fn spawn_base(&self, shape_idx: usize) -> Point {
+        match self.config.spawn {
+            Spawn::Custom(bases) => bases[shape_idx],
+            Spawn::Default => match shape_idx {
+                1 => Point(0, self.field.width as i32 / 2 - 1), // O
+                _ => Point(1, self.field.width as i32 / 2 - 1),
+            },
+        }
+    }
+
+    /// Swap the current shape with the one in the hold slot, or put the current
+    /// shape there and spawn the next one, if the slot is empty. Holding is
+    /// allowed once per drop, `hold_used` is cleared when the next shape spawns.
+    pub fn hold_current_shape(&mut self) {
+        if !self.config.hold_enabled || self.hold_used {
+            return;
+        }
+        let curr_shape_idx = self.curr_shape_idx;
+        match self.hold_shape_idx {
+            Some(hold_shape_idx) => {
+                self.spawn_shape(hold_shape_idx);
+            }
+            None => {
+                // the next shape is taken from `rng_queue` as usual
+                self.spawn_next_shape();
+            }
+        }
+        if !self.game_over {
+            self.hold_shape_idx = Some(curr_shape_idx);
+        }
+        self.hold_used = true;
+    }
+
+    fn gen_next_shape_idx(&mut self) -> usize {
+        match &self.config.randomness {
             Randomness::JustRandom => self.rng.gen_range(0, TETRIMINOES.len()),
             Randomness::ShuffledQueue => {
                 match self.rng_queue.pop() {
@@ -260,31 +823,16 @@ impl GameState {
                     }
                 }
             }
-        };
-        self.rotation = 0;
-        self.base = match self.curr_shape_idx {
-            1 => Point(0, self.field.width as i32 / 2 - 1), // O
-            _ => Point(1, self.field.width as i32 / 2 - 1),
-        };
-        if let Some(cells) = self.try_current_shape(&self.base, self.rotation) {
-            self.curr_cells = cells;
-            self.game_over = false;
-        } else {
-            // restore index to avoid incorrect color change of the last tetrimino,
-            // that caused the game over
-            self.next_shape_idx = self.curr_shape_idx;
-            self.curr_shape_idx = prev_shape_idx;
-            self.game_over = true;
         }
     }
 
-    pub fn drop_current_shape(&mut self) -> (i32, Option<Vec<Point>>) {
+    pub fn drop_current_shape(&mut self) -> (i32, Option<[Point; 4]>) {
         let mut i = self.base.0;
         // the last valid position on the path
-        let mut cur_cells: Option<Vec<Point>> = None;
+        let mut cur_cells: Option<[Point; 4]> = None;
         loop {
             let base_new = Point(i, self.base.1);
-            let cells = self.try_current_shape(&base_new, self.rotation);
+            let cells = try_shape_idx(&self.field, &base_new, self.rotation, self.curr_shape_idx);
             if cells.is_none() {
                 break;
             }
@@ -294,94 +842,57 @@ impl GameState {
         (i, cur_cells)
     }
 
-    pub fn step(&mut self, action: Action) -> (usize, bool) {
+    pub fn step(&mut self, action: Action) -> StepResult {
         if self.game_over {
-            return (0, true);
+            return StepResult { clear: None, game_over: true };
         }
-        let mut lines_burnt = 0;
-        match action {
+        let mut clear = None;
+        let accepted = match action {
             Action::Tick => {
-                // clear current
-                let base_new = Point(self.base.0 + 1, self.base.1);
-                if let Some(cells) = self.try_current_shape(&base_new, self.rotation) {
-                    self.base = base_new;
-                    for i in 0..cells.len() {
-                        self.curr_cells[i] = cells[i];
-                    }
-                } else {
-                    self.draw_current_shape();
-                    lines_burnt = self.burn_lines();
-                    self.spawn_next_shape();
+                if !self.move_current_shape(1, 0) {
+                    clear = Some(self.lock_current_shape());
                 }
+                false
             }
             Action::HardDrop => {
                 let (i_new, cur_cells) = self.drop_current_shape();
                 if let Some(cells) = cur_cells {
-                    for i in 0..cells.len() {
-                        self.curr_cells[i] = cells[i];
+                    self.set_curr_cells(&cells);
+                    // `i_new` is the first invalid row
+                    if i_new - 1 != self.base.0 {
+                        // the drop cancels the spin
+                        self.last_kick = None;
                     }
-                    self.base = Point(i_new, self.base.1);
-                    self.draw_current_shape();
-                    lines_burnt = self.burn_lines();
-                    self.spawn_next_shape();
+                    self.base = Point(i_new - 1, self.base.1);
+                    clear = Some(self.lock_current_shape());
                 }
+                false
             }
             Action::Down => {
-                // clear current
-                let base_new = Point(self.base.0 + 1, self.base.1);
-                if let Some(cells) = self.try_current_shape(&base_new, self.rotation) {
-                    self.base = base_new;
-                    for i in 0..cells.len() {
-                        self.curr_cells[i] = cells[i];
-                    }
-                }
-            }
-            Action::Left => {
-                // clear current
-                let base_new = Point(self.base.0, self.base.1 - 1);
-                if let Some(cells) = self.try_current_shape(&base_new, self.rotation) {
-                    self.base = base_new;
-                    for i in 0..cells.len() {
-                        self.curr_cells[i] = cells[i];
-                    }
-                }
+                self.move_current_shape(1, 0);
+                false
             }
-            Action::Right => {
-                // clear current
-                let base_new = Point(self.base.0, self.base.1 + 1);
-                if let Some(cells) = self.try_current_shape(&base_new, self.rotation) {
-                    self.base = base_new;
-                    for i in 0..cells.len() {
-                        self.curr_cells[i] = cells[i];
-                    }
+            Action::Left => self.move_current_shape(0, -1),
+            Action::Right => self.move_current_shape(0, 1),
+            Action::DasLeft | Action::DasRight => {
+                let dj = if action == Action::DasLeft { -1 } else { 1 };
+                let mut moved = false;
+                while self.move_current_shape(0, dj) {
+                    moved = true;
                 }
+                moved
             }
-            Action::RotateCCW => {
-                // clear current
-                let rotation_new = (self.rotation + 3) % 4;
-                let transition = (self.rotation, rotation_new);
-                if let Some((base, cells)) = self.try_wall_kick_current_shape(transition) {
-                    self.base = base;
-                    self.rotation = rotation_new;
-                    for i in 0..cells.len() {
-                        self.curr_cells[i] = cells[i];
-                    }
-                }
-            }
-            Action::RotateCW => {
-                // clear current
-                let rotation_new = (self.rotation + 1) % 4;
-                let transition = (self.rotation, rotation_new);
-                if let Some((base, cells)) = self.try_wall_kick_current_shape(transition) {
-                    self.base = base;
-                    self.rotation = rotation_new;
-                    for i in 0..cells.len() {
-                        self.curr_cells[i] = cells[i];
-                    }
-                }
+            Action::RotateCCW => self.rotate_current_shape((self.rotation + 3) % 4),
+            Action::RotateCW => self.rotate_current_shape((self.rotation + 1) % 4),
+            Action::Hold => {
+                self.hold_current_shape();
+                false
             }
+        };
+        if accepted {
+            self.piece_inputs += 1;
         }
-        return (lines_burnt, self.game_over);
+        StepResult { clear, game_over: self.game_over }
     }
 
 /*
@@ -408,11 +919,22 @@ impl GameState {
         let n = self.field.width;
         let mut result = String::with_capacity(m * (10 * n + 1) + 2);
         result.push_str("\r\n");
-        result.push_str(&format!("score: {}\r\n", self.score));
-        result.push_str(&format!("next shape: {}\r\n", &TETRIMINOES[self.next_shape_idx]
-            .style.apply_to(&self.next_shape_idx.to_string())));
+        if self.config.track_finesse {
+            result.push_str(&format!("score: {}, finesse faults: {}\r\n", self.score, self.finesse_faults));
+        } else {
+            result.push_str(&format!("score: {}\r\n", self.score));
+        }
+        let next_shapes = self.next_shape_idxs.iter()
+            .map(|idx| TETRIMINOES[*idx].style.apply_to(&idx.to_string()))
+            .join(" ");
+        result.push_str(&format!("next shapes: {}\r\n", next_shapes));
         result.push_str(&format!("current shape: {}\r\n", &TETRIMINOES[self.curr_shape_idx]
             .style.apply_to(&self.curr_shape_idx.to_string())));
+        match self.hold_shape_idx {
+            Some(idx) => result.push_str(&format!("hold shape: {}\r\n", &TETRIMINOES[idx]
+                .style.apply_to(&idx.to_string()))),
+            None => result.push_str("hold shape: -\r\n"),
+        }
 
         // now put all the stuff
         if wide {
@@ -439,7 +961,7 @@ impl GameState {
                     if cell == 0 {
                         result.push_str("    ");
                     } else {
-                        let color: Style = TETRIMINOES[cell as usize - 1].style;
+                        let color: Style = cell_style(cell);
                         result.push_str(&color.apply_to(&fill_block));
                     }
                 }
@@ -457,14 +979,14 @@ impl GameState {
                     if cell == 0 {
                         result.push_str("   .");
                     } else {
-                        let color: Style = TETRIMINOES[cell as usize - 1].style;
+                        let color: Style = cell_style(cell);
                         result.push_str(&color.apply_to(&fill_block));
                     }
                 }
                 result.push_str("\r\n");
             }
             if rewind {
-                for _ in 0..(2 * m + 6) {
+                for _ in 0..(2 * m + 7) {
                     result.push_str("\x1B[A") // up
                 }
             }
@@ -472,6 +994,9 @@ impl GameState {
             // -------------
             // compact mode render
             let empty_style = Style::Empty;
+            let styles: Vec<Style> = (0..CELL_SYMBOLS.len() as u8)
+                .map(|cell| if cell == 0 { Style::Empty } else { cell_style(cell) })
+                .collect();
             let mut curr_piece: String = String::with_capacity(n * 4);
 
             for i in 0..m {
@@ -502,7 +1027,7 @@ impl GameState {
                             result.push_str(&curr_style.apply_to(&curr_piece).to_string());
                             curr_piece.clear();
                         }
-                        curr_style = if cell == 0 { &empty_style } else { &TETRIMINOES[cell as usize - 1].style };
+                        curr_style = if cell == 0 { &empty_style } else { &styles[cell as usize] };
                     }
 
                     curr_piece.push(if cell == 0 { '.' } else { '#' });
@@ -513,7 +1038,7 @@ impl GameState {
                 result.push_str("\r\n");
             }
             if rewind {
-                for _ in 0..(m + 5) {
+                for _ in 0..(m + 6) {
                     result.push_str("\x1B[A") // up
                 }
             }
@@ -526,21 +1051,75 @@ impl GameState {
 /// this allows us not to mutate points and get easier interface on the client
 /// side, when we want to check multiple positions of the same shape with
 /// the only base changed.
-pub fn is_valid(field: &Field, base: &Point, points: &[Point]) -> bool {
+pub fn is_valid<F: Occupancy + ?Sized>(field: &F, base: &Point, points: &[Point]) -> bool {
     for p in points {
         let i = base.0 + p.0;
         let j = base.1 + p.1;
-        if i < 0 || field.height as i32 <= i {
+        if i < 0 || field.height() as i32 <= i {
             return false;
-        } else if j < 0 || field.width as i32 <= j {
+        } else if j < 0 || field.width() as i32 <= j {
             return false;
-        } else if field.cells[i as usize][j as usize] != 0 {
+        } else if field.is_occupied(i as usize, j as usize) {
             return false;
         }
     }
     true
 }
 
+/// The 3-corner rule: the last successful action on T is a rotation, and at least
+/// 3 of 4 cells diagonally adjacent to its center are occupied (walls and floor count).
+/// It is a full T-spin if both corners on the pointing side are occupied or the last
+/// wall kick test (the 1x2 shift) was used, otherwise it is a mini T-spin.
+pub fn detect_tspin<F: Occupancy + ?Sized>(field: &F, shape_idx: usize, base: &Point, rotation: i8, last_kick: Option<usize>) -> TSpin {
+    let kick = match last_kick {
+        Some(kick) if shape_idx == 2 => kick, // T
+        _ => return TSpin::None,
+    };
+    let occupied = |di: i32, dj: i32| !is_valid(field, base, &[Point(di, dj)]);
+    let corners = [(-1, -1), (-1, 1), (1, 1), (1, -1)];
+    let count = corners.iter().filter(|(di, dj)| occupied(*di, *dj)).count();
+    if count < 3 {
+        return TSpin::None;
+    }
+    // the corners are listed clockwise, so the pointing side of
+    // the rotation `r` is formed by the corners `r` and `r + 1`
+    let r = rotation.rem_euclid(4) as usize;
+    let (f1, f2) = (corners[r], corners[(r + 1) % 4]);
+    if occupied(f1.0, f1.1) && occupied(f2.0, f2.1) || kick == 4 {
+        TSpin::Full
+    } else {
+        TSpin::Mini
+    }
+}
+
+/// The wall kick of the shape at `base`, see `GameState::try_wall_kick_current_shape`
+pub fn try_wall_kick<F: Occupancy + ?Sized>(field: &F, shape_idx: usize, base: &Point, transition: (i8, i8)) -> Option<(Point, [Point; 4], usize)> {
+    let test_points: &[Point] = if shape_idx == 0 {
+        WALL_KICKS_I.get(&transition)?
+    } else {
+        WALL_KICKS_X.get(&transition)?
+    };
+    // return the first test point, that enables the rotation around
+    test_points.iter().enumerate().find_map(|(k, t)| {
+        let base_new = Point(base.0 + t.0, base.1 + t.1);
+        try_shape_idx(field, &base_new, transition.1, shape_idx)
+            .map(|cells| (base_new, cells, k))
+    })
+}
+
+/// The same as `try_shape(.., &TETRIMINOES[shape_idx])`, but without the allocations
+pub fn try_shape_idx<F: Occupancy + ?Sized>(field: &F, base: &Point, rotation: i8, shape_idx: usize) -> Option<[Point; 4]> {
+    let mut points = *rotated(shape_idx, rotation);
+    if !is_valid(field, base, &points) {
+        return None;
+    }
+    for p in &mut points {
+        p.0 += base.0;
+        p.1 += base.1;
+    }
+    Some(points)
+}
+
 pub fn try_shape(field: &Field, base: &Point, rotation: i8, shape: &Tetrimino) -> Option<Vec<Point>> {
     let mut points = rotate(&shape, rotation);
     if !is_valid(field, base, &points) {
@@ -557,9 +1136,10 @@ pub fn try_shape(field: &Field, base: &Point, rotation: i8, shape: &Tetrimino) -
 pub fn rotate(shape: &Tetrimino, r: i8) -> Vec<Point> {
     // modulo, NOT the remainder, see https://stackoverflow.com/a/41422009/5066426
     let r = (r % 4 + 4) % 4;
-    let mut p = shape.clone();
+    let mut diffs = shape.diffs.clone();
+    let mut parity = shape.parity;
     for _ in 0..r {
-        for d in &mut p.diffs {
+        for d in &mut diffs {
             // rotate clockwise (-2, 1) => (1, 2),
             // i.e. negate the first coordinate and then swap
             let t = -d.0;
@@ -567,16 +1147,66 @@ pub fn rotate(shape: &Tetrimino, r: i8) -> Vec<Point> {
             d.1 = t;
         }
         // swap shift
-        let t = p.parity.1;
-        p.parity.1 = p.parity.0;
-        p.parity.0 = t;
+        mem::swap(&mut parity.0, &mut parity.1);
     }
     // shift and divide, so (0, 0) is the integer center of the rotated shape
-    for d in &mut p.diffs {
-        d.0 = (d.0 + p.parity.0) / 2;
-        d.1 = (d.1 + p.parity.1) / 2;
+    for d in &mut diffs {
+        d.0 = (d.0 + parity.0) / 2;
+        d.1 = (d.1 + parity.1) / 2;
     }
-    p.diffs
+    diffs
+}
+
+/// The precomputed `rotate(&TETRIMINOES[shape_idx], r)`
+pub fn rotated(shape_idx: usize, r: i8) -> &'static [Point; 4] {
+    &ROTATIONS[shape_idx][r.rem_euclid(4) as usize]
+}
+
+/// The cells of the shape around its SRS rotation center, `x` goes right and `y` goes up,
+/// the O is centered at the bottom left cell and the I at the left one of the middle cells
+/// in the spawn orientation. The external tools (fumen, TBP) locate the pieces this way.
+pub fn srs_blocks(shape_idx: usize, rotation: i8) -> Vec<(i32, i32)> {
+    let blocks: [(i32, i32); 4] = match shape_idx {
+        0 => [(0, 0), (-1, 0), (1, 0), (2, 0)],  // I
+        1 => [(0, 0), (1, 0), (0, 1), (1, 1)],   // O
+        2 => [(0, 0), (-1, 0), (1, 0), (0, 1)],  // T
+        3 => [(0, 0), (-1, 0), (0, 1), (1, 1)],  // S
+        4 => [(0, 0), (1, 0), (0, 1), (-1, 1)],  // Z
+        5 => [(0, 0), (-1, 0), (1, 0), (-1, 1)], // J
+        6 => [(0, 0), (-1, 0), (1, 0), (1, 1)],  // L
+        _ => unreachable!(),
+    };
+    blocks.iter().map(|&(x, y)| match rotation.rem_euclid(4) {
+        0 => (x, y),
+        1 => (y, -x),
+        2 => (-x, -y),
+        _ => (-y, x),
+    }).collect()
+}
+
+/// The SRS center `(x, y)` of the shape placed at `base`, `y` is counted from the bottom
+/// row of the field of the given `height`
+pub fn srs_center(shape_idx: usize, base: &Point, rotation: i8, height: usize) -> (i32, i32) {
+    let offset = srs_center_offset(shape_idx, rotation);
+    (base.1 + offset.1, height as i32 - 1 - (base.0 + offset.0))
+}
+
+/// The inverse of `srs_center`
+pub fn base_from_srs_center(shape_idx: usize, center: (i32, i32), rotation: i8, height: usize) -> Point {
+    let offset = srs_center_offset(shape_idx, rotation);
+    Point(height as i32 - 1 - center.1 - offset.0, center.0 - offset.1)
+}
+
+fn srs_center_offset(shape_idx: usize, rotation: i8) -> Point {
+    let diffs = rotated(shape_idx, rotation);
+    // the blocks in our coordinates: the row goes down
+    let blocks: Vec<Point> = srs_blocks(shape_idx, rotation).iter()
+        .map(|&(x, y)| Point(-y, x))
+        .collect();
+    diffs.iter()
+        .map(|d| Point(d.0 - blocks[0].0, d.1 - blocks[0].1))
+        .find(|c| blocks.iter().all(|b| diffs.contains(&Point(c.0 + b.0, c.1 + b.1))))
+        .expect("SRS blocks must match the shape")
 }
 
 impl fmt::Display for GameState {
@@ -0,0 +1,279 @@
+//! Reachable placements of the current shape
+//!
+//! The breadth-first search over the positions `(base, rotation)` of the current
+//! shape, the edges are `Left`, `Right`, `DasLeft`, `DasRight`, `Down`, `RotateCW`
+//! and `RotateCCW` (with the wall kicks), so the tucks, the slides and the spins are found.
+//! Every position ends with `HardDrop`, and the placements are deduplicated by
+//! the locked cells and the T-spin kind, keeping the shortest input path.
+//!
+//! # Example
+//!
+//! ```
+//! use tetris::model::{GameState, Action};
+//! use tetris::movegen::find_placements;
+//! let gs = GameState::initial(22, 10, Default::default(), Some(1));
+//! let placements = find_placements(&gs);
+//! assert!(placements.iter().all(|p| p.path.last() == Some(&Action::HardDrop)));
+//! ```
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet};
+use crate::agent::DQNAction;
+use crate::bitboard::{BitField, ShapeMask, SHAPE_MASKS};
+use crate::model::{GameState, Point, Action, TSpin, ROTATIONS, WALL_KICKS_I, WALL_KICKS_X, try_shape_idx, detect_tspin};
+
+/// - `action`: the locked position, `base` is the final one, so `TetrisEnv::step`
+///   reproduces the placement including the T-spin
+/// - `cells`: the locked cells, sorted
+/// - `tspin`: the T-spin of the lock, see `detect_tspin`
+/// - `path`: the shortest input sequence from the current position, ends with `HardDrop`
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Placement {
+    pub action: DQNAction,
+    pub cells: Vec<Point>,
+    pub tspin: TSpin,
+    pub path: Vec<Action>,
+}
+
+/// The position of the shape, `last_kick` matters for T only
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy)]
+struct Node {
+    base: Point,
+    rotation: i8,
+    last_kick: Option<usize>,
+}
+
+/// The node with the cost of the best path to it and the last step of that path,
+/// `done` is set when the node is taken from the queue
+struct Visit {
+    node: Node,
+    cost: (u32, u32),
+    parent: Option<(usize, Action)>,
+    done: bool,
+}
+
+/// The dense index of the nodes of the field, the cache of the landing rows and
+/// the nodes already locked, the bases of the nodes are within `margin` of the field
+/// as the shape fits there
+struct NodeIndex {
+    slots: Vec<u32>,
+    locked: Vec<bool>,
+    landings: Vec<i32>,
+    margin: i32,
+    rows: usize,
+    cols: usize,
+    kicks: usize,
+}
+
+impl NodeIndex {
+    const NONE: u32 = u32::MAX;
+    const UNKNOWN: i32 = i32::MIN;
+
+    fn new(bits: &BitField, kicks: usize) -> NodeIndex {
+        let margin = ROTATIONS.iter().flatten().flatten().map(|p| p.0.abs().max(p.1.abs())).max().unwrap();
+        let rows = bits.height + 2 * margin as usize;
+        let cols = bits.width + 2 * margin as usize;
+        NodeIndex {
+            slots: vec![NodeIndex::NONE; 4 * rows * cols * kicks],
+            locked: vec![false; 4 * rows * cols * kicks],
+            landings: vec![NodeIndex::UNKNOWN; 4 * rows * cols],
+            margin,
+            rows,
+            cols,
+            kicks,
+        }
+    }
+
+    fn position(&self, base: &Point, rotation: i8) -> usize {
+        let i = (base.0 + self.margin) as usize;
+        let j = (base.1 + self.margin) as usize;
+        (rotation as usize * self.rows + i) * self.cols + j
+    }
+
+    fn slot(&self, node: &Node) -> usize {
+        let kick = node.last_kick.map_or(0, |k| k + 1);
+        self.position(&node.base, node.rotation) * self.kicks + kick
+    }
+
+    /// The row of the base, where the shape dropped from `(base, rotation)` lands,
+    /// the landing is remembered for all the rows on the way down
+    fn landing(&mut self, bits: &BitField, mask: &ShapeMask, base: &Point, rotation: i8) -> i32 {
+        let mut i = base.0;
+        let landing = loop {
+            let known = self.landings[self.position(&Point(i, base.1), rotation)];
+            if known != NodeIndex::UNKNOWN {
+                break known;
+            }
+            if !bits.fits_mask(mask, &Point(i + 1, base.1)) {
+                break i;
+            }
+            i += 1;
+        };
+        for k in base.0..=i {
+            let position = self.position(&Point(k, base.1), rotation);
+            self.landings[position] = landing;
+        }
+        landing
+    }
+}
+
+/// The locked cells as the key, the cells `i * width + j` are packed by 16 bits in the order
+fn cells_mask(cells: &[Point; 4], width: usize) -> u64 {
+    cells.iter().fold(0, |mask, p| mask << 16 | (p.0 as u64 * width as u64 + p.1 as u64))
+}
+
+/// All the placements of the current shape of `gs`, the hold is not considered
+pub fn find_placements(gs: &GameState) -> Vec<Placement> {
+    find_placements_on(gs, &BitField::from(&gs.field))
+}
+
+/// The same as `find_placements`, `bits` is the `BitField` of `gs.field`,
+/// so the positions on the same field (before and after the hold) share it
+pub fn find_placements_on(gs: &GameState, bits: &BitField) -> Vec<Placement> {
+    if gs.game_over {
+        return vec![];
+    }
+    search(bits, gs.curr_shape_idx, gs.base, gs.rotation, gs.last_kick, false)
+}
+
+/// The placements of the shape from the position `(base, rotation)`. The cost of the path
+/// is the number of the inputs, but when `free_down` is set the cost is the pair of the
+/// number of the other inputs and the number of `Down` (as the gravity does the same),
+/// so the paths have the minimal number of the other inputs, see `finesse`.
+/// The nodes are indexed densely by `(base, rotation, last_kick)` and the placements
+/// are deduplicated by the mask of their cells, the cells are collected for the new ones only.
+pub(crate) fn search(bits: &BitField, shape_idx: usize, base: Point, rotation: i8, last_kick: Option<usize>, free_down: bool) -> Vec<Placement> {
+    if !bits.fits(shape_idx, &base, rotation) {
+        return vec![];
+    }
+    let is_t = shape_idx == 2;
+    // the rotations of O change nothing
+    let actions: &[Action] = if shape_idx == 1 {
+        &[Action::Left, Action::Right, Action::Down, Action::DasLeft, Action::DasRight]
+    } else {
+        &[Action::Left, Action::Right, Action::Down, Action::RotateCW, Action::RotateCCW,
+          Action::DasLeft, Action::DasRight]
+    };
+    let start = Node {
+        base,
+        rotation: rotation.rem_euclid(4),
+        last_kick: if is_t { last_kick } else { None },
+    };
+    let masks = &SHAPE_MASKS[shape_idx];
+    // the wall kick tests from every rotation, clockwise and counterclockwise
+    let kick_table = if shape_idx == 0 { &*WALL_KICKS_I } else { &*WALL_KICKS_X };
+    let kick_tests: Vec<[&[Point]; 2]> = (0..4)
+        .map(|r| [&kick_table[&(r, (r + 1) % 4)][..], &kick_table[&(r, (r + 3) % 4)][..]])
+        .collect();
+    // no kick and the 5 kicks for T
+    let mut index = NodeIndex::new(bits, if is_t { 6 } else { 1 });
+    // the ties in the queue are broken by the index, so the search is deterministic
+    let mut visits = vec![Visit { node: start, cost: (0, 0), parent: None, done: false }];
+    let start_slot = index.slot(&start);
+    index.slots[start_slot] = 0;
+    let mut queue = BinaryHeap::new();
+    queue.push(Reverse(((0, 0), 0)));
+    let mut placements: Vec<Placement> = vec![];
+    let mut seen: HashSet<(u64, TSpin)> = HashSet::new();
+    while let Some(Reverse((cost, idx))) = queue.pop() {
+        if visits[idx].cost < cost || visits[idx].done {
+            continue;
+        }
+        visits[idx].done = true;
+        let node = visits[idx].node;
+        // the hard drop from here, the nodes are visited by the path cost,
+        // so the first path to the placement is the cheapest one
+        let mask = &masks[node.rotation as usize];
+        let i = index.landing(bits, mask, &node.base, node.rotation);
+        let base = Point(i, node.base.1);
+        let last_kick = if i == node.base.0 { node.last_kick } else { None };
+        let landed = index.slot(&Node { base, rotation: node.rotation, last_kick });
+        // the other nodes landing the same way lock the same cells
+        if !index.locked[landed] {
+            index.locked[landed] = true;
+            let tspin = detect_tspin(bits, shape_idx, &base, node.rotation, last_kick);
+            let mut cells = try_shape_idx(bits, &base, node.rotation, shape_idx).unwrap();
+            cells.sort_by_key(|p| (p.0, p.1));
+            if seen.insert((cells_mask(&cells, bits.width), tspin)) {
+                let mut path = vec![Action::HardDrop];
+                let mut k = idx;
+                while let Some((parent, action)) = visits[k].parent {
+                    path.push(action);
+                    k = parent;
+                }
+                path.reverse();
+                placements.push(Placement {
+                    action: DQNAction { base, rotation: node.rotation, hold: false, last_kick },
+                    cells: cells.to_vec(),
+                    tspin,
+                    path,
+                });
+            }
+        }
+        for action in actions {
+            let next = match action {
+                Action::Left | Action::Right | Action::Down => {
+                    let (di, dj) = match action {
+                        Action::Left => (0, -1),
+                        Action::Right => (0, 1),
+                        _ => (1, 0),
+                    };
+                    let base = Point(node.base.0 + di, node.base.1 + dj);
+                    if bits.fits_mask(mask, &base) {
+                        Some(Node { base, rotation: node.rotation, last_kick: None })
+                    } else {
+                        None
+                    }
+                }
+                // the shift by one column is found by `Left` and `Right` already
+                Action::DasLeft | Action::DasRight => {
+                    let dj = if *action == Action::DasLeft { -1 } else { 1 };
+                    let mut base = node.base;
+                    while bits.fits_mask(mask, &Point(base.0, base.1 + dj)) {
+                        base.1 += dj;
+                    }
+                    if (base.1 - node.base.1).abs() > 1 {
+                        Some(Node { base, rotation: node.rotation, last_kick: None })
+                    } else {
+                        None
+                    }
+                }
+                _ => {
+                    // the same as `BitField::try_wall_kick`
+                    let (rotation, tests) = if *action == Action::RotateCW {
+                        ((node.rotation + 1) % 4, kick_tests[node.rotation as usize][0])
+                    } else {
+                        ((node.rotation + 3) % 4, kick_tests[node.rotation as usize][1])
+                    };
+                    tests.iter().enumerate().find_map(|(kick, t)| {
+                        let base = Point(node.base.0 + t.0, node.base.1 + t.1);
+                        if bits.fits_mask(&masks[rotation as usize], &base) {
+                            Some(Node { base, rotation, last_kick: if is_t { Some(kick) } else { None } })
+                        } else {
+                            None
+                        }
+                    })
+                }
+            };
+            if let Some(next) = next {
+                let next_cost = if free_down && *action == Action::Down {
+                    (cost.0, cost.1 + 1)
+                } else {
+                    (cost.0 + 1, cost.1)
+                };
+                let slot = index.slot(&next);
+                if index.slots[slot] == NodeIndex::NONE {
+                    index.slots[slot] = visits.len() as u32;
+                    visits.push(Visit { node: next, cost: (u32::MAX, u32::MAX), parent: None, done: false });
+                }
+                let next_idx = index.slots[slot] as usize;
+                if next_cost < visits[next_idx].cost {
+                    visits[next_idx].cost = next_cost;
+                    visits[next_idx].parent = Some((idx, *action));
+                    queue.push(Reverse((next_cost, next_idx)));
+                }
+            }
+        }
+    }
+    placements
+}
@@ -0,0 +1,138 @@
+//! Rewards of the environment
+//!
+//! `TetrisEnv` calls its `RewardFn` after every placement with the states before and after it.
+//! The built-in terms are `Reward`, `Rewards` is their sum with the features resolved by
+//! the names, so the rewards are configured in `AgentConf::reward` without the code changes.
+//!
+//! # Example
+//!
+//! ```
+//! use std::sync::Arc;
+//! use tetris::reward::{Reward, Rewards};
+//! use tetris::train::TetrisEnv;
+//! let mut env = TetrisEnv::new(Some(1));
+//! let rewards = Rewards::new(&[Reward::LinesSquared, Reward::Survival { bonus: 1.0 }]).unwrap();
+//! env.reward = Arc::new(rewards);
+//! env.reset();
+//! let action = env.get_valid_actions()[0];
+//! let (_, reward, done) = env.step(action);
+//! assert_eq!((reward, done), (1.0, false));
+//! ```
+
+use std::fmt;
+use failure::{format_err, Fallible};
+use serde::{Serialize, Deserialize};
+use crate::features::{self, Board, FeatureExtractor};
+use crate::model::{ClearInfo, GameState};
+
+pub trait RewardFn: fmt::Debug + Send + Sync {
+    /// `clear` - the lines burnt by the placement, see `StepResult::clear`
+    fn reward(&self, before: &GameState, after: &GameState, clear: &ClearInfo) -> f32;
+}
+
+/// - `ScoreDelta`: the score of the placement, see `config::Scoring`
+/// - `LinesSquared`: the square of the number of the burnt lines
+/// - `Survival`: `bonus` for every placement not ending the game
+/// - `GameOver`: `-penalty` for the placement ending the game
+/// - `Penalty`: `-weight * feature` of the field after the placement, the feature is
+///   one of `features::all` by its name, e.g. "holes" or "max_height"
+/// - `Potential`: the potential-based shaping (Ng et al.) `discount * phi(after) - phi(before)`,
+///   where `phi` is `-sum(weight * feature)` and 0 after the game over, it doesn't change
+///   the optimal policy, if `discount` is the one of the agent
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Reward {
+    ScoreDelta,
+    LinesSquared,
+    Survival { bonus: f32 },
+    GameOver { penalty: f32 },
+    Penalty { feature: String, weight: f32 },
+    Potential { features: Vec<(String, f32)>, discount: f32 },
+}
+
+/// The sum of the rewards, the features of every term are resolved once
+pub struct Rewards {
+    terms: Vec<Term>,
+}
+
+struct Term {
+    reward: Reward,
+    features: Vec<(Box<dyn FeatureExtractor>, f32)>,
+}
+
+impl Rewards {
+    /// Fails on the unknown feature names
+    pub fn new(rewards: &[Reward]) -> Fallible<Rewards> {
+        let terms = rewards.iter()
+            .map(|reward| {
+                let named: Vec<(&str, f32)> = match reward {
+                    Reward::Penalty { feature, weight } => vec![(feature.as_str(), *weight)],
+                    Reward::Potential { features, .. } => features.iter().map(|(name, w)| (name.as_str(), *w)).collect(),
+                    _ => vec![],
+                };
+                let features = named.into_iter()
+                    .map(|(name, weight)| {
+                        let feature = features::by_name(name).ok_or_else(|| format_err!("unknown feature {}", name))?;
+                        Ok((feature, weight))
+                    })
+                    .collect::<Fallible<_>>()?;
+                Ok(Term { reward: reward.clone(), features })
+            })
+            .collect::<Fallible<_>>()?;
+        Ok(Rewards { terms })
+    }
+}
+
+/// The score delta, the default of `AgentConf::reward`
+impl Default for Rewards {
+    fn default() -> Rewards {
+        Rewards { terms: vec![Term { reward: Reward::ScoreDelta, features: vec![] }] }
+    }
+}
+
+impl fmt::Debug for Rewards {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.terms.iter().map(|term| &term.reward)).finish()
+    }
+}
+
+impl RewardFn for Rewards {
+    fn reward(&self, before: &GameState, after: &GameState, clear: &ClearInfo) -> f32 {
+        self.terms.iter().map(|term| term.reward(before, after, clear)).sum()
+    }
+}
+
+impl Term {
+    fn reward(&self, before: &GameState, after: &GameState, clear: &ClearInfo) -> f32 {
+        match &self.reward {
+            Reward::ScoreDelta => after.score as f32 - before.score as f32,
+            Reward::LinesSquared => (clear.lines_burnt * clear.lines_burnt) as f32,
+            Reward::Survival { bonus } => if after.game_over { 0.0 } else { *bonus },
+            Reward::GameOver { penalty } => if after.game_over { -*penalty } else { 0.0 },
+            Reward::Penalty { .. } => -self.weighted_features(after),
+            Reward::Potential { discount, .. } => {
+                let potential = |gs: &GameState| if gs.game_over { 0.0 } else { -self.weighted_features(gs) };
+                discount * potential(after) - potential(before)
+            }
+        }
+    }
+
+    /// `sum(weight * feature)` of the field of `gs`
+    fn weighted_features(&self, gs: &GameState) -> f32 {
+        let board = Board::borrowed(&gs.field);
+        let mut values = Vec::with_capacity(1);
+        self.features.iter()
+            .map(|(feature, weight)| {
+                values.clear();
+                feature.extract_into(&board, &mut values);
+                weight * values[0]
+            })
+            .sum()
+    }
+}
+
+/// The sum of the rewards
+impl<R: RewardFn> RewardFn for Vec<R> {
+    fn reward(&self, before: &GameState, after: &GameState, clear: &ClearInfo) -> f32 {
+        self.iter().map(|r| r.reward(before, after, clear)).sum()
+    }
+}
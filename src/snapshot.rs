@@ -0,0 +1,115 @@
+//! Save and restore the game in progress
+//!
+//! The snapshot contains everything needed to continue the game exactly:
+//! the field, the active piece, the preview, the bag contents `rng_queue`,
+//! the score and the state of `Xoshiro512StarStar`. Two formats are supported,
+//! JSON (readable, good for sharing the positions) and the compact binary one.
+//!
+//! # Example
+//!
+//! ```
+//! use tetris::model::GameState;
+//! let gs = GameState::initial(22, 10, Default::default(), Some(42));
+//! let json = gs.to_json().unwrap();
+//! let restored = GameState::from_json(&json).unwrap();
+//! assert_eq!(restored.next_shape_idxs, gs.next_shape_idxs);
+//! ```
+
+use std::fs;
+use std::path::Path;
+use failure::{bail, Fallible};
+use serde::{Serialize, Deserialize};
+use crate::model::{GameState, Point};
+use crate::tetrimino::TETRIMINOES;
+
+/// Increment it when the layout of `GameState` changes
+pub const SNAPSHOT_VERSION: u32 = 4;
+
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    version: u32,
+    state: &'a GameState,
+}
+
+#[derive(Deserialize)]
+struct Snapshot {
+    version: u32,
+    state: GameState,
+}
+
+impl Snapshot {
+    /// The state, if it keeps what the game relies on: the field of its size,
+    /// the known shapes, the non-empty preview and the current piece inside the field
+    fn into_state(self) -> Fallible<GameState> {
+        if self.version != SNAPSHOT_VERSION {
+            bail!("unsupported snapshot version {}, expected {}", self.version, SNAPSHOT_VERSION);
+        }
+        let gs = self.state;
+        let field = &gs.field;
+        if field.cells.len() != field.height || field.cells.iter().any(|row| row.len() != field.width) {
+            bail!("the field cells don't match its size {}x{}", field.height, field.width);
+        }
+        let shapes = TETRIMINOES.len();
+        if gs.curr_shape_idx >= shapes {
+            bail!("unknown current shape {}", gs.curr_shape_idx);
+        }
+        if gs.next_shape_idxs.is_empty() {
+            bail!("the preview is empty");
+        }
+        let mut queued = gs.next_shape_idxs.iter().chain(&gs.hold_shape_idx).chain(&gs.rng_queue);
+        if let Some(idx) = queued.find(|idx| **idx >= shapes) {
+            bail!("unknown shape {} in the preview, the hold or the bag", idx);
+        }
+        let outside = |p: &&Point| p.0 < 0 || p.0 >= field.height as i32 || p.1 < 0 || p.1 >= field.width as i32;
+        if let Some(p) = gs.curr_cells.iter().find(outside) {
+            bail!("the current piece is outside the field at {:?}", p);
+        }
+        Ok(gs)
+    }
+}
+
+impl GameState {
+    pub fn to_json(&self) -> Fallible<String> {
+        let snapshot = SnapshotRef { version: SNAPSHOT_VERSION, state: self };
+        Ok(serde_json::to_string_pretty(&snapshot)?)
+    }
+
+    pub fn from_json(src: &str) -> Fallible<GameState> {
+        let snapshot: Snapshot = serde_json::from_str(src)?;
+        snapshot.into_state()
+    }
+
+    pub fn to_bytes(&self) -> Fallible<Vec<u8>> {
+        let snapshot = SnapshotRef { version: SNAPSHOT_VERSION, state: self };
+        Ok(bincode::serialize(&snapshot)?)
+    }
+
+    pub fn from_bytes(src: &[u8]) -> Fallible<GameState> {
+        let snapshot: Snapshot = bincode::deserialize(src)?;
+        snapshot.into_state()
+    }
+
+    /// The format is chosen by the extension: `.json` or binary otherwise
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Fallible<()> {
+        let path = path.as_ref();
+        if is_json(path) {
+            fs::write(path, self.to_json()?)?;
+        } else {
+            fs::write(path, self.to_bytes()?)?;
+        }
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Fallible<GameState> {
+        let path = path.as_ref();
+        if is_json(path) {
+            GameState::from_json(&fs::read_to_string(path)?)
+        } else {
+            GameState::from_bytes(&fs::read(path)?)
+        }
+    }
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "json")
+}
@@ -0,0 +1,342 @@
+//! Tetris Bot Protocol front-end
+//!
+//! The bot talks to the front-end (a GUI or a match runner) with JSON messages,
+//! one per line on stdin/stdout, see https://github.com/tetris-bot-protocol/tbp-spec.
+//! The front-end sends `rules`, `start` with the board and the queue, then
+//! `suggest`, `play` and `new_piece` as the game goes, the bot answers
+//! `suggestion` with the moves produced by the `Agent`.
+//!
+//! # Example
+//!
+//! ```
+//! use tetris::tbp::{TbpBot, BotMessage};
+//! use tetris::agent::DQNAgent;
+//! let mut bot = TbpBot::new(DQNAgent::new(Some(1)));
+//! bot.handle_line(r#"{"type": "start", "hold": null, "queue": ["T", "I"],
+//!     "combo": 0, "back_to_back": false, "board": []}"#).unwrap();
+//! match bot.handle_line(r#"{"type": "suggest"}"#).unwrap() {
+//!     Some(BotMessage::Suggestion { moves }) => assert!(!moves.is_empty()),
+//!     _ => unreachable!(),
+//! }
+//! ```
+
+use std::collections::VecDeque;
+use std::io::{BufRead, Write};
+use failure::Fallible;
+use serde::{Serialize, Deserialize};
+use crate::agent::{Agent, DQNAction};
+use crate::config::{Config, Randomness};
+use crate::model::{GameState, Field, Point, Action, TSpin, GARBAGE, CELL_SYMBOLS};
+use crate::model::{detect_tspin, try_shape_idx, srs_center, base_from_srs_center};
+use crate::train::TetrisEnv;
+
+/// TBP boards are 40 rows of 10 cells, the pieces spawn in the top rows
+pub const BOARD_HEIGHT: usize = 40;
+pub const BOARD_WIDTH: usize = 10;
+
+/// The pieces in the order of `TETRIMINOES`, so `piece as usize` is the shape index
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum Piece {
+    I, O, T, S, Z, J, L,
+}
+
+const PIECES: [Piece; 7] = [Piece::I, Piece::O, Piece::T, Piece::S, Piece::Z, Piece::J, Piece::L];
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Orientation {
+    North, East, South, West,
+}
+
+const ORIENTATIONS: [Orientation; 4] = [Orientation::North, Orientation::East, Orientation::South, Orientation::West];
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Spin {
+    None, Mini, Full,
+}
+
+/// `x`, `y` - the SRS center of the piece, `y` goes up from the bottom row
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub struct PieceLocation {
+    #[serde(rename = "type")]
+    pub piece: Piece,
+    pub orientation: Orientation,
+    pub x: i32,
+    pub y: i32,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub struct Move {
+    pub location: PieceLocation,
+    pub spin: Spin,
+}
+
+/// `board` - rows from the bottom, the cells are `null`, piece letters or `"G"` for garbage
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Start {
+    pub hold: Option<Piece>,
+    pub queue: Vec<Piece>,
+    pub combo: u32,
+    pub back_to_back: bool,
+    pub board: Vec<Vec<Option<char>>>,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FrontendMessage {
+    Rules,
+    Start(Start),
+    Stop,
+    Suggest,
+    Play {
+        #[serde(rename = "move")]
+        mv: Move,
+    },
+    NewPiece {
+        piece: Piece,
+    },
+    Quit,
+    /// The messages of the later protocol versions are ignored
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BotMessage {
+    Info {
+        name: String,
+        version: String,
+        author: String,
+        features: Vec<String>,
+    },
+    Ready,
+    Error {
+        reason: String,
+    },
+    Suggestion {
+        moves: Vec<Move>,
+    },
+}
+
+/// The bot state between the messages: `env` holds the board, the hold slot and
+/// the chains, its current shape is `queue[0]` and the preview is the rest of `queue`
+pub struct TbpBot<A: Agent> {
+    pub agent: A,
+    pub env: Option<TetrisEnv>,
+    pub queue: VecDeque<usize>,
+}
+
+impl<A: Agent> TbpBot<A> {
+    pub fn new(agent: A) -> TbpBot<A> {
+        TbpBot { agent, env: None, queue: VecDeque::new() }
+    }
+
+    pub fn info() -> BotMessage {
+        BotMessage::Info {
+            name: env!("CARGO_PKG_NAME").into(),
+            version: env!("CARGO_PKG_VERSION").into(),
+            author: env!("CARGO_PKG_AUTHORS").into(),
+            features: vec![],
+        }
+    }
+
+    /// Parse the message and handle it, the unknown messages are ignored
+    pub fn handle_line(&mut self, line: &str) -> Fallible<Option<BotMessage>> {
+        let message: FrontendMessage = serde_json::from_str(line)?;
+        Ok(self.handle(message))
+    }
+
+    pub fn handle(&mut self, message: FrontendMessage) -> Option<BotMessage> {
+        match message {
+            // the agents don't rely on the randomizer
+            FrontendMessage::Rules => Some(BotMessage::Ready),
+            FrontendMessage::Start(start) => {
+                self.start(&start);
+                None
+            }
+            FrontendMessage::Stop => {
+                self.env = None;
+                self.queue.clear();
+                None
+            }
+            FrontendMessage::Suggest => Some(BotMessage::Suggestion { moves: self.suggest() }),
+            FrontendMessage::Play { mv } => {
+                self.play(&mv);
+                None
+            }
+            FrontendMessage::NewPiece { piece } => {
+                self.queue.push_back(piece as usize);
+                self.spawn();
+                None
+            }
+            FrontendMessage::Quit | FrontendMessage::Unknown => None,
+        }
+    }
+
+    fn start(&mut self, start: &Start) {
+        let config = Config {
+            randomness: Randomness::ShuffledQueue,
+            hold_enabled: true,
+            ..Default::default()
+        };
+        let mut gs = GameState::initial(BOARD_HEIGHT, BOARD_WIDTH, config, None);
+        gs.field = board_to_field(&start.board);
+        gs.hold_shape_idx = start.hold.map(|p| p as usize);
+        gs.combo = start.combo;
+        gs.back_to_back = start.back_to_back;
+        self.queue = start.queue.iter().map(|p| *p as usize).collect();
+        self.env = Some(TetrisEnv::from_state(gs));
+        self.agent.reset();
+        self.spawn();
+    }
+
+    /// The moves of the agent from the best to the worst, empty if there is no piece
+    pub fn suggest(&mut self) -> Vec<Move> {
+        let env = match self.env.as_ref() {
+            Some(env) if !self.queue.is_empty() && !env.gs.game_over => env,
+            _ => return vec![],
+        };
+        // with the empty hold slot the hold brings the next piece, it may be not known yet
+        let hold_known = env.gs.hold_shape_idx.is_some() || self.queue.len() > 1;
+        let mut moves = vec![];
+        for action in self.agent.rank_actions(env) {
+            if action.hold && !hold_known {
+                continue;
+            }
+            let mv = to_move(&env.gs, &action);
+            if !moves.contains(&mv) {
+                moves.push(mv);
+            }
+        }
+        moves
+    }
+
+    /// Apply the move, the held piece is determined by the piece type of the move
+    pub fn play(&mut self, mv: &Move) {
+        let (env, current) = match (self.env.as_mut(), self.queue.pop_front()) {
+            (Some(env), Some(current)) => (env, current),
+            _ => return,
+        };
+        let gs = &mut env.gs;
+        let shape_idx = mv.location.piece as usize;
+        if shape_idx != current {
+            if gs.hold_shape_idx.is_none() {
+                // the next piece from the queue goes to play
+                self.queue.pop_front();
+            }
+            gs.hold_shape_idx = Some(current);
+        }
+        let rotation = mv.location.orientation as i8;
+        let base = base_from_srs_center(shape_idx, (mv.location.x, mv.location.y), rotation, BOARD_HEIGHT);
+        gs.curr_shape_idx = shape_idx;
+        if let Some(cells) = gs.try_current_shape(&base, rotation) {
+            gs.base = base;
+            gs.rotation = rotation;
+            gs.set_curr_cells(&cells);
+        }
+        // the front-end has seen the rotation, the lock applies the 3-corner rule,
+        // the last kick test makes it the full T-spin anyway
+        gs.last_kick = match mv.spin {
+            Spin::None => None,
+            Spin::Mini => Some(0),
+            Spin::Full => Some(4),
+        };
+        env.clear = gs.step(Action::HardDrop).clear.unwrap_or_default();
+        env.lines_burnt = env.clear.lines_burnt;
+        self.spawn();
+    }
+
+    /// Put `queue[0]` to play and the rest of `queue` to the preview, the spawns
+    /// after the preview take the pieces from the randomizer of `GameState`,
+    /// so the agents can look ahead
+    fn spawn(&mut self) {
+        let env = match self.env.as_mut() {
+            Some(env) => env,
+            None => return,
+        };
+        let gs = &mut env.gs;
+        gs.next_shape_idxs = self.queue.iter().skip(1).cloned().collect();
+        if let Some(&shape_idx) = self.queue.front() {
+            gs.spawn_shape(shape_idx);
+            gs.hold_used = false;
+        }
+    }
+}
+
+/// Handle the messages from `input` until `quit` or the end of the input,
+/// the malformed messages are reported to stderr and skipped
+pub fn run<A: Agent, R: BufRead, W: Write>(bot: &mut TbpBot<A>, input: R, mut output: W) -> Fallible<()> {
+    writeln!(output, "{}", serde_json::to_string(&TbpBot::<A>::info())?)?;
+    output.flush()?;
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let message: FrontendMessage = match serde_json::from_str(&line) {
+            Ok(message) => message,
+            Err(err) => {
+                eprintln!("skipping the malformed message {}: {}", line.trim(), err);
+                continue;
+            }
+        };
+        let quit = message == FrontendMessage::Quit;
+        if let Some(reply) = bot.handle(message) {
+            writeln!(output, "{}", serde_json::to_string(&reply)?)?;
+            output.flush()?;
+        }
+        if quit {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// The move of the placement in `gs` with the T-spin of the lock, see `detect_tspin`
+pub fn to_move(gs: &GameState, action: &DQNAction) -> Move {
+    let shape_idx = match (action.hold, gs.hold_shape_idx) {
+        (false, _) => gs.curr_shape_idx,
+        (true, Some(hold_shape_idx)) => hold_shape_idx,
+        (true, None) => gs.next_shape_idxs[0],
+    };
+    let mut base = action.base;
+    while try_shape_idx(&gs.field, &Point(base.0 + 1, base.1), action.rotation, shape_idx).is_some() {
+        base.0 += 1;
+    }
+    // the drop cancels the spin
+    let last_kick = if base == action.base { action.last_kick } else { None };
+    let spin = match detect_tspin(&gs.field, shape_idx, &base, action.rotation, last_kick) {
+        TSpin::None => Spin::None,
+        TSpin::Mini => Spin::Mini,
+        TSpin::Full => Spin::Full,
+    };
+    Move { location: to_location(shape_idx, &base, action.rotation), spin }
+}
+
+pub fn to_location(shape_idx: usize, base: &Point, rotation: i8) -> PieceLocation {
+    let (x, y) = srs_center(shape_idx, base, rotation, BOARD_HEIGHT);
+    PieceLocation {
+        piece: PIECES[shape_idx],
+        orientation: ORIENTATIONS[rotation.rem_euclid(4) as usize],
+        x,
+        y,
+    }
+}
+
+/// The rows above `BOARD_HEIGHT` are ignored
+pub fn board_to_field(board: &[Vec<Option<char>>]) -> Field {
+    let mut cells = vec![vec![0; BOARD_WIDTH]; BOARD_HEIGHT];
+    for (y, row) in board.iter().take(BOARD_HEIGHT).enumerate() {
+        for (x, cell) in row.iter().take(BOARD_WIDTH).enumerate() {
+            cells[BOARD_HEIGHT - 1 - y][x] = match cell {
+                None => 0,
+                Some(c) => CELL_SYMBOLS[1..=PIECES.len()].iter()
+                    .position(|s| s == c)
+                    .map_or(GARBAGE, |idx| idx as u8 + 1),
+            };
+        }
+    }
+    Field { cells, height: BOARD_HEIGHT, width: BOARD_WIDTH }
+}
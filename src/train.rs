@@ -1,239 +1,473 @@
-use crate::agent::{DQNAgent, DQNState, DQNAction, AgentConf, DQNTransition};
-use crate::model::{GameState, rotate, Action, Point, is_valid, try_shape};
-use crate::tetrimino::TETRIMINOES;
+use std::borrow::Cow;
 use std::collections::VecDeque;
-use rand_xoshiro::Xoshiro512StarStar;
-use rand::{SeedableRng, Rng};
+use std::path::Path;
+use std::sync::Arc;
+use crate::agent::{Agent, DQNAgent, DQNState, DQNAction, DQNTransition, N_FEATURES};
+use crate::bitboard::BitField;
+use crate::checkpoint::{self, Progress};
+use crate::model::{GameState, ClearInfo, Action, TSpin};
 use rand::prelude::SliceRandom;
-use crate::config::Config;
+use crate::config::{Config, TopOut};
+use crate::finesse::finesse_path;
+use crate::movegen::{Placement, find_placements_on};
+use crate::reward::{RewardFn, Rewards};
+use crate::tetrimino::TETRIMINOES;
+use serde::{Serialize, Deserialize};
+use tch::Tensor;
+
+/// What the agent sees of the environment, see `TetrisEnv::observe`
+/// - `Features`: `DQNState::features` of the current state
+/// - `Board`: the planes `[channels, height, width]` for the convolutional networks,
+///   see `TetrisEnv::board_observation`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ObservationMode {
+    Features,
+    Board,
+}
+
+/// The values in the row-major order with their shape
+#[derive(Debug, Clone, PartialEq)]
+pub struct Observation {
+    pub data: Vec<f32>,
+    pub shape: Vec<usize>,
+}
+
+impl Observation {
+    pub fn to_tensor(&self) -> Tensor {
+        let shape: Vec<i64> = self.shape.iter().map(|d| *d as i64).collect();
+        Tensor::of_slice(&self.data).view(&shape[..])
+    }
+}
 
 /// `lines_burnt` - how many lines has been burnt since
 /// the last state with the new action applied,
 /// essentially it is the part of the previous state
+/// `clear` - the outcome of the last step, the default one if no piece was locked
+/// `reward` - the reward of `step`, the score delta by default
 #[derive(Debug, Clone)]
 pub struct TetrisEnv {
     pub gs: GameState,
     pub lines_burnt: usize,
+    pub clear: ClearInfo,
+    pub reward: Arc<dyn RewardFn>,
 }
 
 impl TetrisEnv {
     pub fn new(seed: Option<u64>) -> TetrisEnv {
-        TetrisEnv {
-            gs: GameState::initial(22, 10, Config::default(), seed),
-            lines_burnt: 0,
-        }
+        TetrisEnv::with_config(Config::default(), seed)
+    }
+
+    /// The board size and the rules are taken from `config`, see `GameState::from_config`
+    pub fn with_config(config: Config, seed: Option<u64>) -> TetrisEnv {
+        TetrisEnv::from_state(GameState::from_config(config, seed))
+    }
+
+    pub fn from_state(gs: GameState) -> TetrisEnv {
+        TetrisEnv { gs, lines_burnt: 0, clear: ClearInfo::default(), reward: Arc::new(Rewards::default()) }
     }
 
     pub fn reset(&mut self) -> DQNState {
         self.gs.reset();
         self.lines_burnt = 0;
+        self.clear = ClearInfo::default();
         self.convert_to_dqn_state()
     }
 
+    /// Put the piece to the placement and hard drop it, if `config.timing` is set
+    /// the placement is played against the clock, see `play_timed`
     pub fn step(&mut self, dqn_action: DQNAction) -> (DQNState, f32, bool) {
+        let before = self.gs.clone();
+        let clear = self.play(&dqn_action);
+        self.finish_step(&before, clear)
+    }
+
+    fn play(&mut self, dqn_action: &DQNAction) -> Option<ClearInfo> {
         // note: dqn_action should be valid,
         // the last action in the sequence must be Action::HardDrop to get
         // the correct `lines_burnt` value
+        match self.gs.config.timing {
+            Some(timing) => self.play_timed(dqn_action, timing.input_delay),
+            None => self.play_instant(dqn_action),
+        }
+    }
+
+    fn play_instant(&mut self, dqn_action: &DQNAction) -> Option<ClearInfo> {
         let gs = &mut self.gs;
-        let old_score = gs.score;
+        if dqn_action.hold {
+            gs.step(Action::Hold);
+        }
         if let Some(cells) = gs.try_current_shape(&dqn_action.base, dqn_action.rotation) {
             gs.base = dqn_action.base;
             gs.rotation = dqn_action.rotation;
-            gs.curr_cells = cells;
+            gs.set_curr_cells(&cells);
+            gs.last_kick = dqn_action.last_kick;
         }
-        let (lines_burnt, _) = gs.step(Action::HardDrop);
-        self.lines_burnt = lines_burnt;
-        let reward = (gs.score - old_score) as f32;
-        (self.convert_to_dqn_state(), reward, self.gs.game_over)
+        gs.step(Action::HardDrop).clear
     }
 
-    pub fn get_valid_actions(&self) -> Vec<DQNAction> {
-        // called after the new piece spawn
-        let rotations = match self.gs.curr_shape_idx {
-            1 => vec![0], // O
-            0 | 3 | 4 => vec![0, 1], // I, S, Z
-            2 | 5 | 6 => vec![0, 1, 2, 3], // T, J, L
-            _ => unreachable!(),
+    /// Play the finesse inputs of the placement, the clock advances by `input_delay`
+    /// after every input, so the gravity and the lock delay may put the piece elsewhere
+    fn play_timed(&mut self, dqn_action: &DQNAction, input_delay: u32) -> Option<ClearInfo> {
+        let path = match finesse_path(&self.gs, dqn_action) {
+            Some(path) => path,
+            None => return self.play_instant(dqn_action),
         };
-        // in the worst case we have 4 rotations with each base, so the memory
-        let n = self.gs.field.width;
-        let mut valid_actions = Vec::with_capacity(2 * self.gs.field.width);
-        let mut j_shifts = Vec::with_capacity(self.gs.field.width);
-        for rotation in rotations {
-            j_shifts.clear();
-            let mut rotated_shape = rotate(&TETRIMINOES[self.gs.curr_shape_idx], rotation);
-            let mut gs_base = self.gs.base;
-            if rotation > 0 {
-                // we do the complex try_wall_kick_current_shape instead of
-                // just rotate, because some shapes can be rotated only with the shift down
-                if let Some((base, cells)) = self.gs.try_wall_kick_current_shape((rotation - 1, rotation)) {
-                    gs_base = base;
-                } else {
-                    // no more rotations is possible
-                    break;
-                }
+        for action in path {
+            let result = self.gs.step(action);
+            if result.clear.is_some() {
+                return result.clear;
             }
-            // initial position
-            {
-                let base = Point(gs_base.0, gs_base.1);
-                if is_valid(&self.gs.field, &base, &rotated_shape) {
-                    j_shifts.push(0 as i32);
-                }
+            let result = self.gs.advance(input_delay, false);
+            if result.clear.is_some() || result.game_over {
+                return result.clear;
             }
-            // left
-            for dj in 1..n {
-                let base = Point(gs_base.0, gs_base.1 - (dj as i32));
-                if is_valid(&self.gs.field, &base, &rotated_shape) {
-                    j_shifts.push(-(dj as i32));
-                } else {
-                    break;
+        }
+        None
+    }
+
+    /// Apply the inputs of the placement, unlike `step` the spins are scored
+    pub fn step_placement(&mut self, placement: &Placement) -> (DQNState, f32, bool) {
+        let before = self.gs.clone();
+        let mut clear = None;
+        for action in &placement.path {
+            clear = clear.or(self.gs.step(*action).clear);
+        }
+        self.finish_step(&before, clear)
+    }
+
+    /// Record the outcome of the step from the state `before` and compute the reward
+    fn finish_step(&mut self, before: &GameState, clear: Option<ClearInfo>) -> (DQNState, f32, bool) {
+        self.clear = clear.unwrap_or_default();
+        self.lines_burnt = self.clear.lines_burnt;
+        let reward = self.reward.reward(before, &self.gs, &self.clear);
+        (self.convert_to_dqn_state(), reward, self.gs.game_over)
+    }
+
+    pub fn observe(&self, mode: ObservationMode) -> Observation {
+        match mode {
+            ObservationMode::Features => Observation {
+                data: self.convert_to_dqn_state().features().to_vec(),
+                shape: vec![N_FEATURES],
+            },
+            ObservationMode::Board => self.board_observation(),
+        }
+    }
+
+    /// The planes of the field size, 0 or 1 in every cell:
+    /// - 0: the occupied cells
+    /// - 1: the cells of the active piece
+    /// - 2..9: the one-hot encoding of the current shape, the plane of the shape is all ones
+    /// - then 7 planes for each shape of the preview in the same way
+    pub fn board_observation(&self) -> Observation {
+        let gs = &self.gs;
+        let (height, width) = (gs.field.height, gs.field.width);
+        let plane = height * width;
+        let n_shapes = TETRIMINOES.len();
+        let channels = 2 + n_shapes * (1 + gs.next_shape_idxs.len());
+        let mut data = vec![0.0; channels * plane];
+        for (i, row) in gs.field.cells.iter().enumerate() {
+            for (j, cell) in row.iter().enumerate() {
+                if *cell != 0 {
+                    data[i * width + j] = 1.0;
                 }
             }
-            // right
-            for dj in 1..n {
-                let base = Point(gs_base.0, gs_base.1 + (dj as i32));
-                if is_valid(&self.gs.field, &base, &rotated_shape) {
-                    j_shifts.push(dj as i32);
-                } else {
-                    break;
-                }
+        }
+        if !gs.game_over {
+            for p in &gs.curr_cells {
+                data[plane + p.0 as usize * width + p.1 as usize] = 1.0;
+            }
+        }
+        let shapes = Some(gs.curr_shape_idx).into_iter().chain(gs.next_shape_idxs.iter().cloned());
+        for (k, shape_idx) in shapes.enumerate() {
+            let channel = 2 + k * n_shapes + shape_idx;
+            for x in &mut data[channel * plane..(channel + 1) * plane] {
+                *x = 1.0;
+            }
+        }
+        Observation { data, shape: vec![channels, height, width] }
+    }
+
+    /// The placements of `get_reachable_placements`, including the tucks and the spins
+    pub fn get_valid_actions(&self) -> Vec<DQNAction> {
+        self.get_reachable_placements()
+            .into_iter()
+            .map(|placement| placement.action)
+            .collect()
+    }
+
+    /// The valid actions with the states after them, the same order as in `get_valid_actions`.
+    /// The states are computed from the landing cells, see `afterstate`, the placements
+    /// are played with `config.timing` only, as the clock may put the piece elsewhere
+    pub fn get_next_states(&self) -> Vec<(DQNAction, DQNState)> {
+        if self.gs.config.timing.is_some() {
+            return self.get_next_transitions()
+                .into_iter()
+                .map(|(action, state, _, _)| (action, state))
+                .collect();
+        }
+        let bits = BitField::from(&self.gs.field);
+        let mut after = bits.clone();
+        let mut states = vec![];
+        for (gs, hold) in self.placement_states() {
+            let previews = gs.peek_next_shapes();
+            for placement in find_placements_on(&gs, &bits) {
+                let action = DQNAction { hold, ..placement.action };
+                let (state, _) = self.afterstate(&gs, &placement, &bits, &mut after, &previews);
+                states.push((action, state));
             }
-            // populate the actions
-            j_shifts.sort();
-            for dj in &j_shifts {
-                let base = Point(gs_base.0, gs_base.1 + *dj);
-                valid_actions.push(DQNAction { base, rotation });
+        }
+        states
+    }
+
+    /// The valid actions with the results of `step`, the rewards need the whole state
+    /// after the placement, so it is played on the copy of the env restored for every action
+    pub fn get_next_transitions(&self) -> Vec<(DQNAction, DQNState, f32, bool)> {
+        let mut env = self.clone();
+        self.get_valid_actions()
+            .into_iter()
+            .map(|action| {
+                env.gs.clone_from(&self.gs);
+                let clear = env.play(&action);
+                let (state, reward, done) = env.finish_step(&self.gs, clear);
+                (action, state, reward, done)
+            })
+            .collect()
+    }
+
+    /// Every legal placement including tucks and spins, see `movegen::find_placements`,
+    /// the placements after the hold have `Action::Hold` in the beginning of the path
+    pub fn get_reachable_placements(&self) -> Vec<Placement> {
+        let bits = BitField::from(&self.gs.field);
+        let mut placements = vec![];
+        for (gs, hold) in self.placement_states() {
+            placements.extend(find_placements_on(&gs, &bits).into_iter().map(|mut p| {
+                if hold {
+                    p.action.hold = true;
+                    p.path.insert(0, Action::Hold);
+                }
+                p
+            }));
+        }
+        placements
+    }
+
+    /// The states to place the current shape from, the current one and the one after
+    /// the hold (marked `true`), if the hold is allowed and does not end the game.
+    /// The field is the same in both. The cloned state has the same rng,
+    /// so the shape spawned after the hold is the same as in the subsequent `step`
+    fn placement_states(&self) -> Vec<(Cow<'_, GameState>, bool)> {
+        let mut states = vec![(Cow::Borrowed(&self.gs), false)];
+        if self.gs.config.hold_enabled && !self.gs.hold_used {
+            let mut gs = self.gs.clone();
+            gs.step(Action::Hold);
+            if !gs.game_over {
+                states.push((Cow::Owned(gs), true));
             }
         }
-        valid_actions
+        states
+    }
+
+    /// The state after `step` of the placement from `gs` (without the timing) and whether
+    /// the game is over, the placement is not played: the cells are put on `after`,
+    /// the copy of `bits`, the lines are burnt and the next shape spawns there,
+    /// `previews` are the ones of `GameState::peek_next_shapes`
+    fn afterstate(
+        &self,
+        gs: &GameState,
+        placement: &Placement,
+        bits: &BitField,
+        after: &mut BitField,
+        previews: &(VecDeque<usize>, VecDeque<usize>),
+    ) -> (DQNState, bool) {
+        after.rows.copy_from_slice(&bits.rows);
+        after.fill(&placement.cells);
+        let lines_burnt = after.burn_lines();
+        let locked_out = gs.config.top_out == TopOut::LockOut
+            && placement.cells.iter().all(|p| (p.0 as usize) < gs.config.hidden_rows);
+        // see `GameState::update_clear_info`
+        let (combo, back_to_back) = if lines_burnt > 0 {
+            (gs.combo + 1, lines_burnt == 4 || placement.tspin != TSpin::None)
+        } else {
+            (0, gs.back_to_back)
+        };
+        let (failed, spawned) = previews;
+        let next_shape_idx = failed[0];
+        let (curr_shape_idx, next_shape_idxs, game_over) = if locked_out {
+            (gs.curr_shape_idx, &gs.next_shape_idxs, true)
+        } else if after.fits(next_shape_idx, &gs.spawn_base(next_shape_idx), 0) {
+            (next_shape_idx, spawned, false)
+        } else {
+            (gs.curr_shape_idx, failed, true)
+        };
+        let block_heights = after.column_heights();
+        let state = DQNState {
+            lines_burnt,
+            sum_holes: after.holes(),
+            sum_bumps: self.get_sum_bumps(&block_heights),
+            sum_height: self.get_sum_height(&block_heights),
+            curr_shape_idx,
+            next_shape_idxs: next_shape_idxs.iter().cloned().collect(),
+            hold_shape_idx: gs.hold_shape_idx,
+            combo,
+            back_to_back,
+        };
+        (state, game_over)
     }
 
     fn convert_to_dqn_state(&self) -> DQNState {
-        let block_heights = self.get_block_heights();
+        let bits = BitField::from(&self.gs.field);
+        let block_heights = bits.column_heights();
         DQNState {
             lines_burnt: self.lines_burnt,
-            sum_holes: self.get_sum_holes(&block_heights),
+            sum_holes: bits.holes(),
             sum_bumps: self.get_sum_bumps(&block_heights),
             sum_height: self.get_sum_height(&block_heights),
             curr_shape_idx: self.gs.curr_shape_idx,
+            next_shape_idxs: self.gs.next_shape_idxs.iter().cloned().collect(),
+            hold_shape_idx: self.gs.hold_shape_idx,
+            combo: self.gs.combo,
+            back_to_back: self.gs.back_to_back,
         }
     }
 
+    /// The heights of the columns, see `BitField::column_heights`
     pub fn get_block_heights(&self) -> Vec<u16> {
-        let n = self.gs.field.width;
-        let m = self.gs.field.height;
-        let mut heights = Vec::with_capacity(n);
-        for j in 0..n {
-            let mut block_height = 0 as u16;
-            for i in 0..m {
-                if self.gs.field.cells[i][j] > 0 {
-                    block_height = (m - i) as u16;
-                    break
-                }
-            }
-            heights.push(block_height);
-        }
-        heights
+        BitField::from(&self.gs.field).column_heights()
     }
 
-    pub fn get_sum_holes(&self, block_heights: &[u16]) -> u16 {
-        // iterate through columns, the empty cells inside j-th block we call a 'hole'
-        let n = self.gs.field.width;
-        let m = self.gs.field.height;
-        let mut sum_holes = 0;
-        for j in 0..n {
-            let m1 = m - block_heights[j] as usize;
-            for i in m1..m {
-                if self.gs.field.cells[i][j] == 0 {
-                    sum_holes += 1;
-                }
-            }
-        }
-        sum_holes
+    /// The empty cells below the top block of their columns, see `BitField::holes`
+    pub fn get_sum_holes(&self, _block_heights: &[u16]) -> u16 {
+        BitField::from(&self.gs.field).holes()
     }
 
     pub fn get_sum_height(&self, block_heights: &[u16]) -> u16 {
         block_heights.iter().sum()
     }
 
+    /// The sum of the height differences of the neighbour columns
     pub fn get_sum_bumps(&self, block_heights: &[u16]) -> u16 {
-        // sum of the differences of heights between pair of columns
-        let n = self.gs.field.width;
-        let m = self.gs.field.height;
-        let mut sum_bumps = 0;
-        block_heights.windows(2).for_each(|hs| {
-            let h1 = hs[0] as i16;
-            let h2 = hs[1] as i16;
-            let bump = (h1 - h2).abs();
-            sum_bumps += bump as u16;
-        });
-        sum_bumps
+        block_heights.windows(2).map(|hs| (hs[0] as i16 - hs[1] as i16).unsigned_abs()).sum()
     }
 }
 
-pub fn run_training(seed: Option<u64>) -> failure::Fallible<()> {
-    let rng = if let Some(seed) = seed {
-        Xoshiro512StarStar::seed_from_u64(seed)
-    } else {
-        Xoshiro512StarStar::from_entropy()
+/// Play the game from the start until the game over or `max_step` placements, return the score,
+/// with `learn` the agent observes every transition
+pub fn play_episode<A: Agent + ?Sized>(agent: &mut A, env: &mut TetrisEnv, max_step: usize, learn: bool) -> u32 {
+    agent.reset();
+    let mut state = env.reset();
+    for _ in 0..max_step {
+        let action = match agent.select_action(env) {
+            Some(action) => action,
+            None => break,
+        };
+        let (next_state, reward, done) = env.step(action);
+        if learn {
+            agent.observe(DQNTransition {
+                curr_state: state,
+                action,
+                reward,
+                next_state: next_state.clone(),
+                done,
+                candidates: vec![],
+                steps: 1,
+            });
+        }
+        if done {
+            break;
+        }
+        state = next_state;
+    }
+    env.gs.score
+}
+
+/// The scores of `episodes` games without learning
+pub fn evaluate<A: Agent + ?Sized>(agent: &mut A, env: &mut TetrisEnv, episodes: usize, max_step: usize) -> Vec<u32> {
+    (0..episodes).map(|_| play_episode(agent, env, max_step, false)).collect()
+}
+
+/// Train `DQNAgent` with the default `AgentConf`, the stats are logged every `log_every` episodes.
+/// The checkpoints are saved to `checkpoint_dir` every `save_every` episodes and after the last one,
+/// with `resume` the training continues from the checkpoint in `checkpoint_dir`.
+pub fn run_training(seed: Option<u64>, checkpoint_dir: Option<&Path>, resume: bool) -> failure::Fallible<()> {
+    let (mut agent, mut env, mut progress) = match checkpoint_dir {
+        Some(dir) if resume => {
+            let (agent, env, progress) = checkpoint::load(dir)?;
+            println!("Resumed from {} after {} episodes", dir.display(), progress.episode);
+            (agent, env, progress)
+        }
+        _ => {
+            let agent = DQNAgent::new(seed);
+            let progress = Progress::new(agent.epsilon);
+            (agent, TetrisEnv::new(seed), progress)
+        }
     };
-    let conf = AgentConf::default();
-    let memory = VecDeque::default();
-    let mut agent = DQNAgent { conf, memory, rng };
-    let mut env = TetrisEnv::new(seed);
-    let episodes = 2000;
-    let max_steps = Some(10000);
-    let replay_memory_init_size = 50000;
-
-    println!("Populating replay memory...");
-    //    state = env.reset()
-    //    state = state_processor.process(sess, state)
-    //    state = np.stack([state] * 4, axis=2)
-    //    for i in range(replay_memory_init_size):
-    //        action_probs = policy(sess, state, epsilons[min(total_t, epsilon_decay_steps - 1)])
-    //        action = np.random.choice(np.arange(len(action_probs)), p=action_probs)
-    //        next_state, reward, done, _ = env.step(VALID_ACTIONS[action])
-    //        next_state = state_processor.process(sess, next_state)
-    //        next_state = np.append(state[:, :, 1:], np.expand_dims(next_state, 2), axis=2)
-    //        replay_memory.append(Transition(state, action, reward, next_state, done))
-    //        if done:
-    //            state = env.reset()
-    //            state = state_processor.process(sess, state)
-    //            state = np.stack([state] * 4, axis=2)
-    //        else:
-    //            state = next_state
-
-    // agent.add_to_memory(current_state, next_states[best_action], reward, done)
-    let state = env.reset();
-    let mut replay_memory = VecDeque::<DQNTransition>::new();
-    for _ in 0..replay_memory_init_size {
+    let conf = agent.conf.clone();
+    env.reward = Arc::new(Rewards::new(&conf.reward)?);
+    let max_step = conf.max_step.unwrap_or(i32::MAX);
+    for episode in progress.episode + 1..=conf.episodes {
+        let mut current_state = env.reset();
+        let render = match conf.render_every {
+            Some(n) => episode % n == 0,
+            None => false,
+        };
         let mut steps = 0;
-        let state = env.reset();
-        while max_steps.is_none() || steps < max_steps.unwrap() {
-            let valid_actions = env.get_valid_actions();
-            let best_action = agent.select_best_action(&valid_actions);
-            let (next_state, reward, done) = env.step(best_action);
-            // next_state, reward, done, _ = env.step(VALID_ACTIONS[action])
-
+        while steps < max_step {
+            let transitions = env.get_next_transitions();
+            if transitions.is_empty() {
+                break;
+            }
+            let candidates: Vec<_> = transitions.iter().map(|(a, s, _, _)| (*a, s.clone())).collect();
+            let action = agent.select_best_action(&candidates);
+            let (next_state, reward, done) = env.step(action);
+            if render {
+                println!("{}", env.gs.prettify_game_state(false, false, false));
+            }
+            let candidates = if conf.double_dqn {
+                transitions.into_iter().map(|(_, s, r, d)| (s, r, d)).collect()
+            } else {
+                vec![]
+            };
+            agent.add_to_memory(DQNTransition {
+                curr_state: current_state,
+                action,
+                reward,
+                next_state: next_state.clone(),
+                done,
+                candidates,
+                steps: 1,
+            });
+            current_state = next_state;
+            steps += 1;
             if done {
                 break;
             }
         }
+        agent.finish_episode();
+        progress.scores.push(env.gs.score);
+        let loss = if episode % conf.train_every == 0 { agent.train() } else { None };
+        agent.decay_epsilon();
+        progress.episode = episode;
+        progress.epsilon = agent.epsilon;
+        progress.train_steps = agent.train_steps;
+        if episode % conf.log_every == 0 {
+            let scores = &progress.scores;
+            let last = &scores[scores.len().saturating_sub(conf.log_every as usize)..];
+            let avg = last.iter().sum::<u32>() as f32 / last.len() as f32;
+            println!(
+                "episode: {:5}, score avg: {:8.1}, min: {:6}, max: {:6}, epsilon: {:.3}, loss: {}",
+                episode, avg, last.iter().min().unwrap(), last.iter().max().unwrap(), agent.epsilon,
+                loss.map_or("-".to_string(), |l| format!("{:.3}", l)),
+            );
+        }
+        if let Some(dir) = checkpoint_dir {
+            let due = match conf.save_every {
+                Some(n) => episode % n == 0,
+                None => false,
+            };
+            if due || episode == conf.episodes {
+                checkpoint::save(dir, &agent, &env, &progress)?;
+            }
+        }
     }
-
-//    for episode in 0..episodes {
-//        let mut steps = 0;
-//        let state = env.reset();
-//        while max_steps.is_none() || steps < max_steps.unwrap() {
-//            let next_states = env.get_next_transitions();
-//            let best_action = agent.select(next_states);
-//            let (next_state, reward, done) = env.step(best_action);
-//            // next_state, reward, done, _ = env.step(VALID_ACTIONS[action])
-//
-//            if done {
-//                break;
-//            }
-//        }
-//    }
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,103 @@
+#![feature(type_ascription)]
+
+use std::fs;
+use tetris::agent::{Agent, DQNAgent, GreedyAgent, RandomAgent, AGENT_NAMES, new_agent};
+use tetris::model::Action;
+use tetris::train::{TetrisEnv, evaluate, play_episode};
+
+#[test]
+fn test_random_agent() {
+    let mut env = TetrisEnv::new(Some(1));
+    env.reset();
+    let mut a = RandomAgent::new(None);
+    let mut b = RandomAgent::new(None);
+    a.seed(3);
+    b.seed(3);
+    for _ in 0..10 {
+        let action = a.select_action(&env).unwrap();
+        assert_eq!(b.select_action(&env), Some(action));
+        assert!(env.get_valid_actions().contains(&action));
+        env.step(action);
+    }
+}
+
+#[test]
+fn test_greedy_beats_random() {
+    let mut env = TetrisEnv::new(Some(2));
+    let greedy = evaluate(&mut GreedyAgent::default(), &mut env, 3, 200);
+    let random = evaluate(&mut RandomAgent::new(Some(2)), &mut env, 3, 200);
+    // the score is the number of the pieces with the default scoring
+    assert_eq!(greedy, vec![200, 200, 200]);
+    assert!(random.iter().all(|s| *s < 100), "{:?}", random);
+}
+
+#[test]
+fn test_select_inputs() {
+    let mut env = TetrisEnv::new(Some(4));
+    env.reset();
+    let mut agent = GreedyAgent::default();
+    for _ in 0..5 {
+        let action = agent.select_action(&env).unwrap();
+        let inputs = agent.select_inputs(&env).unwrap();
+        assert_eq!(inputs.last(), Some(&Action::HardDrop));
+        let mut expected = env.clone();
+        expected.step(action);
+        for input in inputs {
+            env.gs.step(input);
+        }
+        assert_eq!(env.gs.field, expected.gs.field);
+    }
+}
+
+#[test]
+fn test_agents_by_name() {
+    for name in AGENT_NAMES.iter() {
+        let mut agent = new_agent(name, Some(5), None).unwrap();
+        let mut env = TetrisEnv::new(Some(5));
+        agent.seed(5);
+        assert_eq!(evaluate(&mut agent, &mut env, 1, 3), vec![3], "{}", name);
+    }
+    assert!(new_agent("human", None, None).is_err());
+}
+
+#[test]
+fn test_learning_agent() {
+    let mut agent = DQNAgent::new(Some(9));
+    let mut env = TetrisEnv::new(Some(9));
+    play_episode(&mut agent, &mut env, 20, false);
+    assert!(agent.memory.is_empty());
+    play_episode(&mut agent, &mut env, 20, true);
+    assert_eq!(agent.memory.len(), 20);
+
+    let dir = std::env::temp_dir().join(format!("tetris-agent-{}", std::process::id()));
+    agent.save(&dir).unwrap();
+    let mut restored = DQNAgent::new(Some(7));
+    restored.load(&dir).unwrap();
+    assert_eq!(restored.memory, agent.memory);
+    assert_eq!(restored.conf, agent.conf);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_load_agents_by_name() {
+    let dir = std::env::temp_dir().join(format!("tetris-agents-{}", std::process::id()));
+    DQNAgent::new(Some(10)).save(&dir).unwrap();
+    let mut env = TetrisEnv::new(Some(10));
+    env.reset();
+    // the loaded agent plays the placements of its network, whatever the seed
+    let mut a = new_agent("dqn", Some(1), Some(&dir)).unwrap();
+    let mut b = new_agent("dqn", Some(2), Some(&dir)).unwrap();
+    for _ in 0..10 {
+        let action = a.select_action(&env).unwrap();
+        assert_eq!(a.select_action(&env), Some(action));
+        assert_eq!(b.select_action(&env), Some(action));
+        env.step(action);
+    }
+    // the MCTS values the leaves by the loaded network
+    let mut mcts = new_agent("mcts", Some(10), Some(&dir)).unwrap();
+    assert!(env.get_valid_actions().contains(&mcts.select_action(&env).unwrap()));
+    for name in ["random", "greedy"].iter() {
+        assert!(new_agent(name, Some(10), Some(&dir)).is_err(), "{}", name);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,122 @@
+#![feature(type_ascription)]
+
+use tetris::bitboard::{BitField, SHAPE_MASKS};
+use tetris::model::{Field, GameState, Occupancy, Point, detect_tspin, try_shape, try_shape_idx, try_wall_kick};
+use tetris::tetrimino::TETRIMINOES;
+use tetris::train::TetrisEnv;
+use tetris::config::Config;
+use tetris::field;
+
+fn sample_field() -> Field {
+    field!("
+        . . . . . . . . . .
+        . . . . . . . . . .
+        . . . . * . . . . .
+        * * . . * . . * . .
+        . . . . . * * . . .
+        * . * * . * . . . *
+        * . * * * * * . * *
+        * * * * * * * . * *
+    ")
+}
+
+#[test]
+fn test_conversion() {
+    let field = sample_field();
+    let bits = BitField::from(&field);
+    assert_eq!(bits.rows[3], 0b0010010011);
+    assert_eq!(bits.rows[7], 0b1101111111);
+    assert!(!bits.is_empty());
+    assert!(BitField::new(4, 10).is_empty());
+    let back = Field::from(&bits);
+    for (row, row_back) in field.cells.iter().zip(back.cells.iter()) {
+        for (cell, cell_back) in row.iter().zip(row_back.iter()) {
+            assert_eq!(*cell == 0, *cell_back == 0);
+        }
+    }
+}
+
+#[test]
+fn test_fits_and_wall_kicks() {
+    let field = sample_field();
+    let bits = BitField::from(&field);
+    assert_eq!(SHAPE_MASKS[0][0].rows, vec![(0, 0b1111)]);
+    for shape_idx in 0..7 {
+        for rotation in 0..4 {
+            for i in -2..10 {
+                for j in -2..12 {
+                    let base = Point(i, j);
+                    assert_eq!(
+                        bits.fits(shape_idx, &base, rotation),
+                        try_shape(&field, &base, rotation, &TETRIMINOES[shape_idx]).is_some(),
+                        "shape {} rotation {} base {:?}", shape_idx, rotation, base,
+                    );
+                    let transition = (rotation, (rotation + 1) % 4);
+                    assert_eq!(
+                        bits.try_wall_kick(shape_idx, &base, transition),
+                        try_wall_kick(&field, shape_idx, &base, transition).map(|(b, _, k)| (b, k)),
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_burn_lines() {
+    let mut bits = BitField::from(&field!("
+        . . . .
+        I . . .
+        I I I I
+        . I . .
+        I I I I
+    "));
+    assert_eq!(bits.burn_lines(), 2);
+    assert_eq!(bits.rows, vec![0, 0, 0, 0b0001, 0b0010]);
+    assert_eq!(bits.burn_lines(), 0);
+}
+
+#[test]
+fn test_heights_and_holes() {
+    let mut gs = GameState::initial(8, 10, Config::default(), Some(1));
+    gs.field = sample_field();
+    let env = TetrisEnv::from_state(gs);
+    let bits = BitField::from(&env.gs.field);
+    let heights = env.get_block_heights();
+    assert_eq!(heights, vec![5, 5, 3, 3, 6, 4, 4, 5, 2, 3]);
+    assert_eq!(bits.column_heights(), heights);
+    assert_eq!(env.get_sum_holes(&heights), 11);
+    assert_eq!(bits.holes(), 11);
+    assert_eq!(env.get_sum_bumps(&heights), 12);
+    assert_eq!(bits.bumpiness(), 12);
+}
+
+#[test]
+fn test_occupancy() {
+    let field = sample_field();
+    let bits = BitField::from(&field);
+    for i in 0..8 {
+        for j in 0..10 {
+            assert_eq!(bits.is_occupied(i, j), field.is_occupied(i, j));
+        }
+    }
+    for shape_idx in 0..7 {
+        for rotation in 0..4 {
+            for i in -2..10 {
+                for j in -2..12 {
+                    let base = Point(i, j);
+                    assert_eq!(
+                        try_shape_idx(&bits, &base, rotation, shape_idx),
+                        try_shape_idx(&field, &base, rotation, shape_idx),
+                    );
+                    for kick in 0..5 {
+                        assert_eq!(
+                            detect_tspin(&bits, shape_idx, &base, rotation, Some(kick)),
+                            detect_tspin(&field, shape_idx, &base, rotation, Some(kick)),
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
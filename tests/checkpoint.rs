@@ -0,0 +1,114 @@
+#![feature(type_ascription)]
+
+use std::fs;
+use std::path::PathBuf;
+use rand::Rng;
+use tetris::agent::{AgentConf, DQNAgent, DQNTransition};
+use tetris::checkpoint::{self, Progress, CHECKPOINT_VERSION};
+use tetris::train::TetrisEnv;
+
+fn checkpoint_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("tetris-checkpoint-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn play(agent: &mut DQNAgent, env: &mut TetrisEnv, steps: usize) {
+    let mut state = env.reset();
+    for _ in 0..steps {
+        let candidates = env.get_next_states();
+        if candidates.is_empty() {
+            state = env.reset();
+            continue;
+        }
+        let action = agent.select_best_action(&candidates);
+        let (next_state, reward, done) = env.step(action);
+        agent.add_to_memory(DQNTransition {
+            curr_state: state,
+            action,
+            reward,
+            next_state: next_state.clone(),
+            done,
+            candidates: vec![],
+            steps: 1,
+        });
+        state = if done { env.reset() } else { next_state };
+    }
+}
+
+#[test]
+fn test_save_and_load() {
+    let conf = AgentConf { n_neurons: vec![8], activations: vec!["relu".into(), "linear".into()], ..Default::default() };
+    let mut agent = DQNAgent::with_conf(conf, Some(5)).unwrap();
+    let mut env = TetrisEnv::new(Some(5));
+    play(&mut agent, &mut env, 30);
+    agent.decay_epsilon();
+    let progress = Progress { episode: 3, epsilon: agent.epsilon, scores: vec![10, 0, 40], ..Progress::new(1.0) };
+    let dir = checkpoint_dir("save");
+    checkpoint::save(&dir, &agent, &env, &progress).unwrap();
+
+    let (mut restored, mut restored_env, restored_progress) = checkpoint::load(&dir).unwrap();
+    assert_eq!(restored_progress, progress);
+    assert_eq!(restored.conf, agent.conf);
+    assert_eq!(restored.memory, agent.memory);
+    assert_eq!(restored.epsilon, agent.epsilon);
+    assert_eq!(restored_env.gs.field, env.gs.field);
+    // the random streams go on the same way
+    assert_eq!(restored.rng.gen::<u64>(), agent.rng.gen::<u64>());
+    assert_eq!(restored_env.reset(), env.reset());
+    assert_eq!(restored_env.gs.next_shape_idxs, env.gs.next_shape_idxs);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_optimizer_and_versions() {
+    let conf = AgentConf { batch_size: 8, replay_start_size: 8, ..Default::default() };
+    let mut agent = DQNAgent::with_conf(conf, Some(2)).unwrap();
+    let mut env = TetrisEnv::new(Some(2));
+    play(&mut agent, &mut env, 20);
+    for _ in 0..3 {
+        agent.train().unwrap();
+    }
+    assert_eq!(agent.opt.steps(), 3);
+    let dir = checkpoint_dir("versions");
+    checkpoint::save(&dir, &agent, &env, &Progress::new(1.0)).unwrap();
+    assert_eq!(checkpoint::current_version(&dir).unwrap(), dir.join("v0"));
+    // the moments of Adam go on from where they were
+    let (restored, _, _) = checkpoint::load(&dir).unwrap();
+    assert_eq!(restored.opt.steps(), 3);
+    // the next save replaces the version as a whole
+    checkpoint::save_agent(&dir, &agent).unwrap();
+    assert_eq!(checkpoint::current_version(&dir).unwrap(), dir.join("v1"));
+    assert!(!dir.join("v0").exists());
+    assert!(checkpoint::load_agent(&dir).is_ok());
+    // the version without the progress is not a training checkpoint
+    assert!(checkpoint::load(&dir).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_unsupported_version() {
+    let agent = DQNAgent::new(Some(1));
+    let env = TetrisEnv::new(Some(1));
+    let progress = Progress { version: CHECKPOINT_VERSION + 1, ..Progress::new(1.0) };
+    let dir = checkpoint_dir("version");
+    checkpoint::save(&dir, &agent, &env, &progress).unwrap();
+    assert!(checkpoint::load(&dir).is_err());
+    assert!(checkpoint::load(dir.join("missing")).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_invalid_conf() {
+    let agent = DQNAgent::new(Some(3));
+    let dir = checkpoint_dir("conf");
+    checkpoint::save_agent(&dir, &agent).unwrap();
+    let path = checkpoint::current_version(&dir).unwrap().join("conf.json");
+    let json = fs::read_to_string(&path).unwrap();
+    // a typo in the hand-edited conf is an error, not a panic
+    fs::write(&path, json.replacen("\"relu\"", "\"rleu\"", 1)).unwrap();
+    assert!(checkpoint::load_agent(&dir).is_err());
+    fs::write(&path, json.replacen("\"log_every\": 10", "\"log_every\": 0", 1)).unwrap();
+    assert!(checkpoint::load_agent(&dir).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
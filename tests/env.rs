@@ -0,0 +1,119 @@
+#![feature(type_ascription)]
+
+use std::thread::{self, ThreadId};
+use tetris::env::{Environment, Step, VecEnv};
+use tetris::train::TetrisEnv;
+
+/// The leftmost placement fills the field quickly, so the games end soon
+fn leftmost<E: Environment<Action = tetris::agent::DQNAction>>(env: &E) -> E::Action {
+    *env.valid_actions().iter().min_by_key(|a| (a.base.1, a.rotation)).unwrap()
+}
+
+#[test]
+fn test_tetris_environment() {
+    let mut env = TetrisEnv::new(Some(1));
+    let mut reference = env.clone();
+    let observation = Environment::reset(&mut env);
+    assert_eq!(observation, reference.reset());
+    loop {
+        let action = leftmost(&env);
+        assert_eq!(Environment::valid_actions(&env), reference.get_valid_actions());
+        let step = Environment::step(&mut env, action);
+        let (state, reward, done) = reference.step(action);
+        assert_eq!((&step.observation, step.reward, step.done), (&state, reward, done));
+        assert_eq!(step.info.score, env.gs.score);
+        assert_eq!(step.info.clear, env.clear);
+        if step.done {
+            break;
+        }
+    }
+    assert!(Environment::valid_actions(&env).is_empty());
+}
+
+#[test]
+fn test_seed() {
+    let mut a = TetrisEnv::new(Some(1));
+    let mut b = TetrisEnv::new(Some(2));
+    a.seed(7);
+    b.seed(7);
+    for _ in 0..3 {
+        assert_eq!(Environment::reset(&mut a), Environment::reset(&mut b));
+        assert_eq!(a.gs.next_shape_idxs, b.gs.next_shape_idxs);
+    }
+}
+
+fn run(threads: usize, steps: usize) -> (Vec<Vec<(f32, bool)>>, usize) {
+    let envs = (0..5).map(|_| TetrisEnv::new(None)).collect();
+    let mut vec_env = VecEnv::new(envs, threads);
+    vec_env.seed(10);
+    let mut history = vec![];
+    let mut episodes = 0;
+    for _ in 0..steps {
+        let actions = vec_env.envs.iter().map(leftmost).collect();
+        let steps = vec_env.step(actions);
+        for (k, step) in steps.iter().enumerate() {
+            assert_eq!(vec_env.valid_actions()[k], Environment::valid_actions(&vec_env.envs[k]));
+            if step.done {
+                episodes += 1;
+                // the game is started again
+                let observation = &vec_env.observations[k];
+                assert_eq!((observation.sum_height, observation.lines_burnt), (0, 0));
+                assert!(!vec_env.envs[k].gs.game_over);
+            } else {
+                assert_eq!(vec_env.observations[k], step.observation);
+            }
+        }
+        history.push(steps.iter().map(|s| (s.reward, s.done)).collect());
+    }
+    (history, episodes)
+}
+
+#[test]
+fn test_vec_env() {
+    let (history, episodes) = run(1, 40);
+    assert!(episodes >= 5, "{}", episodes);
+    // the threads don't change the games
+    for threads in 2..=6 {
+        assert_eq!(run(threads, 40), (history.clone(), episodes));
+    }
+}
+
+/// Observes the thread it is stepped on
+struct ThreadEnv;
+
+impl Environment for ThreadEnv {
+    type Observation = Option<ThreadId>;
+    type Action = ();
+    type Info = ();
+
+    fn reset(&mut self) -> Option<ThreadId> {
+        None
+    }
+
+    fn step(&mut self, _action: ()) -> Step<Option<ThreadId>, ()> {
+        Step { observation: Some(thread::current().id()), reward: 0.0, done: false, info: () }
+    }
+
+    fn valid_actions(&self) -> Vec<()> {
+        vec![()]
+    }
+
+    fn seed(&mut self, _seed: u64) {}
+}
+
+#[test]
+fn test_vec_env_workers() {
+    let mut vec_env = VecEnv::new((0..5).map(|_| ThreadEnv).collect(), 3);
+    assert_eq!(vec_env.threads(), 3);
+    let first: Vec<_> = vec_env.step(vec![(); 5]).into_iter().map(|s| s.observation).collect();
+    // the same threads step the same games every time
+    for _ in 0..3 {
+        let next: Vec<_> = vec_env.step(vec![(); 5]).into_iter().map(|s| s.observation).collect();
+        assert_eq!(next, first);
+    }
+    let mut threads = first.clone();
+    threads.dedup();
+    assert_eq!(threads.len(), 3);
+    assert!(!first.contains(&Some(thread::current().id())));
+    assert_eq!(VecEnv::new(vec![ThreadEnv, ThreadEnv], 1).threads(), 1);
+}
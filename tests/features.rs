@@ -0,0 +1,115 @@
+#![feature(type_ascription)]
+
+use tetris::features::*;
+use tetris::model::Point;
+use tetris::field;
+
+fn sample_board() -> Board<'static> {
+    Board::new(field!("
+        . . . . .
+        . . . . .
+        * . . . .
+        * . * . *
+        * * * . *
+        * . * * *
+    "))
+}
+
+fn feature<F: FeatureExtractor>(f: F, board: &Board) -> f32 {
+    let values = f.extract(board);
+    assert_eq!(values.len(), 1);
+    values[0]
+}
+
+#[test]
+fn test_height_features() {
+    let board = sample_board();
+    assert_eq!(board.column_heights(), vec![4, 2, 3, 1, 3]);
+    assert_eq!(feature(MaxHeight, &board), 4.0);
+    assert_eq!(feature(AggregateHeight, &board), 13.0);
+    assert_eq!(feature(Bumpiness, &board), 7.0);
+}
+
+#[test]
+fn test_holes_and_transitions() {
+    let board = sample_board();
+    assert_eq!(board.holes(), vec![Point(5, 1)]);
+    assert_eq!(feature(Holes, &board), 1.0);
+    assert_eq!(feature(HoleDepth, &board), 1.0);
+    assert_eq!(feature(RowsWithHoles, &board), 1.0);
+    // the empty rows above the stack don't count
+    assert_eq!(feature(RowTransitions, &board), 10.0);
+    assert_eq!(feature(ColumnTransitions, &board), 7.0);
+
+    let board = Board::new(field!("
+        . . .
+        * * *
+        . * .
+        * . .
+    "));
+    // 3 holes under the row, the one in the middle is covered twice
+    assert_eq!(feature(Holes, &board), 4.0);
+    assert_eq!(feature(HoleDepth, &board), 1.0 + 1.0 + 2.0 + 1.0);
+    assert_eq!(feature(RowsWithHoles, &board), 2.0);
+}
+
+#[test]
+fn test_wells() {
+    let board = sample_board();
+    // the wells of the depth 1 in the column 1 and 2 in the column 3
+    assert_eq!(feature(CumulativeWells, &board), 1.0 + (1.0 + 2.0));
+    assert_eq!(feature(WellCount { min_depth: 1 }, &board), 2.0);
+    assert_eq!(feature(WellCount { min_depth: 2 }, &board), 1.0);
+    // the walls are the sides of the wells
+    let board = Board::new(field!("
+        . . . .
+        . * * .
+        . * * .
+    "));
+    assert_eq!(feature(CumulativeWells, &board), 3.0 + 3.0);
+    assert_eq!(feature(WellCount { min_depth: 2 }, &board), 2.0);
+}
+
+#[test]
+fn test_placement_features() {
+    let field = field!("
+        . . . .
+        . . . .
+        * * . *
+        * * . *
+    ");
+    let cells = [Point(0, 2), Point(1, 2), Point(2, 2), Point(3, 2)];
+    let board = Board::after_placement(&field, &cells);
+    assert_eq!(*board.field, field!("
+        . . . .
+        . . . .
+        . . I .
+        . . I .
+    "));
+    assert_eq!(feature(LandingHeight, &board), 2.5);
+    // 2 lines with 2 cells of the piece in each
+    assert_eq!(feature(ErodedPieceCells, &board), 4.0);
+    assert_eq!(feature(RowsBurnt, &board), 2.0);
+    // no last piece, no placement features
+    let board = Board::new(field);
+    assert_eq!(feature(LandingHeight, &board), 0.0);
+    assert_eq!(feature(ErodedPieceCells, &board), 0.0);
+}
+
+#[test]
+fn test_composition() {
+    let board = sample_board();
+    let features = dellacherie();
+    assert_eq!(
+        features.names(),
+        vec!["landing_height", "eroded_piece_cells", "row_transitions", "column_transitions", "holes", "cumulative_wells"],
+    );
+    assert_eq!(features.extract(&board), vec![0.0, 0.0, 10.0, 7.0, 1.0, 4.0]);
+    let features = all();
+    assert_eq!(features.names().len(), 13);
+    assert_eq!(features.extract(&board).len(), 13);
+    // the groups compose as the single features
+    let nested: Vec<Box<dyn FeatureExtractor>> = vec![Box::new(dellacherie()), Box::new(Holes)];
+    assert_eq!(nested.extract(&board)[..6], features.extract(&board)[..6]);
+    assert_eq!(nested.names().len(), 7);
+}
@@ -0,0 +1,106 @@
+#![feature(type_ascription)]
+
+use tetris::model::{Point, GameState, Action};
+use tetris::agent::DQNAction;
+use tetris::finesse::{finesse_path, count_inputs, min_inputs};
+use tetris::train::TetrisEnv;
+use tetris::config::Config;
+use tetris::field;
+
+#[test]
+fn test_finesse_path() {
+    let mut gs = GameState::initial(22, 10, Config::default(), Some(1));
+    gs.spawn_shape(1); // O
+    let target = DQNAction { base: Point(1, 8), rotation: 0, hold: false, last_kick: None };
+    let path = finesse_path(&gs, &target).unwrap();
+    assert_eq!(path, vec![Action::DasRight, Action::HardDrop]);
+    // the vertical I to the left wall: either rotation gives the same cells
+    gs.spawn_shape(0);
+    let target = DQNAction { base: Point(1, 0), rotation: 3, hold: false, last_kick: None };
+    let path = finesse_path(&gs, &target).unwrap();
+    assert_eq!(path, vec![Action::RotateCW, Action::DasLeft, Action::HardDrop]);
+    assert_eq!(count_inputs(&path), 2);
+}
+
+#[test]
+fn test_finesse_path_with_hold() {
+    let config = Config {
+        hold_enabled: true,
+        ..Default::default()
+    };
+    let gs = GameState::initial(22, 10, config, Some(3));
+    let env = TetrisEnv::from_state(gs.clone());
+    let target = *env.get_valid_actions().iter().find(|a| a.hold).unwrap();
+    let path = finesse_path(&gs, &target).unwrap();
+    assert_eq!(path[0], Action::Hold);
+}
+
+#[test]
+fn test_finesse_replay() {
+    let mut gs = GameState::initial(8, 10, Config::default(), Some(1));
+    gs.field = field!("
+        . . . . . . . . . .
+        . . . . . . . . . .
+        . . . . . . . . . .
+        * * * * . . . . . .
+        . . . . . . . . . .
+        . . . . . * * . . .
+        * . * * . * . . . *
+        * . * * * * * . * *
+    ");
+    for shape_idx in 0..7 {
+        gs.spawn_shape(shape_idx);
+        let env = TetrisEnv::from_state(gs.clone());
+        for action in env.get_valid_actions() {
+            let mut expected = env.clone();
+            expected.step(action);
+            let mut replayed = gs.clone();
+            let path = finesse_path(&gs, &action).unwrap();
+            for a in &path {
+                replayed.step(*a);
+            }
+            assert_eq!(replayed.field, expected.gs.field, "shape {} action {:?}", shape_idx, action);
+        }
+    }
+}
+
+#[test]
+fn test_min_inputs_tuck() {
+    let field = field!("
+        . . . . . .
+        . . . . . .
+        * * . . . .
+        . . . . . .
+    ");
+    let cells = [Point(3, 0), Point(3, 1), Point(3, 2), Point(3, 3)];
+    // the I goes down the hole and slides to the wall
+    assert_eq!(min_inputs(&field, 0, Point(1, 2), &cells), Some(2));
+}
+
+#[test]
+fn test_finesse_faults() {
+    let config = Config {
+        track_finesse: true,
+        ..Default::default()
+    };
+    let mut gs = GameState::initial(22, 10, config, Some(1));
+    gs.spawn_shape(1); // O
+    for action in &[Action::Left, Action::Right, Action::Left, Action::HardDrop] {
+        gs.step(*action);
+    }
+    assert_eq!(gs.finesse_faults, 2);
+    assert_eq!(gs.piece_inputs, 0);
+    // the shifts into the wall are not inputs
+    gs.spawn_shape(1);
+    for action in &[Action::DasLeft, Action::Left, Action::Left, Action::HardDrop] {
+        gs.step(*action);
+    }
+    assert_eq!(gs.finesse_faults, 2);
+    assert!(gs.prettify_game_state(false, false, false).contains("finesse faults: 2"));
+    // no faults are counted without the tracking
+    let mut gs = GameState::initial(22, 10, Config::default(), Some(1));
+    for action in &[Action::Left, Action::Right, Action::HardDrop] {
+        gs.step(*action);
+    }
+    assert_eq!(gs.finesse_faults, 0);
+}
@@ -0,0 +1,128 @@
+#![feature(type_ascription)]
+
+use tetris::model::{Point, Field, GameState, Action};
+use tetris::fumen::{self, Page, Operation};
+use tetris::agent::DQNAction;
+use tetris::field;
+
+#[test]
+fn test_empty_page() {
+    let pages = fumen::decode("v115@vhAAgH", 22).unwrap();
+    assert_eq!(pages, vec![Page::new(empty_field(22), None)]);
+    assert_eq!(fumen::encode(&pages).unwrap(), "v115@vhAAgH");
+}
+
+#[test]
+fn test_decode_piece() {
+    // the horizontal I in the bottom left corner
+    let pages = fumen::decode("v115@vhAxOJ", 22).unwrap();
+    let expected = Operation { shape_idx: 0, base: Point(21, 1), rotation: 0 };
+    assert_eq!(pages[0].operation, Some(expected));
+}
+
+#[test]
+fn test_field_round_trip() {
+    let field = field!("
+        . . . . . . . . . .
+        . . . . . . . . . .
+        I . . . . . . . . .
+        I . . . . . . . O O
+        I L . . . . . T O O
+        I L L L . . T T T *
+        J J J Z Z . S S * *
+    ");
+    let data = fumen::encode(&[Page::new(field.clone(), None)]).unwrap();
+    assert_eq!(fumen::decode(&data, 7).unwrap()[0].field, field);
+    // bottom aligned with the fields of the other heights
+    let pages = fumen::decode(&data, 22).unwrap();
+    assert_eq!(pages[0].field.cells[21], field.cells[6]);
+    assert!(fumen::decode(&data, 4).is_err());
+}
+
+#[test]
+fn test_operation_round_trip() {
+    for shape_idx in 0..7 {
+        for rotation in 0..4 {
+            let operation = Operation { shape_idx, base: Point(10, 4), rotation };
+            let page = Page::new(empty_field(22), Some(operation));
+            let pages = fumen::decode(&fumen::encode(&[page]).unwrap(), 22).unwrap();
+            assert_eq!(pages[0].operation, Some(operation), "shape {} rotation {}", shape_idx, rotation);
+        }
+    }
+}
+
+#[test]
+fn test_pages_and_comments() {
+    let mut pages = vec![];
+    let mut page = Page::new(empty_field(22), Some(Operation { shape_idx: 1, base: Point(20, 0), rotation: 0 }));
+    page.comment = "100% perfect — ☺".into();
+    pages.push(page);
+    // the same comment is not repeated
+    let mut page = Page::new(empty_field(22), None);
+    page.comment = "100% perfect — ☺".into();
+    page.lock = false;
+    pages.push(page);
+    // the O is locked, so the field of the second page changes
+    pages[1].field = pages[0].next().unwrap().field;
+    assert_eq!(pages[1].field.cells[21][..3], [2, 2, 0]);
+    let decoded = fumen::decode(&fumen::encode(&pages).unwrap(), 22).unwrap();
+    assert_eq!(decoded, pages);
+    // the empty fields of the pages without the locked pieces are repeated
+    let mut repeated = decoded.clone();
+    repeated.extend(vec![decoded[1].clone(); 100]);
+    let data_repeated = fumen::encode(&repeated).unwrap();
+    assert_eq!(fumen::decode(&data_repeated, 22).unwrap(), repeated);
+    assert!(data_repeated.contains('?'));
+}
+
+#[test]
+fn test_rise_and_mirror() {
+    let mut page = Page::new(field!("
+        . . . . . . . . . .
+        L . . . . . . . . .
+        L L . . . . . . . .
+    "), None);
+    page.garbage = vec![8, 8, 8, 8, 8, 8, 8, 8, 8, 0];
+    page.rise = true;
+    page.mirror = true;
+    let next = page.next().unwrap();
+    let pages = fumen::decode(&fumen::encode(&[page, next.clone()]).unwrap(), 3).unwrap();
+    assert_eq!(pages[1], next);
+    let expected = field!("
+        . . . . . . . . . L
+        . . . . . . . . L L
+        . * * * * * * * * *
+    ");
+    assert_eq!(next.field, expected);
+    assert_eq!(next.garbage, vec![0; 10]);
+}
+
+#[test]
+fn test_game_state() {
+    let mut gs = GameState::initial(22, 10, Default::default(), Some(7));
+    gs.step(Action::Left);
+    gs.step(Action::HardDrop);
+    let data = fumen::encode_game_state(&gs).unwrap();
+    let restored = fumen::decode_game_state(&data, &GameState::initial(22, 10, Default::default(), Some(1))).unwrap();
+    assert_eq!(restored.field, gs.field);
+    assert_eq!(restored.curr_shape_idx, gs.curr_shape_idx);
+    assert_eq!(restored.curr_cells, gs.curr_cells);
+}
+
+#[test]
+fn test_replay_pages() {
+    let gs = GameState::initial(22, 10, Default::default(), Some(3));
+    let actions: Vec<DQNAction> = (0..4)
+        .map(|k| DQNAction { base: Point(1, 1 + 2 * k), rotation: 0, hold: false, last_kick: None })
+        .collect();
+    let pages = fumen::replay_pages(&gs, &actions);
+    assert_eq!(pages.len(), 4);
+    assert!(pages[0].field.is_empty());
+    let data = fumen::encode(&pages).unwrap();
+    let decoded = fumen::decode(&data, 22).unwrap();
+    assert_eq!(decoded, pages);
+}
+
+fn empty_field(height: usize) -> Field {
+    Field { cells: vec![vec![0; 10]; height], height, width: 10 }
+}
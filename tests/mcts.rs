@@ -0,0 +1,134 @@
+#![feature(type_ascription)]
+
+use rand::{Rng, SeedableRng};
+use rand_xoshiro::Xoshiro512StarStar;
+use tetris::agent::{Agent, DQNAction, DQNAgent, Evaluator, MCTSAgent, MCTSConf, QNetwork, RandomRollout, Selection};
+use tetris::train::TetrisEnv;
+use tetris::config::{Config, Scoring};
+
+fn rollout() -> RandomRollout {
+    RandomRollout { depth: 0, discount: 0.99 }
+}
+
+/// The I is current, the column 9 is empty under the 4 full rows
+fn tetris_ready(seed: u64) -> TetrisEnv {
+    // every lock is worth 1 with `BurnOnly`, so the lines should be scored
+    let config = Config { scoring: Scoring::Guideline, ..Config::default() };
+    let mut env = TetrisEnv::with_config(config, Some(seed));
+    env.reset();
+    for i in 18..22 {
+        env.gs.field.cells[i] = vec![8, 8, 8, 8, 8, 8, 8, 8, 8, 0];
+    }
+    env.gs.spawn_shape(0);
+    env
+}
+
+#[test]
+fn test_search_statistics() {
+    let env = tetris_ready(1);
+    let conf = MCTSConf { simulations: 60, ..Default::default() };
+    let mut agent = MCTSAgent::new(conf, rollout(), Some(1));
+    let stats = agent.search(&env);
+    let actions: Vec<_> = stats.iter().map(|(a, _, _)| *a).collect();
+    assert_eq!(actions, env.get_valid_actions());
+    // the first simulation expands the root
+    assert_eq!(stats.iter().map(|(_, n, _)| n).sum::<u32>(), 59);
+    assert!(agent.tree_size() > stats.len());
+}
+
+#[test]
+fn test_finds_the_tetris() {
+    let selections = [Selection::Uct { c: 1.0 }, Selection::Puct { c: 1.5 }];
+    for selection in selections.iter() {
+        let mut env = tetris_ready(2);
+        let conf = MCTSConf { simulations: 200, selection: *selection, ..Default::default() };
+        let mut agent = MCTSAgent::new(conf, rollout(), Some(2));
+        let action = agent.select_action(&env).unwrap();
+        env.step(action);
+        assert_eq!(env.lines_burnt, 4, "{:?}", selection);
+    }
+}
+
+#[test]
+fn test_subtree_reuse() {
+    for reuse_tree in [true, false].iter() {
+        let mut env = TetrisEnv::new(Some(3));
+        env.reset();
+        let conf = MCTSConf { simulations: 300, reuse_tree: *reuse_tree, ..Default::default() };
+        let mut agent = MCTSAgent::new(conf, RandomRollout { depth: 1, discount: 0.99 }, Some(3));
+        let action = agent.select_action(&env).unwrap();
+        env.step(action);
+        agent.conf.simulations = 0;
+        // the kept subtree has the statistics of the new position already
+        let visits: u32 = agent.search(&env).iter().map(|(_, n, _)| n).sum();
+        assert_eq!(visits > 0, *reuse_tree);
+        assert_eq!(agent.tree_size() > 1, *reuse_tree);
+    }
+}
+
+#[test]
+fn test_same_seed_same_moves() {
+    let play = || {
+        let mut env = TetrisEnv::new(Some(4));
+        env.reset();
+        let conf = MCTSConf { simulations: 30, ..Default::default() };
+        let mut agent = MCTSAgent::new(conf, RandomRollout { depth: 2, discount: 0.99 }, Some(4));
+        (0..5)
+            .map(|_| {
+                let action = agent.select_action(&env).unwrap();
+                env.step(action);
+                action
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(play(), play());
+}
+
+#[test]
+fn test_q_network_evaluator() {
+    let env = tetris_ready(5);
+    let actions = env.get_valid_actions();
+    let mut evaluator = QNetwork { agent: DQNAgent::new(Some(5)), temperature: 1.0 };
+    let mut rng = Xoshiro512StarStar::seed_from_u64(5);
+    let (value, priors) = evaluator.evaluate(&env, &actions, &mut rng);
+    assert_eq!(priors.len(), actions.len());
+    assert!((priors.iter().sum::<f32>() - 1.0).abs() < 1e-4);
+    assert!(value.is_finite());
+    let conf = MCTSConf { simulations: 10, ..Default::default() };
+    let mut agent = MCTSAgent::new(conf, evaluator, Some(5));
+    assert!(actions.contains(&agent.select_action(&env).unwrap()));
+}
+
+#[test]
+fn test_max_nodes() {
+    let mut env = TetrisEnv::new(Some(6));
+    env.reset();
+    let conf = MCTSConf { simulations: 300, max_nodes: 50, ..Default::default() };
+    let mut agent = MCTSAgent::new(conf, RandomRollout { depth: 1, discount: 0.99 }, Some(6));
+    for _ in 0..3 {
+        let action = agent.select_action(&env).unwrap();
+        assert!(agent.tree_size() <= 50);
+        env.step(action);
+    }
+}
+
+/// Values everything with NaN
+struct NanEvaluator;
+
+impl Evaluator for NanEvaluator {
+    fn evaluate<R: Rng>(&mut self, _env: &TetrisEnv, actions: &[DQNAction], _rng: &mut R) -> (f32, Vec<f32>) {
+        (f32::NAN, vec![f32::NAN; actions.len()])
+    }
+}
+
+#[test]
+fn test_nan_values() {
+    let env = tetris_ready(7);
+    let selections = [Selection::Uct { c: 1.0 }, Selection::Puct { c: 1.5 }];
+    for selection in selections.iter() {
+        let conf = MCTSConf { simulations: 30, selection: *selection, ..Default::default() };
+        let mut agent = MCTSAgent::new(conf, NanEvaluator, Some(7));
+        let action = agent.select_action(&env).unwrap();
+        assert!(env.get_valid_actions().contains(&action), "{:?}", selection);
+    }
+}
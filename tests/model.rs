@@ -1,7 +1,10 @@
 #![feature(type_ascription)]
 
-use tetris::model::{Point, Field, try_shape, rotate, GameState, Action};
-use tetris::tetrimino::{Tetrimino, build_tetrimino, I, O, L, J, T, S, Z, Style};
+use tetris::model::{Point, Field, try_shape, try_shape_idx, rotate, rotated, GameState, Action, TSpin, detect_tspin};
+use tetris::tetrimino::{Tetrimino, TETRIMINOES, build_tetrimino, I, O, L, J, T, S, Z, Style};
+use tetris::config::{Config, Scoring, Randomness, Timing, Spawn, TopOut};
+use tetris::field;
+use tetris::utils::Trim;
 
 #[test]
 fn test_build_tetrimino_i() {
@@ -136,6 +139,30 @@ fn test_try_position() {
     assert_eq!(try_shape(&field, &Point(0, 1), 1, &piece_i), expected);
 }
 
+#[test]
+fn test_precomputed_rotations() {
+    let field = field!("
+        . . . . .
+        . . . . .
+        . * . . .
+        . . . * *
+    ");
+    for (shape_idx, shape) in TETRIMINOES.iter().enumerate() {
+        for r in -4..8 {
+            assert_eq!(rotated(shape_idx, r).to_vec(), rotate(shape, r));
+            for i in -1..5 {
+                for j in -1..6 {
+                    let base = Point(i, j);
+                    assert_eq!(
+                        try_shape_idx(&field, &base, r, shape_idx).map(|cells| cells.to_vec()),
+                        try_shape(&field, &base, r, shape),
+                    );
+                }
+            }
+        }
+    }
+}
+
 #[test]
 fn test_burn() {
     let field = Field {
@@ -183,17 +210,387 @@ fn test_hard_drop() {
     let mut gs = GameState::initial(6, 4, Default::default(), Some(22));
     assert_eq!(gs.curr_shape_idx, 1);
     gs.field = field;
-    let (lines_burnt, done) = gs.step(Action::HardDrop);
-    assert_eq!(lines_burnt, 0);
-    assert_eq!(done, true);
+    let result = gs.step(Action::HardDrop);
+    assert_eq!(result.lines_burnt(), 0);
+    assert_eq!(result.game_over, true);
+}
+
+#[test]
+fn test_hold() {
+    let config = Config {
+        scoring: Scoring::BurnOnly,
+        randomness: Randomness::ShuffledQueue,
+        hold_enabled: true,
+        preview_len: 1,
+        timing: None,
+        track_finesse: false,
+        ..Config::default()
+    };
+    let mut gs = GameState::initial(22, 10, config, Some(5));
+    let shape_1 = gs.curr_shape_idx;
+    let shape_2 = gs.next_shape_idxs[0];
+    // the empty slot takes the current shape, the next one is spawned
+    gs.step(Action::Hold);
+    assert_eq!(gs.hold_shape_idx, Some(shape_1));
+    assert_eq!(gs.curr_shape_idx, shape_2);
+    assert_eq!(gs.rng_queue.len(), 4);
+    let shape_3 = gs.next_shape_idxs[0];
+    // the second hold in a row is ignored
+    gs.step(Action::Hold);
+    assert_eq!(gs.hold_shape_idx, Some(shape_1));
+    assert_eq!(gs.curr_shape_idx, shape_2);
+    // after the drop the hold is swapped without touching the queue
+    gs.step(Action::HardDrop);
+    assert_eq!(gs.curr_shape_idx, shape_3);
+    let rng_queue = gs.rng_queue.clone();
+    gs.step(Action::Hold);
+    assert_eq!(gs.hold_shape_idx, Some(shape_3));
+    assert_eq!(gs.curr_shape_idx, shape_1);
+    assert_eq!(gs.rng_queue, rng_queue);
+    assert_eq!(gs.base, gs.spawn_base(shape_1));
+    assert_eq!(gs.rotation, 0);
+}
+
+#[test]
+fn test_hold_disabled() {
+    let mut gs = GameState::initial(22, 10, Default::default(), Some(5));
+    let curr_shape_idx = gs.curr_shape_idx;
+    gs.step(Action::Hold);
+    assert_eq!(gs.hold_shape_idx, None);
+    assert_eq!(gs.curr_shape_idx, curr_shape_idx);
+}
+
+#[test]
+fn test_preview_queue() {
+    let config = Config {
+        scoring: Scoring::BurnOnly,
+        randomness: Randomness::ShuffledQueue,
+        hold_enabled: false,
+        preview_len: 5,
+        timing: None,
+        track_finesse: false,
+        ..Config::default()
+    };
+    let mut gs = GameState::initial(22, 10, config, Some(11));
+    assert_eq!(gs.next_shape_idxs.len(), 5);
+    // the current shape and the preview come from the same bag
+    let mut shapes = vec![gs.curr_shape_idx];
+    shapes.extend(gs.next_shape_idxs.iter());
+    shapes.extend(gs.rng_queue.iter());
+    shapes.sort();
+    assert_eq!(shapes, vec![0, 1, 2, 3, 4, 5, 6]);
+    // the preview shifts by one on every spawn
+    let preview = gs.next_shape_idxs.clone();
+    gs.step(Action::HardDrop);
+    assert_eq!(gs.curr_shape_idx, preview[0]);
+    assert_eq!(gs.next_shape_idxs.len(), 5);
+    assert_eq!(gs.next_shape_idxs.iter().take(4).collect::<Vec<_>>(),
+               preview.iter().skip(1).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_gravity_interval() {
+    let mut gs = GameState::initial(22, 10, Default::default(), Some(5));
+    assert_eq!(gs.level, 1);
+    assert_eq!(gs.gravity_interval(), 1000);
+    gs.level = 2;
+    assert_eq!(gs.gravity_interval(), 793);
+    gs.level = 15;
+    assert_eq!(gs.gravity_interval(), 7);
+}
+
+#[test]
+fn test_lock_delay() {
+    let config = Config {
+        timing: Some(Timing::default()),
+        ..Default::default()
+    };
+    let mut gs = GameState::initial(6, 4, config, Some(22));
+    assert_eq!(gs.curr_shape_idx, 1); // O
+    // the gravity moves the piece one row per second at level 1
+    gs.advance(999, false);
+    assert_eq!(gs.base, Point(0, 1));
+    gs.advance(1, false);
+    assert_eq!(gs.base, Point(1, 1));
+    // the soft drop brings it to the ground
+    for _ in 0..3 {
+        gs.advance(50, true);
+    }
+    assert_eq!(gs.base, Point(4, 1));
+    assert!(gs.field.cells.iter().flatten().all(|c| *c == 0));
+    // the move on the ground resets the lock delay
+    gs.advance(400, false);
+    gs.step(Action::Left);
+    gs.advance(400, false);
+    assert!(gs.field.cells.iter().flatten().all(|c| *c == 0));
+    gs.advance(100, false);
+    assert_eq!(gs.field.cells[5], vec![2, 2, 0, 0]);
+}
+
+#[test]
+fn test_lock_resets_limit() {
+    let config = Config {
+        timing: Some(Timing { max_lock_resets: 3, ..Timing::default() }),
+        ..Default::default()
+    };
+    let mut gs = GameState::initial(6, 4, config, Some(22));
+    gs.step(Action::HardDrop);
+    gs.step(Action::Down);
+    while !gs.is_on_ground() {
+        gs.step(Action::Down);
+    }
+    let shape_idx = gs.curr_shape_idx;
+    for _ in 0..3 {
+        gs.advance(100, false);
+        gs.step(Action::Left);
+        gs.step(Action::Right);
+    }
+    // the resets are exhausted, the piece on the ground is locked at once
+    assert_eq!(gs.clock.lock_resets, 3);
+    gs.advance(1, false);
+    assert_ne!(gs.clock.lock_resets, 3);
+    assert!(gs.field.cells.iter().flatten().any(|c| *c == shape_idx as u8 + 1));
+}
+
+#[test]
+fn test_tspin_double() {
+    let field = Field {
+        cells: vec![
+            vec![0, 0, 0, 0, 0],
+            vec![0, 0, 0, 0, 0],
+            vec![1, 0, 0, 0, 0],
+            vec![0, 0, 0, 1, 1],
+            vec![1, 0, 1, 1, 1],
+        ],
+        height: 5,
+        width: 5,
+    };
+    let config = Config {
+        scoring: Scoring::Guideline,
+        ..Default::default()
+    };
+    let mut gs = GameState::initial(5, 5, config, Some(12));
+    gs.field = field;
+    gs.curr_shape_idx = 2; // T
+    gs.rotation = 1;
+    gs.base = Point(3, 1);
+    gs.curr_cells = gs.try_current_shape(&gs.base, gs.rotation).unwrap().to_vec();
+    gs.step(Action::RotateCW);
+    assert_eq!(gs.last_kick, Some(0));
+    let result = gs.step(Action::HardDrop);
+    assert_eq!(result.lines_burnt(), 2);
+    assert_eq!(gs.last_clear.tspin, TSpin::Full);
+    assert_eq!(gs.score, 1200);
+}
+
+#[test]
+fn test_detect_tspin() {
+    let field = Field {
+        cells: vec![
+            vec![0, 0, 0, 0],
+            vec![0, 0, 0, 0],
+            vec![1, 0, 0, 0],
+            vec![0, 0, 0, 1],
+        ],
+        height: 4,
+        width: 4,
+    };
+    // T pointing up on the floor, only one of the front corners is occupied
+    assert_eq!(detect_tspin(&field, 2, &Point(3, 1), 0, Some(0)), TSpin::Mini);
+    // the last wall kick test upgrades the mini T-spin
+    assert_eq!(detect_tspin(&field, 2, &Point(3, 1), 0, Some(4)), TSpin::Full);
+    // no rotation, no spin
+    assert_eq!(detect_tspin(&field, 2, &Point(3, 1), 0, None), TSpin::None);
+    // only T can spin
+    assert_eq!(detect_tspin(&field, 5, &Point(3, 1), 0, Some(0)), TSpin::None);
+    // 2 corners are not enough
+    assert_eq!(detect_tspin(&field, 2, &Point(2, 2), 0, Some(0)), TSpin::None);
 }
 
+#[test]
+fn test_combo_and_perfect_clear() {
+    let config = Config {
+        scoring: Scoring::Versus,
+        ..Default::default()
+    };
+    let mut gs = GameState::initial(6, 4, config, Some(3));
+    // the horizontal I on the empty field
+    gs.curr_shape_idx = 0;
+    gs.rotation = 0;
+    gs.base = Point(0, 1);
+    gs.curr_cells = gs.try_current_shape(&gs.base, gs.rotation).unwrap().to_vec();
+    let clear = gs.step(Action::HardDrop).clear.unwrap();
+    assert_eq!(clear.lines_burnt, 1);
+    assert_eq!(clear.combo, 1);
+    assert_eq!(clear.perfect_clear, true);
+    assert_eq!(gs.last_clear, clear);
+    assert_eq!(gs.score, 10);
+    // the moves lock nothing, the last clear is kept
+    assert_eq!(gs.step(Action::Left).clear, None);
+    assert_eq!(gs.last_clear, clear);
+    // the vertical I burns 2 more lines
+    gs.field.cells[4] = vec![1, 1, 1, 0];
+    gs.field.cells[5] = vec![1, 1, 1, 0];
+    gs.curr_shape_idx = 0;
+    gs.rotation = 1;
+    gs.base = Point(1, 2);
+    gs.curr_cells = gs.try_current_shape(&gs.base, gs.rotation).unwrap().to_vec();
+    let clear = gs.step(Action::HardDrop).clear.unwrap();
+    assert_eq!(clear.lines_burnt, 2);
+    assert_eq!(clear.combo, 2);
+    assert_eq!(clear.perfect_clear, false);
+    assert_eq!(clear.back_to_back, false);
+    assert_eq!(gs.score, 10 + 1);
+    // the lock without lines breaks the combo
+    gs.curr_shape_idx = 1;
+    gs.rotation = 0;
+    gs.base = gs.spawn_base(1);
+    gs.curr_cells = gs.try_current_shape(&gs.base, gs.rotation).unwrap().to_vec();
+    let clear = gs.step(Action::HardDrop).clear.unwrap();
+    assert_eq!(clear.lines_burnt, 0);
+    assert_eq!(clear.combo, 0);
+    assert_eq!(gs.combo, 0);
+}
+
+#[test]
+fn test_back_to_back() {
+    let config = Config {
+        scoring: Scoring::Versus,
+        ..Default::default()
+    };
+    let mut gs = GameState::initial(8, 4, config, Some(3));
+    for k in 0..2 {
+        for i in 4..8 {
+            gs.field.cells[i] = vec![1, 1, 1, 0];
+        }
+        gs.curr_shape_idx = 0;
+        gs.rotation = 1;
+        gs.base = Point(1, 2);
+        gs.curr_cells = gs.try_current_shape(&gs.base, gs.rotation).unwrap().to_vec();
+        let clear = gs.step(Action::HardDrop).clear.unwrap();
+        assert_eq!(clear.lines_burnt, 4);
+        assert_eq!(clear.back_to_back, k == 1);
+    }
+    // tetris + perfect clear, then tetris + combo + back-to-back + perfect clear
+    assert_eq!(gs.score, (4 + 10) + (4 + 1 + 10));
+}
 
-pub fn build_field(_: &str) -> Field {
-    // TODO implement this to be similar to the build_shape
-    Field {
-        cells: vec![],
-        height: 0,
-        width: 0,
+#[test]
+fn test_build_field() {
+    let field = build_field(r#"
+        . . . .
+        T . . .
+        T T * .
+        T I I I
+    "#);
+    let expected = Field {
+        cells: vec![
+            vec![0, 0, 0, 0],
+            vec![3, 0, 0, 0],
+            vec![3, 3, 8, 0],
+            vec![3, 1, 1, 1],
+        ],
+        height: 4,
+        width: 4,
+    };
+    assert_eq!(field, expected);
+    // the same with the macro and without spaces
+    assert_eq!(field!("....\nT...\nTT*.\nTIII"), expected);
+}
+
+#[test]
+fn test_field_display() {
+    let src = "
+        . . . . .
+        . O O . .
+        Z O O * S
+        J Z Z L L
+    ";
+    let field = build_field(src);
+    assert_eq!(field.to_string(), src.trim_indent());
+    assert_eq!(build_field(&field.to_string()), field);
+}
+
+#[test]
+fn test_build_field_errors() {
+    assert!(". . .\n. .".parse::<Field>().is_err());
+    assert!(". X .".parse::<Field>().is_err());
+}
+
+pub fn build_field(src: &str) -> Field {
+    src.parse().unwrap()
+}
+
+#[test]
+fn test_burn_lines_keeps_top_row() {
+    let mut gs = GameState::initial(4, 4, Config::default(), Some(1));
+    gs.field = build_field("
+        I . . .
+        O . . .
+        T T T T
+        S . . .
+    ");
+    assert_eq!(gs.burn_lines(), 1);
+    assert_eq!(gs.field, build_field("
+        . . . .
+        I . . .
+        O . . .
+        S . . .
+    "));
+    assert_eq!(gs.burn_lines(), 0);
+    assert_eq!(gs.field.cells[1][0], 1);
+}
+
+#[test]
+fn test_board_size_config() {
+    // the default is the field 22x10 with 2 hidden rows
+    let gs = GameState::from_config(Config::default(), Some(1));
+    assert_eq!((gs.field.height, gs.field.width), (22, 10));
+    let gs = GameState::initial(22, 10, Config::default(), Some(1));
+    assert_eq!((gs.config.height, gs.config.width, gs.config.hidden_rows), (20, 10, 2));
+
+    let config = Config { height: 10, width: 6, hidden_rows: 1, ..Config::default() };
+    let mut gs = GameState::from_config(config, Some(1));
+    assert_eq!((gs.field.height, gs.field.width), (11, 6));
+    gs.spawn_shape(1); // O
+    assert_eq!(gs.base, Point(0, 2));
+    let mut drops = 0;
+    while !gs.game_over {
+        gs.step(Action::HardDrop);
+        drops += 1;
+    }
+    // the middle columns fill up in 5 or 6 pieces
+    assert!(drops < 10, "{}", drops);
+}
+
+#[test]
+fn test_custom_spawn() {
+    let bases = [Point(2, 1), Point(1, 0), Point(2, 1), Point(2, 1), Point(2, 1), Point(2, 1), Point(2, 1)];
+    let config = Config { spawn: Spawn::Custom(bases), ..Config::default() };
+    let mut gs = GameState::from_config(config, Some(1));
+    gs.spawn_shape(1); // O
+    assert_eq!(gs.base, Point(1, 0));
+    assert_eq!(gs.curr_cells, vec![Point(1, 0), Point(1, 1), Point(2, 0), Point(2, 1)]);
+    gs.spawn_shape(2); // T
+    assert_eq!(gs.base, Point(2, 1));
+}
+
+#[test]
+fn test_top_out() {
+    for (top_out, game_over) in [(TopOut::BlockOut, false), (TopOut::LockOut, true)].iter() {
+        let config = Config { height: 4, width: 8, top_out: *top_out, ..Config::default() };
+        let mut gs = GameState::from_config(config, Some(1));
+        for i in 2..6 {
+            gs.field.cells[i][0] = 8;
+            gs.field.cells[i][1] = 8;
+        }
+        gs.spawn_shape(1); // O
+        for _ in 0..3 {
+            gs.step(Action::Left);
+        }
+        gs.step(Action::HardDrop);
+        // the O lies in the hidden rows 0 and 1
+        assert_eq!(gs.field.cells[0][..2], [2, 2]);
+        assert_eq!(gs.game_over, *game_over);
     }
 }
@@ -0,0 +1,162 @@
+#![feature(type_ascription)]
+
+use std::collections::HashSet;
+use tetris::model::{Point, GameState, Action, TSpin};
+use tetris::agent::DQNAction;
+use tetris::movegen::find_placements;
+use tetris::train::TetrisEnv;
+use tetris::config::{Config, Scoring};
+use tetris::field;
+
+#[test]
+fn test_empty_field_placements() {
+    let mut gs = GameState::initial(22, 10, Config::default(), Some(1));
+    // I, O, T, S, Z, J, L
+    let expected = [17, 9, 34, 17, 17, 34, 34];
+    for (shape_idx, count) in expected.iter().enumerate() {
+        gs.spawn_shape(shape_idx);
+        let placements = find_placements(&gs);
+        let cells: HashSet<_> = placements.iter().map(|p| p.cells.clone()).collect();
+        assert_eq!(cells.len(), *count, "shape {}", shape_idx);
+        assert!(placements.iter().all(|p| p.path.last() == Some(&Action::HardDrop)));
+    }
+}
+
+#[test]
+fn test_tuck() {
+    let mut gs = GameState::initial(6, 10, Config::default(), Some(1));
+    gs.field = field!("
+        . . . . . . . . . .
+        . . . . . . . . . .
+        . . . . . . . . . .
+        * * * * * * . . . .
+        . . . . . . . . . .
+        . . . . . . . . . .
+    ");
+    gs.spawn_shape(1); // O
+    let placements = find_placements(&gs);
+    let tuck = placements.iter()
+        .find(|p| p.cells == vec![Point(4, 0), Point(4, 1), Point(5, 0), Point(5, 1)])
+        .expect("the tuck under the overhang");
+    let down = tuck.path.iter().position(|a| *a == Action::Down).unwrap();
+    assert!(tuck.path[down..].contains(&Action::DasLeft));
+    // the agents see it
+    let env = TetrisEnv::from_state(gs);
+    assert!(env.get_valid_actions().contains(&tuck.action));
+}
+
+#[test]
+fn test_tspin_double_placement() {
+    let config = Config {
+        scoring: Scoring::Guideline,
+        ..Default::default()
+    };
+    let mut gs = GameState::initial(5, 5, config, Some(12));
+    gs.field = field!("
+        . . . . .
+        . . . . .
+        I . . . .
+        . . . I I
+        I . I I I
+    ");
+    gs.spawn_shape(2); // T
+    let placements = find_placements(&gs);
+    let tsd = placements.iter().find(|p| p.tspin == TSpin::Full).expect("the T-spin double");
+    assert_eq!(tsd.path[tsd.path.len() - 2], Action::RotateCW);
+    let mut env = TetrisEnv::from_state(gs);
+    let (state, reward, _) = env.step_placement(tsd);
+    assert_eq!(state.lines_burnt, 2);
+    assert_eq!(env.clear.tspin, TSpin::Full);
+    assert_eq!(reward, 1200.0);
+}
+
+#[test]
+fn test_tspin_slot_in_valid_actions() {
+    let config = Config {
+        scoring: Scoring::Guideline,
+        ..Default::default()
+    };
+    let mut gs = GameState::initial(5, 5, config, Some(12));
+    gs.field = field!("
+        . . . . .
+        . . . . .
+        I . . . .
+        . . . I I
+        I . I I I
+    ");
+    gs.spawn_shape(2); // T
+    let env = TetrisEnv::from_state(gs);
+    let spins: Vec<_> = env.get_valid_actions().into_iter().filter(|a| a.last_kick.is_some()).collect();
+    let tsd = spins.iter()
+        .find(|a| {
+            let mut env = env.clone();
+            env.step(**a);
+            env.clear.tspin == TSpin::Full
+        })
+        .expect("the T-spin double among the valid actions");
+    let mut spun = env.clone();
+    let (state, reward, _) = spun.step(*tsd);
+    assert_eq!(state.lines_burnt, 2);
+    assert_eq!(reward, 1200.0);
+    // the same cells without the rotation at the end is a plain double
+    let mut plain = env.clone();
+    let (_, reward, _) = plain.step(DQNAction { last_kick: None, ..*tsd });
+    assert_eq!(plain.gs.field, spun.gs.field);
+    assert_eq!(plain.clear.tspin, TSpin::None);
+    assert_eq!(reward, 300.0);
+}
+
+#[test]
+fn test_paths_and_valid_actions() {
+    let mut gs = GameState::initial(8, 10, Config::default(), Some(1));
+    gs.field = field!("
+        . . . . . . . . . .
+        . . . . . . . . . .
+        . . . . . . . . . .
+        . . . . . . . . . .
+        * * . . . . . . . .
+        . . . . . * * . . .
+        * . * * . * . . . *
+        * . * * * * * . * *
+    ");
+    for shape_idx in 0..7 {
+        gs.spawn_shape(shape_idx);
+        let placements = find_placements(&gs);
+        for placement in &placements {
+            let mut gs = gs.clone();
+            let mut lines_burnt = 0;
+            for action in &placement.path {
+                lines_burnt += gs.step(*action).lines_burnt();
+            }
+            if lines_burnt == 0 {
+                assert!(placement.cells.iter().all(|p| gs.field.cells[p.0 as usize][p.1 as usize] == shape_idx as u8 + 1));
+            }
+        }
+        // every valid action lands as one of the placements
+        let cells: HashSet<_> = placements.iter().map(|p| p.cells.clone()).collect();
+        let env = TetrisEnv::from_state(gs.clone());
+        for action in env.get_valid_actions() {
+            let mut gs = gs.clone();
+            gs.base = action.base;
+            gs.rotation = action.rotation;
+            let (i_new, _) = gs.drop_current_shape();
+            let mut landed = gs.try_current_shape(&Point(i_new - 1, action.base.1), action.rotation).unwrap();
+            landed.sort_by_key(|p| (p.0, p.1));
+            assert!(cells.contains(&landed[..]), "shape {} action {:?}", shape_idx, action);
+        }
+    }
+}
+
+#[test]
+fn test_reachable_placements_with_hold() {
+    let config = Config {
+        hold_enabled: true,
+        ..Default::default()
+    };
+    let env = TetrisEnv::from_state(GameState::initial(22, 10, config, Some(5)));
+    let placements = env.get_reachable_placements();
+    let held: Vec<_> = placements.iter().filter(|p| p.action.hold).collect();
+    assert!(!held.is_empty());
+    assert!(held.iter().all(|p| p.path[0] == Action::Hold));
+    assert!(placements.iter().filter(|p| !p.action.hold).all(|p| p.path[0] != Action::Hold));
+}
@@ -0,0 +1,114 @@
+#![feature(type_ascription)]
+
+use rand::SeedableRng;
+use rand_xoshiro::Xoshiro512StarStar;
+use tetris::agent::{ReplayBuffer, ReplayMode, SumTree};
+
+fn filled(capacity: usize, mode: ReplayMode) -> ReplayBuffer<usize> {
+    let mut buffer = ReplayBuffer::new(capacity, mode);
+    for item in 0..capacity {
+        buffer.push(item);
+    }
+    buffer
+}
+
+#[test]
+fn test_sum_tree() {
+    let mut tree = SumTree::new(5);
+    for (i, p) in [1.0, 0.0, 2.0, 3.0, 4.0].iter().enumerate() {
+        tree.update(i, *p);
+    }
+    assert_eq!(tree.total(), 10.0);
+    tree.update(4, 0.5);
+    assert_eq!(tree.total(), 6.5);
+    assert_eq!(tree.get(2), 2.0);
+    // every leaf gets the share of the values equal to its priority
+    let mut counts = [0; 5];
+    for k in 0..650 {
+        counts[tree.find(k as f64 / 100.0)] += 1;
+    }
+    assert_eq!(counts, [100, 0, 200, 300, 50]);
+}
+
+#[test]
+fn test_uniform_sampling() {
+    let mut rng = Xoshiro512StarStar::seed_from_u64(1);
+    let mut buffer = filled(10, ReplayMode::Uniform);
+    let mut counts = [0; 10];
+    for _ in 0..20000 {
+        let batch = buffer.sample(&mut rng, 5);
+        assert_eq!(batch.weights, vec![1.0; 5]);
+        let mut indices = batch.indices.clone();
+        indices.sort();
+        indices.dedup();
+        assert_eq!(indices.len(), 5);
+        for i in batch.indices {
+            counts[buffer[i]] += 1;
+        }
+    }
+    // every transition is in the half of the batches
+    assert!(counts.iter().all(|c| (*c as f64 - 10000.0).abs() < 300.0), "{:?}", counts);
+}
+
+#[test]
+fn test_prioritized_sampling() {
+    let mut rng = Xoshiro512StarStar::seed_from_u64(2);
+    let mode = ReplayMode::Prioritized { alpha: 1.0, beta: 0.5, beta_increment: 0.0, eps: 0.0 };
+    let mut buffer = filled(4, mode);
+    buffer.update_priorities(&[0, 1, 2, 3], &[1.0, -2.0, 3.0, 4.0]);
+    let mut counts = [0; 4];
+    let samples = 40000;
+    for _ in 0..samples / 4 {
+        let batch = buffer.sample(&mut rng, 4);
+        for i in batch.indices {
+            counts[i] += 1;
+        }
+    }
+    for (i, count) in counts.iter().enumerate() {
+        let expected = (i + 1) as f64 / 10.0;
+        let frequency = *count as f64 / samples as f64;
+        assert!((frequency - expected).abs() < 0.01, "{:?}", counts);
+    }
+}
+
+#[test]
+fn test_importance_sampling_weights() {
+    let mut rng = Xoshiro512StarStar::seed_from_u64(3);
+    let mode = ReplayMode::Prioritized { alpha: 1.0, beta: 0.5, beta_increment: 0.25, eps: 0.0 };
+    let mut buffer = filled(4, mode);
+    buffer.update_priorities(&[0, 1, 2, 3], &[1.0, 1.0, 1.0, 4.0]);
+    let batch = buffer.sample(&mut rng, 8);
+    for (i, w) in batch.indices.iter().zip(batch.weights.iter()) {
+        // the rarest transitions get the weight 1, (N * P)^-beta is 4^-0.5 times less for the last one
+        let expected = if *i == 3 { 0.5 } else { 1.0 };
+        assert!((w - expected).abs() < 1e-6);
+    }
+    // beta grows to 1, so the weights compensate the priorities completely
+    buffer.sample(&mut rng, 8);
+    let batch = buffer.sample(&mut rng, 8);
+    let w = batch.indices.iter().zip(batch.weights.iter()).find(|(i, _)| **i == 3).unwrap().1;
+    assert!((w - 0.25).abs() < 1e-6);
+}
+
+#[test]
+fn test_capacity() {
+    let mut rng = Xoshiro512StarStar::seed_from_u64(4);
+    let mut buffer = filled(3, ReplayMode::prioritized());
+    buffer.update_priorities(&[0, 1, 2], &[0.0, 0.0, 0.0]);
+    buffer.push(3);
+    buffer.push(4);
+    assert_eq!(buffer.len(), 3);
+    assert_eq!(buffer.capacity(), 3);
+    let mut items: Vec<usize> = buffer.iter().cloned().collect();
+    items.sort();
+    assert_eq!(items, vec![2, 3, 4]);
+    // the new transitions have the max priority, so they are sampled mostly
+    let mut new = 0;
+    for _ in 0..100 {
+        let batch = buffer.sample(&mut rng, 1);
+        if buffer[batch.indices[0]] >= 3 {
+            new += 1;
+        }
+    }
+    assert!(new > 90, "{}", new);
+}
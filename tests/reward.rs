@@ -0,0 +1,108 @@
+#![feature(type_ascription)]
+
+use std::sync::Arc;
+use tetris::agent::AgentConf;
+use tetris::model::{ClearInfo, GameState};
+use tetris::reward::{Reward, RewardFn, Rewards};
+use tetris::train::TetrisEnv;
+use tetris::config::Config;
+use tetris::field;
+
+fn states() -> (GameState, GameState) {
+    let mut before = GameState::initial(4, 4, Config::default(), Some(1));
+    before.field = field!("
+        . . . .
+        . . . .
+        * . * *
+        * * . *
+    ");
+    let mut after = before.clone();
+    after.field = field!("
+        . . . .
+        . * . .
+        * . * .
+        * * . *
+    ");
+    after.score = before.score + 40;
+    (before, after)
+}
+
+fn reward(rewards: &[Reward], before: &GameState, after: &GameState, clear: &ClearInfo) -> f32 {
+    Rewards::new(rewards).unwrap().reward(before, after, clear)
+}
+
+#[test]
+fn test_builtin_rewards() {
+    let (before, mut after) = states();
+    let clear = ClearInfo { lines_burnt: 2, ..ClearInfo::default() };
+    assert_eq!(reward(&[Reward::ScoreDelta], &before, &after, &clear), 40.0);
+    assert_eq!(reward(&[Reward::LinesSquared], &before, &after, &clear), 4.0);
+    assert_eq!(reward(&[Reward::Survival { bonus: 0.5 }], &before, &after, &clear), 0.5);
+    assert_eq!(reward(&[Reward::GameOver { penalty: 10.0 }], &before, &after, &clear), 0.0);
+    // 2 holes in the columns 1 and 3
+    let holes = Reward::Penalty { feature: "holes".into(), weight: 0.5 };
+    assert_eq!(reward(std::slice::from_ref(&holes), &before, &after, &clear), -1.0);
+    let height = Reward::Penalty { feature: "max_height".into(), weight: 1.0 };
+    assert_eq!(reward(&[height], &before, &after, &clear), -3.0);
+    // phi goes from -1 to -2
+    let potential = Reward::Potential { features: vec![("holes".into(), 1.0)], discount: 0.5 };
+    assert_eq!(reward(std::slice::from_ref(&potential), &before, &after, &clear), 0.5 * -2.0 + 1.0);
+
+    after.game_over = true;
+    assert_eq!(reward(&[Reward::Survival { bonus: 0.5 }], &before, &after, &clear), 0.0);
+    assert_eq!(reward(&[Reward::GameOver { penalty: 10.0 }], &before, &after, &clear), -10.0);
+    assert_eq!(reward(&[potential], &before, &after, &clear), 1.0);
+    let sum = [Reward::ScoreDelta, Reward::GameOver { penalty: 10.0 }, holes];
+    assert_eq!(reward(&sum, &before, &after, &clear), 40.0 - 10.0 - 1.0);
+}
+
+#[derive(Debug)]
+struct LinesBurnt;
+
+impl RewardFn for LinesBurnt {
+    fn reward(&self, _before: &GameState, after: &GameState, clear: &ClearInfo) -> f32 {
+        assert_eq!(after.last_clear, *clear);
+        clear.lines_burnt as f32
+    }
+}
+
+#[test]
+fn test_env_reward() {
+    let mut gs = GameState::initial(22, 10, Config::default(), Some(3));
+    for i in 20..22 {
+        gs.field.cells[i] = vec![8, 8, 8, 8, 8, 8, 8, 8, 0, 0];
+    }
+    let mut env = TetrisEnv::from_state(gs);
+    env.gs.spawn_shape(1); // O
+    let action = *env.get_valid_actions().iter().max_by_key(|a| a.base.1).unwrap();
+    let mut scored = env.clone();
+    scored.reward = Arc::new(Rewards::new(&[Reward::LinesSquared, Reward::Survival { bonus: 1.0 }]).unwrap());
+    assert_eq!(scored.step(action).1, 4.0 + 1.0);
+    env.reward = Arc::new(LinesBurnt);
+    assert_eq!(env.step(action).1, 2.0);
+}
+
+#[test]
+fn test_reward_conf() {
+    assert_eq!(AgentConf::default().reward, vec![Reward::ScoreDelta]);
+    let conf: AgentConf = serde_json::from_str(r#"{
+        "reward": [
+            "LinesSquared",
+            {"GameOver": {"penalty": 5.0}},
+            {"Potential": {"features": [["holes", 1.0], ["bumpiness", 0.2]], "discount": 0.99}}
+        ]
+    }"#).unwrap();
+    assert_eq!(conf.reward[1], Reward::GameOver { penalty: 5.0 });
+    assert_eq!(conf.reward.len(), 3);
+}
+
+#[test]
+fn test_unknown_feature() {
+    let penalty = Reward::Penalty { feature: "holez".into(), weight: 1.0 };
+    assert!(Rewards::new(&[Reward::ScoreDelta, penalty.clone()]).is_err());
+    let potential = Reward::Potential { features: vec![("holes".into(), 1.0), ("wells".into(), 1.0)], discount: 0.9 };
+    assert!(Rewards::new(&[potential]).is_err());
+    assert!(AgentConf::default().validate().is_ok());
+    let conf = AgentConf { reward: vec![penalty], ..Default::default() };
+    assert!(conf.validate().is_err());
+}
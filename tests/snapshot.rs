@@ -0,0 +1,77 @@
+#![feature(type_ascription)]
+
+use tetris::model::{GameState, Action};
+use tetris::config::{Config, Randomness, Scoring};
+use tetris::snapshot::SNAPSHOT_VERSION;
+
+fn play(gs: &mut GameState, actions: &[Action]) -> Vec<(usize, usize, u32)> {
+    let mut trace = vec![];
+    for action in actions {
+        gs.step(*action);
+        trace.push((gs.curr_shape_idx, gs.next_shape_idxs[0], gs.score));
+    }
+    trace
+}
+
+fn game_in_progress() -> GameState {
+    let config = Config {
+        scoring: Scoring::Guideline,
+        randomness: Randomness::ShuffledQueue,
+        hold_enabled: true,
+        preview_len: 3,
+        ..Default::default()
+    };
+    let mut gs = GameState::initial(22, 10, config, Some(17));
+    play(&mut gs, &[Action::Left, Action::HardDrop, Action::Hold, Action::RotateCW, Action::HardDrop, Action::Right]);
+    gs
+}
+
+#[test]
+fn test_json_snapshot() {
+    let mut gs = game_in_progress();
+    let mut restored = GameState::from_json(&gs.to_json().unwrap()).unwrap();
+    assert_eq!(restored.field, gs.field);
+    assert_eq!(restored.curr_cells, gs.curr_cells);
+    assert_eq!(restored.hold_shape_idx, gs.hold_shape_idx);
+    assert_eq!(restored.rng_queue, gs.rng_queue);
+    // the rng state is restored exactly, so the games go on the same way
+    let actions = vec![Action::HardDrop; 20];
+    assert_eq!(play(&mut restored, &actions), play(&mut gs, &actions));
+}
+
+#[test]
+fn test_binary_snapshot() {
+    let mut gs = game_in_progress();
+    let bytes = gs.to_bytes().unwrap();
+    assert!(bytes.len() < gs.to_json().unwrap().len());
+    let mut restored = GameState::from_bytes(&bytes).unwrap();
+    let actions = vec![Action::HardDrop; 20];
+    assert_eq!(play(&mut restored, &actions), play(&mut gs, &actions));
+}
+
+#[test]
+fn test_snapshot_version() {
+    let gs = game_in_progress();
+    let json = gs.to_json().unwrap().replacen(&format!("\"version\": {}", SNAPSHOT_VERSION), "\"version\": 100", 1);
+    assert!(GameState::from_json(&json).is_err());
+}
+
+#[test]
+fn test_invalid_snapshot() {
+    let broken: Vec<fn(&mut GameState)> = vec![
+        |gs| gs.field.height = 5,
+        |gs| { gs.field.cells[3].pop(); },
+        |gs| gs.curr_shape_idx = 7,
+        |gs| gs.next_shape_idxs.clear(),
+        |gs| gs.next_shape_idxs[0] = 9,
+        |gs| gs.hold_shape_idx = Some(7),
+        |gs| gs.rng_queue.push(7),
+        |gs| gs.curr_cells[0].1 = 10,
+    ];
+    for (k, breaks) in broken.iter().enumerate() {
+        let mut gs = game_in_progress();
+        breaks(&mut gs);
+        assert!(GameState::from_json(&gs.to_json().unwrap()).is_err(), "{}", k);
+        assert!(GameState::from_bytes(&gs.to_bytes().unwrap()).is_err(), "{}", k);
+    }
+}
@@ -0,0 +1,189 @@
+#![feature(type_ascription)]
+
+use tetris::agent::DQNAgent;
+use tetris::model::{Point, TSpin, srs_center, base_from_srs_center};
+use tetris::tbp::{self, TbpBot, FrontendMessage, BotMessage, Move, PieceLocation, Piece, Orientation, Spin, Start};
+
+#[test]
+fn test_parse_messages() {
+    let message: FrontendMessage = serde_json::from_str(r#"{"type": "new_piece", "piece": "L"}"#).unwrap();
+    assert_eq!(message, FrontendMessage::NewPiece { piece: Piece::L });
+    let message: FrontendMessage = serde_json::from_str(r#"{"type": "play", "move": {
+        "location": {"type": "T", "orientation": "west", "x": 0, "y": 1}, "spin": "mini"}}"#).unwrap();
+    let location = PieceLocation { piece: Piece::T, orientation: Orientation::West, x: 0, y: 1 };
+    assert_eq!(message, FrontendMessage::Play { mv: Move { location, spin: Spin::Mini } });
+    let message: FrontendMessage = serde_json::from_str(r#"{"type": "some_future_message", "x": 1}"#).unwrap();
+    assert_eq!(message, FrontendMessage::Unknown);
+    let reply = serde_json::to_string(&BotMessage::Ready).unwrap();
+    assert_eq!(reply, r#"{"type":"ready"}"#);
+}
+
+#[test]
+fn test_srs_center() {
+    // the horizontal I on the floor of the field of height 22
+    assert_eq!(srs_center(0, &Point(21, 1), 0, 22), (1, 0));
+    for shape_idx in 0..7 {
+        for rotation in 0..4 {
+            let base = Point(10, 4);
+            let center = srs_center(shape_idx, &base, rotation, 22);
+            assert_eq!(base_from_srs_center(shape_idx, center, rotation, 22), base);
+        }
+    }
+}
+
+#[test]
+fn test_suggest_and_play() {
+    let mut bot = TbpBot::new(DQNAgent::new(Some(3)));
+    bot.handle(FrontendMessage::Start(Start {
+        hold: None,
+        queue: vec![Piece::T, Piece::I, Piece::O],
+        combo: 0,
+        back_to_back: false,
+        board: board(&["GGGGGGGGG."]),
+    }));
+    let mv = match bot.handle(FrontendMessage::Suggest) {
+        Some(BotMessage::Suggestion { moves }) => moves[0],
+        other => panic!("unexpected reply {:?}", other),
+    };
+    assert!(mv.location.piece == Piece::T || mv.location.piece == Piece::I);
+    bot.handle(FrontendMessage::Play { mv });
+    let env = bot.env.as_ref().unwrap();
+    let gs = &env.gs;
+    // the burnt row takes one cell of the piece with it
+    assert_eq!(gs.field.cells.iter().flatten().filter(|c| **c > 0 && **c < 8).count() + env.lines_burnt, 4);
+    assert_eq!(gs.curr_shape_idx, bot.queue[0]);
+    bot.handle(FrontendMessage::NewPiece { piece: Piece::S });
+    assert_eq!(bot.queue.back(), Some(&(Piece::S as usize)));
+}
+
+#[test]
+fn test_play_with_hold() {
+    let mut bot = TbpBot::new(DQNAgent::new(Some(3)));
+    bot.handle_line(r#"{"type": "start", "hold": null, "queue": ["T", "I", "O"],
+        "combo": 0, "back_to_back": false, "board": []}"#).unwrap();
+    let location = PieceLocation { piece: Piece::I, orientation: Orientation::North, x: 1, y: 0 };
+    bot.handle(FrontendMessage::Play { mv: Move { location, spin: Spin::None } });
+    let gs = &bot.env.as_ref().unwrap().gs;
+    assert_eq!(gs.hold_shape_idx, Some(Piece::T as usize));
+    assert_eq!(gs.curr_shape_idx, Piece::O as usize);
+    assert_eq!(gs.field.cells[39][..5], [1, 1, 1, 1, 0]);
+    assert_eq!(bot.queue, vec![Piece::O as usize]);
+}
+
+#[test]
+fn test_play_tspin_double() {
+    let mut bot = TbpBot::new(DQNAgent::new(Some(3)));
+    bot.handle(FrontendMessage::Start(Start {
+        hold: None,
+        queue: vec![Piece::T, Piece::I],
+        combo: 0,
+        back_to_back: false,
+        board: board(&[
+            "GGGG.GGGGG",
+            "GGG...GGGG",
+            "...G......",
+        ]),
+    }));
+    let location = PieceLocation { piece: Piece::T, orientation: Orientation::South, x: 4, y: 1 };
+    bot.handle(FrontendMessage::Play { mv: Move { location, spin: Spin::Full } });
+    let env = bot.env.as_ref().unwrap();
+    assert_eq!(env.lines_burnt, 2);
+    assert_eq!(env.gs.last_clear.tspin, TSpin::Full);
+}
+
+#[test]
+fn test_suggest_tspin_double() {
+    let mut bot = TbpBot::new(DQNAgent::new(Some(3)));
+    bot.handle(FrontendMessage::Start(Start {
+        hold: None,
+        queue: vec![Piece::T, Piece::I],
+        combo: 0,
+        back_to_back: false,
+        board: board(&[
+            "GGGG.GGGGG",
+            "GGG...GGGG",
+            "...G......",
+        ]),
+    }));
+    let moves = bot.suggest();
+    let location = PieceLocation { piece: Piece::T, orientation: Orientation::South, x: 4, y: 1 };
+    let mv = *moves.iter().find(|mv| mv.location == location).unwrap();
+    assert_eq!(mv.spin, Spin::Full);
+    assert!(moves.iter().all(|mv| mv.location.piece == Piece::T || mv.spin == Spin::None));
+    bot.handle(FrontendMessage::Play { mv });
+    assert_eq!(bot.env.as_ref().unwrap().lines_burnt, 2);
+}
+
+#[test]
+fn test_suggest_unknown_hold() {
+    let mut bot = TbpBot::new(DQNAgent::new(Some(3)));
+    bot.handle_line(r#"{"type": "start", "hold": null, "queue": ["T"],
+        "combo": 0, "back_to_back": false, "board": []}"#).unwrap();
+    let moves = bot.suggest();
+    assert!(!moves.is_empty());
+    assert!(moves.iter().all(|mv| mv.location.piece == Piece::T));
+    assert!(bot.env.as_ref().unwrap().gs.config.hold_enabled);
+    bot.handle(FrontendMessage::Play { mv: moves[0] });
+    assert!(bot.queue.is_empty());
+    bot.handle(FrontendMessage::NewPiece { piece: Piece::O });
+    assert_eq!(bot.env.as_ref().unwrap().gs.curr_shape_idx, Piece::O as usize);
+}
+
+#[test]
+fn test_run() {
+    let input = r#"
+        {"type": "rules", "randomizer": "seven_bag"}
+        {"type": "start", "hold": "S", "queue": ["Z"], "combo": 0, "back_to_back": false, "board": []}
+        {"type": "suggest"}
+        {"type": "quit"}
+        {"type": "suggest"}
+    "#;
+    let mut output = Vec::new();
+    let mut bot = TbpBot::new(DQNAgent::new(Some(1)));
+    tbp::run(&mut bot, input.as_bytes(), &mut output).unwrap();
+    let replies: Vec<BotMessage> = String::from_utf8(output).unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(replies.len(), 3);
+    assert_eq!(replies[1], BotMessage::Ready);
+    match &replies[2] {
+        BotMessage::Suggestion { moves } => {
+            assert!(moves.len() > 1);
+            assert!(moves.iter().any(|mv| mv.location.piece == Piece::S));
+            assert!(moves.iter().enumerate().all(|(i, mv)| !moves[..i].contains(mv)));
+        }
+        other => panic!("unexpected reply {:?}", other),
+    }
+}
+
+#[test]
+fn test_run_skips_malformed() {
+    let input = r#"
+        {"type": "rules", "randomizer": "seven_bag"}
+        {"type": "start", "hold": 5}
+        not a message
+        {"type": "start", "hold": null, "queue": ["T", "O"], "combo": 0, "back_to_back": false, "board": []}
+        {"type": "suggest"
+        {"type": "suggest"}
+    "#;
+    let mut output = Vec::new();
+    let mut bot = TbpBot::new(DQNAgent::new(Some(1)));
+    tbp::run(&mut bot, input.as_bytes(), &mut output).unwrap();
+    let replies: Vec<BotMessage> = String::from_utf8(output).unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(replies.len(), 3);
+    match &replies[2] {
+        BotMessage::Suggestion { moves } => assert!(moves.iter().any(|mv| mv.location.piece == Piece::T)),
+        other => panic!("unexpected reply {:?}", other),
+    }
+}
+
+/// The rows from the bottom, `.` is an empty cell
+fn board(rows: &[&str]) -> Vec<Vec<Option<char>>> {
+    rows.iter()
+        .map(|row| row.chars().map(|c| if c == '.' { None } else { Some(c) }).collect())
+        .collect()
+}
@@ -1,12 +1,14 @@
 #![feature(type_ascription)]
 
 use tetris::model::{Point, Field, try_shape, rotate, GameState, Action};
-use tetris::train::TetrisEnv;
-use tetris::agent::{DQNAction, DQNAgent, DQNState};
+use tetris::train::{TetrisEnv, ObservationMode};
+use tetris::agent::{AgentConf, DQNAction, DQNAgent, DQNState, DQNTransition, NStepQueue, TargetUpdate};
 use std::collections::HashMap;
 use rand_xoshiro::Xoshiro512StarStar;
 use rand::SeedableRng;
 use std::fmt::format;
+use tetris::config::{Config, Scoring, Randomness, Timing, TopOut};
+use tetris::field;
 
 #[test]
 fn test_dqn_state_after_step() {
@@ -29,14 +31,16 @@ fn test_dqn_state_after_step() {
     gs.curr_shape_idx = 0;
     gs.rotation = 1;
     gs.base = Point(1, 2);
-    gs.curr_cells = gs.try_current_shape(&gs.base, gs.rotation).unwrap();
+    gs.curr_cells = gs.try_current_shape(&gs.base, gs.rotation).unwrap().to_vec();
     // the bottom line should be burnt
     // let dqn_action = DQNAction { base: gs.base, rotation: gs.rotation }; <- cannot do here
     let dqn_action = DQNAction {
         base: gs.base,
         rotation: gs.rotation,
+        hold: false,
+        last_kick: None,
     };
-    let mut env = TetrisEnv { gs, lines_burnt: 0 };
+    let mut env = TetrisEnv::from_state(gs);
     let (dqn_state, reward, done) = env.step(dqn_action);
     // the cells are = [
     //    [0, 0, 0, 0]
@@ -72,19 +76,50 @@ fn test_get_valid_actions() {
     };
     let mut gs = GameState::initial(field.height, field.width, Default::default(), Some(30));
     gs.field = field;
-    let mut env = TetrisEnv { gs, lines_burnt: 0 };
-    // note, the action DQNAction { base: Point(3, 1), rotation: 1 } doesn't included
+    let mut env = TetrisEnv::from_state(gs);
+    // the bases are the landing ones, the vertical I goes to every column but the 4th,
+    // including the 3rd one reached by the rotation below the spawn row
     let expected = vec![
-        DQNAction { base: Point(1, 1), rotation: 0 },
-        DQNAction { base: Point(1, 2), rotation: 0 },
-        DQNAction { base: Point(1, -1), rotation: 1 },
-        DQNAction { base: Point(1, 0), rotation: 1 },
-        DQNAction { base: Point(1, 1), rotation: 1 },
+        DQNAction { base: Point(2, 1), rotation: 0, hold: false, last_kick: None },
+        DQNAction { base: Point(2, 2), rotation: 0, hold: false, last_kick: None },
+        DQNAction { base: Point(2, 1), rotation: 1, hold: false, last_kick: None },
+        DQNAction { base: Point(1, 1), rotation: 3, hold: false, last_kick: None },
+        DQNAction { base: Point(1, -1), rotation: 1, hold: false, last_kick: None },
+        DQNAction { base: Point(1, 3), rotation: 1, hold: false, last_kick: None },
     ];
     assert_eq!(env.gs.curr_shape_idx, 0);
     assert_eq!(env.get_valid_actions(), expected);
 }
 
+#[test]
+fn test_get_valid_actions_with_hold() {
+    let config = Config {
+        scoring: Scoring::BurnOnly,
+        randomness: Randomness::JustRandom,
+        hold_enabled: true,
+        preview_len: 1,
+        timing: None,
+        track_finesse: false,
+        ..Config::default()
+    };
+    let gs = GameState::initial(22, 10, config, Some(30));
+    let curr_shape_idx = gs.curr_shape_idx;
+    let next_shape_idx = gs.next_shape_idxs[0];
+    let mut env = TetrisEnv::from_state(gs);
+    let valid_actions = env.get_valid_actions();
+    let hold_action = *valid_actions.iter().find(|a| a.hold).unwrap();
+    assert!(valid_actions.iter().any(|a| !a.hold));
+    let (next_state, _, done) = env.step(hold_action);
+    assert_eq!(done, false);
+    assert_eq!(next_state.hold_shape_idx, Some(curr_shape_idx));
+    // the shape spawned after the hold has been dropped, now the hold is available again
+    let dropped_cells = env.gs.field.cells.iter().flatten()
+        .filter(|c| **c == next_shape_idx as u8 + 1)
+        .count();
+    assert_eq!(dropped_cells, 4);
+    assert!(env.get_valid_actions().iter().any(|a| a.hold));
+}
+
 #[test]
 fn test_select_actions() {
     let field = Field {
@@ -103,15 +138,16 @@ fn test_select_actions() {
     let mut gs = GameState::initial(field.height, field.width, Default::default(), Some(30));
     gs.field = field;
     let gs_curr_shape_idx = gs.curr_shape_idx;
-    let mut env = TetrisEnv { gs, lines_burnt: 0 };
+    let mut env = TetrisEnv::from_state(gs);
     let mut agent = DQNAgent::new(Some(24));
 
-    let valid_actions = env.get_valid_actions();
-    let action = agent.select_best_action(&valid_actions);
+    let candidates = env.get_next_states();
+    assert_eq!(candidates.iter().map(|(a, _)| *a).collect::<Vec<_>>(), env.get_valid_actions());
+    let action = agent.select_best_action(&candidates);
     let (next_state, reward, done) = env.step(action);
     assert_eq!(gs_curr_shape_idx, 0);
-    assert_eq!(action, DQNAction { base: Point(1, 1), rotation: 1 });
-    assert_eq!(next_state, DQNState { lines_burnt: 1, sum_holes: 0, sum_bumps: 8, sum_height: 12, curr_shape_idx: 1 });
+    assert_eq!(action, DQNAction { base: Point(2, 1), rotation: 1, hold: false, last_kick: None });
+    assert_eq!(next_state, DQNState { lines_burnt: 1, sum_holes: 0, sum_bumps: 8, sum_height: 12, curr_shape_idx: 1, next_shape_idxs: vec![6], hold_shape_idx: None, combo: 1, back_to_back: false });
     assert_eq!(reward, 1.0);
     assert_eq!(done, false);
 }
@@ -137,3 +173,318 @@ fn test_game_state_seeds() {
     expected.insert(6, vec![16, 25, 43, 48, 53, 65, 75, 87, 98]);
     assert_eq!(seeds_map, expected);
 }
+
+#[test]
+fn test_dqn_agent_training() {
+    let conf = AgentConf {
+        n_neurons: vec![8],
+        activations: vec!["relu".into(), "linear".into()],
+        batch_size: 16,
+        mem_size: 40,
+        replay_start_size: 32,
+        epsilon_stop_episode: 4,
+        ..Default::default()
+    };
+    let mut agent = DQNAgent::with_conf(conf, Some(7)).unwrap();
+    let mut env = TetrisEnv::new(Some(7));
+    let mut state = env.reset();
+    let mut losses = vec![];
+    for _ in 0..50 {
+        let candidates = env.get_next_states();
+        if candidates.is_empty() {
+            state = env.reset();
+            continue;
+        }
+        let action = agent.select_best_action(&candidates);
+        let (next_state, reward, done) = env.step(action);
+        agent.add_to_memory(DQNTransition {
+            curr_state: state,
+            action,
+            reward,
+            next_state: next_state.clone(),
+            done,
+            candidates: vec![],
+            steps: 1,
+        });
+        state = if done { env.reset() } else { next_state };
+        losses.push(agent.train());
+    }
+    assert_eq!(agent.memory.len(), 40);
+    // no training until the memory has `replay_start_size` transitions
+    assert!(losses[..31].iter().all(|l| l.is_none()));
+    assert!(losses[31..].iter().all(|l| matches!(l, Some(l) if l.is_finite())));
+    for _ in 0..6 {
+        agent.decay_epsilon();
+    }
+    assert_eq!(agent.epsilon, 0.0);
+    // the greedy choice is one of the candidates
+    let candidates = env.get_next_states();
+    let action = agent.select_best_action(&candidates);
+    assert!(candidates.iter().any(|(a, _)| *a == action));
+}
+
+#[test]
+fn test_target_network_and_double_dqn() {
+    // the missing fields of the old configs get the defaults
+    let conf: AgentConf = serde_json::from_str(r#"{"batch_size": 8, "replay_start_size": 8}"#).unwrap();
+    assert_eq!(conf.target_update, TargetUpdate::Off);
+    assert!(!conf.double_dqn && !conf.huber_loss);
+    let updates = [TargetUpdate::Sync { every: 2 }, TargetUpdate::Polyak { tau: 0.1 }];
+    for target_update in updates.iter() {
+        let conf = AgentConf {
+            n_neurons: vec![8],
+            activations: vec!["relu".into(), "linear".into()],
+            target_update: *target_update,
+            double_dqn: true,
+            huber_loss: true,
+            ..conf.clone()
+        };
+        let mut agent = DQNAgent::with_conf(conf, Some(3)).unwrap();
+        assert!(agent.target.is_some());
+        let mut env = TetrisEnv::new(Some(3));
+        let mut state = env.reset();
+        for _ in 0..12 {
+            let transitions = env.get_next_transitions();
+            let candidates: Vec<_> = transitions.iter().map(|(a, s, _, _)| (*a, s.clone())).collect();
+            let action = agent.select_best_action(&candidates);
+            let (next_state, reward, done) = env.step(action);
+            assert!(transitions.iter().any(|(a, s, r, d)| *a == action && *s == next_state && *r == reward && *d == done));
+            agent.add_to_memory(DQNTransition {
+                curr_state: state,
+                action,
+                reward,
+                next_state: next_state.clone(),
+                done,
+                candidates: transitions.into_iter().map(|(_, s, r, d)| (s, r, d)).collect(),
+                steps: 1,
+            });
+            state = if done { env.reset() } else { next_state };
+            agent.train();
+        }
+        assert_eq!(agent.train_steps, 5);
+    }
+}
+
+#[test]
+fn test_diverged_network() {
+    let conf = AgentConf {
+        n_neurons: vec![8],
+        activations: vec!["relu".into(), "linear".into()],
+        batch_size: 4,
+        replay_start_size: 4,
+        double_dqn: true,
+        ..Default::default()
+    };
+    let mut agent = DQNAgent::with_conf(conf, Some(4)).unwrap();
+    for (_, mut var) in agent.vs.variables() {
+        tch::no_grad(|| var.fill_(f64::NAN));
+    }
+    agent.epsilon = 0.0;
+    let mut env = TetrisEnv::new(Some(4));
+    env.reset();
+    let candidates = env.get_next_states();
+    let action = agent.select_best_action(&candidates);
+    assert!(candidates.iter().any(|(a, _)| *a == action));
+    for k in 0..4 {
+        agent.add_to_memory(one_step(k, 1.0, false));
+    }
+    // the NaN values don't stop the training, they show up in the loss
+    assert!(agent.train().is_some());
+}
+
+#[test]
+fn test_invalid_conf() {
+    let invalid = [
+        AgentConf { activations: vec!["relu".into(), "softmax".into()], ..Default::default() },
+        AgentConf { train_every: 0, ..Default::default() },
+        AgentConf { log_every: 0, ..Default::default() },
+        AgentConf { render_every: Some(0), ..Default::default() },
+        AgentConf { save_every: Some(-1), ..Default::default() },
+    ];
+    for conf in invalid.iter() {
+        assert!(conf.validate().is_err(), "{:?}", conf);
+        assert!(DQNAgent::with_conf(conf.clone(), Some(1)).is_err(), "{:?}", conf);
+    }
+    let conf = AgentConf { render_every: Some(5), save_every: None, ..Default::default() };
+    assert!(conf.validate().is_ok());
+}
+
+fn one_step(from: usize, reward: f32, done: bool) -> DQNTransition {
+    // the states differ only in `lines_burnt`, so it works as their number
+    let state = |k: usize| DQNState {
+        lines_burnt: k,
+        sum_holes: 0,
+        sum_bumps: 0,
+        sum_height: 0,
+        curr_shape_idx: 0,
+        next_shape_idxs: vec![],
+        hold_shape_idx: None,
+        combo: 0,
+        back_to_back: false,
+    };
+    DQNTransition {
+        curr_state: state(from),
+        action: DQNAction { base: Point(0, from as i32), rotation: 0, hold: false, last_kick: None },
+        reward,
+        next_state: state(from + 1),
+        done,
+        candidates: vec![(state(from + 1), reward, done), (state(from + 100), 10.0, true)],
+        steps: 1,
+    }
+}
+
+#[test]
+fn test_n_step_returns() {
+    let mut queue = NStepQueue::new(3, 0.5);
+    assert!(queue.push(one_step(0, 1.0, false)).is_empty());
+    assert!(queue.push(one_step(1, 2.0, false)).is_empty());
+    let ts = queue.push(one_step(2, 4.0, false));
+    assert_eq!(ts.len(), 1);
+    let t = &ts[0];
+    assert_eq!((t.curr_state.lines_burnt, t.next_state.lines_burnt), (0, 3));
+    assert_eq!(t.action.base, Point(0, 0));
+    assert_eq!((t.reward, t.done, t.steps), (1.0 + 0.5 * 2.0 + 0.25 * 4.0, false, 3));
+    // the candidates of the last step get the rewards of the steps before it
+    assert_eq!(t.candidates[1].0.lines_burnt, 102);
+    assert_eq!((t.candidates[1].1, t.candidates[1].2), (2.0 + 0.25 * 10.0, true));
+
+    // the game over ends all the transitions in the window
+    let ts = queue.push(one_step(3, 8.0, true));
+    let returns: Vec<_> = ts.iter().map(|t| (t.curr_state.lines_burnt, t.reward, t.done, t.steps)).collect();
+    assert_eq!(returns, vec![(1, 2.0 + 0.5 * 4.0 + 0.25 * 8.0, true, 3), (2, 4.0 + 0.5 * 8.0, true, 2), (3, 8.0, true, 1)]);
+    assert!(queue.flush().is_empty());
+    assert!(ts.iter().all(|t| t.next_state.lines_burnt == 4));
+}
+
+#[test]
+fn test_n_step_flush_at_max_step() {
+    let conf = AgentConf { n_step: 3, discount: 0.5, replay_start_size: 100, ..Default::default() };
+    let mut agent = DQNAgent::with_conf(conf, Some(1)).unwrap();
+    agent.add_to_memory(one_step(0, 1.0, false));
+    agent.add_to_memory(one_step(1, 2.0, false));
+    assert!(agent.memory.is_empty());
+    // the episode cut off by `max_step` is bootstrapped from its last state
+    agent.finish_episode();
+    let mut ts: Vec<_> = agent.memory.iter().cloned().collect();
+    ts.sort_by_key(|t| t.curr_state.lines_burnt);
+    let returns: Vec<_> = ts.iter().map(|t| (t.reward, t.done, t.steps, t.next_state.lines_burnt)).collect();
+    assert_eq!(returns, vec![(2.0, false, 2, 2), (2.0, false, 1, 2)]);
+    // the next episode starts with the empty window
+    agent.add_to_memory(one_step(10, 1.0, true));
+    assert_eq!(agent.memory.len(), 3);
+    // with the default `n_step` the transitions go to the memory as they are
+    let mut agent = DQNAgent::new(Some(1));
+    agent.add_to_memory(one_step(0, 1.0, false));
+    assert_eq!(agent.memory[0], one_step(0, 1.0, false));
+}
+
+#[test]
+fn test_observations() {
+    let config = Config { preview_len: 2, ..Config::default() };
+    let mut gs = GameState::initial(5, 4, config, Some(1));
+    gs.field = field!("
+        . . . .
+        . . . .
+        . . . .
+        * . . .
+        * * . *
+    ");
+    gs.spawn_shape(1); // O
+    let next_shape_idxs: Vec<usize> = gs.next_shape_idxs.iter().cloned().collect();
+    let env = TetrisEnv::from_state(gs);
+
+    let features = env.observe(ObservationMode::Features);
+    assert_eq!(features.shape, vec![4]);
+    assert_eq!(features.data, vec![0.0, 0.0, 1.0 + 1.0 + 1.0, 2.0 + 1.0 + 1.0]);
+
+    let board = env.observe(ObservationMode::Board);
+    // 2 planes of the cells and 7 for each of the current and 2 upcoming shapes
+    assert_eq!(board.shape, vec![2 + 7 * 3, 5, 4]);
+    assert_eq!(board.data.len(), 23 * 20);
+    let plane = |k: usize| board.data[k * 20..(k + 1) * 20].to_vec();
+    assert_eq!(plane(0), vec![
+        0.0, 0.0, 0.0, 0.0,
+        0.0, 0.0, 0.0, 0.0,
+        0.0, 0.0, 0.0, 0.0,
+        1.0, 0.0, 0.0, 0.0,
+        1.0, 1.0, 0.0, 1.0,
+    ]);
+    assert_eq!(plane(1), vec![
+        0.0, 1.0, 1.0, 0.0,
+        0.0, 1.0, 1.0, 0.0,
+        0.0, 0.0, 0.0, 0.0,
+        0.0, 0.0, 0.0, 0.0,
+        0.0, 0.0, 0.0, 0.0,
+    ]);
+    let hot = |shape_idx: usize, k: usize| {
+        let shapes: Vec<f32> = (0..7).map(|s| plane(2 + 7 * k + s)[0]).collect();
+        shapes == (0..7).map(|s| (s == shape_idx) as i32 as f32).collect::<Vec<_>>()
+    };
+    assert!(hot(1, 0));
+    assert!(hot(next_shape_idxs[0], 1));
+    assert!(hot(next_shape_idxs[1], 2));
+    assert!((2..23).all(|k| plane(k).iter().all(|x| *x == plane(k)[0])));
+    assert_eq!(board.to_tensor().size(), vec![23, 5, 4]);
+}
+
+#[test]
+fn test_timed_steps() {
+    // at level 1 the inputs are done before the gravity matters
+    let timing = Timing::default();
+    let mut env = TetrisEnv::with_config(Config { timing: Some(timing), ..Config::default() }, Some(2));
+    let mut reference = TetrisEnv::new(Some(2));
+    for _ in 0..10 {
+        let action = *env.get_valid_actions().last().unwrap();
+        assert_eq!(env.step(action), reference.step(action));
+        assert_eq!(env.gs.field, reference.gs.field);
+    }
+    // at level 20 the piece falls to the floor after the first input, and locks
+    // at once with the short lock delay, the O stays at the wall after DAS
+    let timing = Timing { start_level: 20, lock_delay: 10, ..timing };
+    let mut gs = GameState::initial(22, 10, Config { timing: Some(timing), ..Config::default() }, Some(2));
+    gs.spawn_shape(1);
+    let mut env = TetrisEnv::from_state(gs);
+    let action = DQNAction { base: Point(20, 7), rotation: 0, hold: false, last_kick: None };
+    let (_, _, done) = env.step(action);
+    assert_eq!(done, false);
+    assert_eq!(env.gs.field.cells[21], vec![0, 0, 0, 0, 0, 0, 0, 0, 2, 2]);
+}
+
+#[test]
+fn test_next_states_match_step() {
+    let configs = [
+        Config { height: 8, width: 6, ..Default::default() },
+        Config { height: 8, width: 6, hold_enabled: true, preview_len: 3, randomness: Randomness::ShuffledQueue, ..Default::default() },
+        Config { height: 6, width: 5, hidden_rows: 3, hold_enabled: true, top_out: TopOut::LockOut, ..Default::default() },
+    ];
+    for config in configs.iter() {
+        for seed in 0..5 {
+            let mut env = TetrisEnv::with_config(*config, Some(seed));
+            let mut game_overs = 0;
+            for k in 0..60 {
+                let transitions = env.get_next_transitions();
+                let states = env.get_next_states();
+                assert_eq!(states.len(), transitions.len());
+                if states.is_empty() {
+                    env.reset();
+                    continue;
+                }
+                for ((action, state), (t_action, t_state, t_reward, t_done)) in states.iter().zip(transitions.iter()) {
+                    let mut next = env.clone();
+                    let (next_state, reward, done) = next.step(*action);
+                    assert_eq!(action, t_action);
+                    assert_eq!(*state, next_state, "{:?} {:?}", config, action);
+                    assert_eq!((t_state, *t_reward, *t_done), (&next_state, reward, done));
+                    if done {
+                        game_overs += 1;
+                    }
+                }
+                let (_, _, done) = env.step(states[k % states.len()].0);
+                if done {
+                    env.reset();
+                }
+            }
+            assert!(game_overs > 0);
+        }
+    }
+}